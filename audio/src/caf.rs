@@ -0,0 +1,566 @@
+//! Core Audio Format chunks
+//! 直接读写 caf 文件的 chunk，用于修改已经关闭的文件
+//! 文件头: "caff" + 版本(u16) + flags(u16)，之后是 chunk: 类型(4字节) + 大小(i64) + 数据，都是大端序
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::aoerror::{AudioError, Result};
+
+const FILE_HEADER_SIZE: u64 = 8;
+const CHUNK_HEADER_SIZE: u64 = 12;
+// data chunk 的数据以 4 字节的 edit count 开始
+const EDIT_COUNT_SIZE: u64 = 4;
+
+pub const CHUNK_DESC: [u8; 4] = *b"desc";
+pub const CHUNK_DATA: [u8; 4] = *b"data";
+pub const CHUNK_INFO: [u8; 4] = *b"info";
+pub const CHUNK_MARK: [u8; 4] = *b"mark";
+pub const CHUNK_STRG: [u8; 4] = *b"strg";
+
+// kCAFMarkerType_Generic
+const MARKER_TYPE_GENERIC: [u8; 4] = [0; 4];
+// CAFMarker: 类型(u32) + 帧位置(f64) + id(i32) + SMPTE 时间(8) + 声道(u32)
+const MARKER_SIZE: usize = 28;
+
+/// 文件中的一个标记
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    /// 在文件中的帧位置
+    pub frame: u64,
+    pub label: String,
+}
+
+/// recover 的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Recovery {
+    /// 恢复后的帧数
+    pub frames: u64,
+    /// 删除的不完整的数据，字节
+    pub removed: u64,
+    /// 是否修改了文件
+    pub repaired: bool,
+}
+
+/// 一个 chunk 的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk {
+    pub kind: [u8; 4],
+    /// chunk 头的位置
+    pub offset: u64,
+    /// 数据的大小，-1 表示数据一直到文件结束，只有 data chunk 可以是 -1
+    pub size: i64,
+}
+
+impl Chunk {
+    /// 数据的位置
+    pub fn data_offset(&self) -> u64 {
+        self.offset + CHUNK_HEADER_SIZE
+    }
+}
+
+/// 读取所有 chunk
+pub fn read_chunks<R: Read + Seek>(reader: &mut R) -> Result<Vec<Chunk>> {
+    let length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; FILE_HEADER_SIZE as usize];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"caff" {
+        return Err(AudioError::with_msg("not a caf file"));
+    }
+    let mut chunks = Vec::new();
+    let mut offset = FILE_HEADER_SIZE;
+    while offset + CHUNK_HEADER_SIZE <= length {
+        let mut chunk_header = [0u8; CHUNK_HEADER_SIZE as usize];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut chunk_header)?;
+        let kind = [
+            chunk_header[0],
+            chunk_header[1],
+            chunk_header[2],
+            chunk_header[3],
+        ];
+        let mut size_bytes = [0u8; 8];
+        size_bytes.copy_from_slice(&chunk_header[4..]);
+        let size = i64::from_be_bytes(size_bytes);
+        chunks.push(Chunk { kind, offset, size });
+        if size < 0 {
+            // 数据一直到文件结束
+            break;
+        }
+        offset += CHUNK_HEADER_SIZE + size as u64;
+    }
+    Ok(chunks)
+}
+
+/// data chunk 中的音频数据字节数，length 是文件长度
+pub fn audio_data_size(chunk: &Chunk, length: u64) -> u64 {
+    let size = if chunk.size < 0 {
+        length.saturating_sub(chunk.data_offset())
+    } else {
+        chunk.size as u64
+    };
+    size.saturating_sub(EDIT_COUNT_SIZE)
+}
+
+/// 只保留前 frames 帧音频数据，截断文件
+/// 只支持 data chunk 是最后一个 chunk 的固定帧大小的格式
+pub fn truncate_frames<P: AsRef<Path>>(path: P, frames: u64, bytes_per_frame: u32) -> Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let length = file.metadata()?.len();
+    let chunks = read_chunks(&mut file)?;
+    let Some(data) = chunks.last().filter(|chunk| chunk.kind == CHUNK_DATA) else {
+        return Err(AudioError::with_msg("data chunk is not the last chunk"));
+    };
+    let audio_size = frames * bytes_per_frame as u64;
+    if audio_size >= audio_data_size(data, length) {
+        return Ok(());
+    }
+    let size = EDIT_COUNT_SIZE + audio_size;
+    file.seek(SeekFrom::Start(data.offset + 4))?;
+    file.write_all(&(size as i64).to_be_bytes())?;
+    file.set_len(data.data_offset() + size)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// 修复没有正常关闭的文件
+/// data chunk 的大小是 -1 或超出文件时，按文件中实际的完整 packet 写入大小，删除不完整的 packet；
+/// data chunk 之后不完整的 chunk 被删除。只支持固定 packet 大小的格式
+pub fn recover<P: AsRef<Path>>(path: P) -> Result<Recovery> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let length = file.metadata()?.len();
+    let chunks = read_chunks(&mut file)?;
+    let Some(desc) = chunks.iter().find(|chunk| chunk.kind == CHUNK_DESC) else {
+        return Err(AudioError::with_msg("no desc chunk"));
+    };
+    let Some(index) = chunks.iter().position(|chunk| chunk.kind == CHUNK_DATA) else {
+        return Err(AudioError::with_msg("no data chunk"));
+    };
+    let data = chunks[index];
+    // desc: 采样率(f64) + format id + flags + bytes per packet + frames per packet + ...
+    let mut packet = [0u8; 8];
+    file.seek(SeekFrom::Start(desc.data_offset() + 16))?;
+    file.read_exact(&mut packet)?;
+    let bytes_per_packet = u32::from_be_bytes([packet[0], packet[1], packet[2], packet[3]]) as u64;
+    let frames_per_packet = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]) as u64;
+    if bytes_per_packet == 0 {
+        return Err(AudioError::with_msg(
+            "variable packet size is not supported",
+        ));
+    }
+    let complete =
+        |chunk: &Chunk| chunk.size >= 0 && chunk.data_offset() + chunk.size as u64 <= length;
+    let end = if complete(&data) {
+        // 音频数据完整，删除之后不完整的 chunk
+        chunks[index..]
+            .iter()
+            .take_while(|chunk| complete(chunk))
+            .map(|chunk| chunk.data_offset() + chunk.size as u64)
+            .last()
+            .unwrap_or(length)
+    } else {
+        // 只保留完整的 packet，不足 edit count 时补 0
+        let audio_size = length.saturating_sub(data.data_offset() + EDIT_COUNT_SIZE);
+        let size = EDIT_COUNT_SIZE + audio_size / bytes_per_packet * bytes_per_packet;
+        file.seek(SeekFrom::Start(data.offset + 4))?;
+        file.write_all(&(size as i64).to_be_bytes())?;
+        data.data_offset() + size
+    };
+    let repaired = !complete(&data) || end != length;
+    if repaired {
+        file.set_len(end)?;
+        file.sync_all()?;
+    }
+    let audio_size = match complete(&data) {
+        true => audio_data_size(&data, length),
+        false => end - data.data_offset() - EDIT_COUNT_SIZE,
+    };
+    Ok(Recovery {
+        frames: audio_size / bytes_per_packet * frames_per_packet,
+        removed: length.saturating_sub(end),
+        repaired,
+    })
+}
+
+/// 读取 info chunk 中的字符串，没有 info chunk 时返回空
+pub fn read_info<P: AsRef<Path>>(path: P) -> Result<Vec<(String, String)>> {
+    let mut file = fs::File::open(path)?;
+    let chunks = read_chunks(&mut file)?;
+    let Some(info) = chunks.iter().find(|chunk| chunk.kind == CHUNK_INFO) else {
+        return Ok(Vec::new());
+    };
+    if info.size < 4 {
+        return Err(AudioError::with_msg("info chunk too small"));
+    }
+    let mut data = vec![0u8; info.size as usize];
+    file.seek(SeekFrom::Start(info.data_offset()))?;
+    file.read_exact(&mut data)?;
+    // 条目数 + 以 0 结束的 key、value
+    let count = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+    let mut strings = data[4..]
+        .split(|byte| *byte == 0)
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+    let mut entries = Vec::with_capacity(count);
+    for _ in 0..count {
+        match (strings.next(), strings.next()) {
+            (Some(key), Some(value)) => entries.push((key, value)),
+            _ => return Err(AudioError::with_msg("info chunk is broken")),
+        }
+    }
+    Ok(entries)
+}
+
+/// 在文件末尾写入 info chunk，data chunk 的大小必须已经确定
+/// 已经有 info chunk 时合并，相同的 key 使用新的值；原来的 info chunk 必须是最后一个 chunk
+pub fn write_info<P: AsRef<Path>>(path: P, entries: &[(String, String)]) -> Result<()> {
+    let path = path.as_ref();
+    let mut merged = read_info(path)?;
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let length = file.metadata()?.len();
+    let chunks = read_chunks(&mut file)?;
+    if chunks.iter().any(|chunk| chunk.size < 0) {
+        return Err(AudioError::with_msg("data chunk size is unknown"));
+    }
+    let end = match chunks.iter().position(|chunk| chunk.kind == CHUNK_INFO) {
+        Some(index) if index + 1 == chunks.len() => chunks[index].offset,
+        Some(_) => return Err(AudioError::with_msg("info chunk is not the last chunk")),
+        None => length,
+    };
+    for (key, value) in entries {
+        match merged.iter_mut().find(|(merged_key, _)| merged_key == key) {
+            Some(entry) => entry.1 = value.clone(),
+            None => merged.push((key.clone(), value.clone())),
+        }
+    }
+    let mut data = (merged.len() as u32).to_be_bytes().to_vec();
+    for (key, value) in merged.iter() {
+        // key 和 value 中不能出现 0
+        data.extend(key.bytes().filter(|byte| *byte != 0));
+        data.push(0);
+        data.extend(value.bytes().filter(|byte| *byte != 0));
+        data.push(0);
+    }
+    file.set_len(end)?;
+    file.seek(SeekFrom::Start(end))?;
+    file.write_all(&CHUNK_INFO)?;
+    file.write_all(&(data.len() as i64).to_be_bytes())?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// 写入 mark chunk，标记的名称写入 strg chunk
+/// 和 info chunk 一样追加在文件末尾，需要在 write_info 之前调用
+pub fn write_markers<P: AsRef<Path>>(path: P, markers: &[Marker]) -> Result<()> {
+    // strg: 条目数，每个条目是 id(i32) + 字符串在数据中的位置(i64)，之后是以 0 结束的字符串
+    let mut strings = Vec::new();
+    let mut strg = (markers.len() as u32).to_be_bytes().to_vec();
+    for (index, marker) in markers.iter().enumerate() {
+        strg.extend_from_slice(&(index as i32 + 1).to_be_bytes());
+        strg.extend_from_slice(&(strings.len() as i64).to_be_bytes());
+        strings.extend(marker.label.bytes().filter(|byte| *byte != 0));
+        strings.push(0);
+    }
+    strg.extend_from_slice(&strings);
+    // mark: SMPTE 时间类型，标记数，之后是 CAFMarker
+    let mut mark = 0u32.to_be_bytes().to_vec();
+    mark.extend_from_slice(&(markers.len() as u32).to_be_bytes());
+    for (index, marker) in markers.iter().enumerate() {
+        mark.extend_from_slice(&MARKER_TYPE_GENERIC);
+        mark.extend_from_slice(&(marker.frame as f64).to_be_bytes());
+        mark.extend_from_slice(&(index as i32 + 1).to_be_bytes());
+        mark.extend_from_slice(&[0u8; 8]);
+        // 0 表示所有声道
+        mark.extend_from_slice(&0u32.to_be_bytes());
+    }
+    let mut bytes = Vec::new();
+    for (kind, data) in [(CHUNK_STRG, strg), (CHUNK_MARK, mark)] {
+        bytes.extend_from_slice(&kind);
+        bytes.extend_from_slice(&(data.len() as i64).to_be_bytes());
+        bytes.extend_from_slice(&data);
+    }
+    append_chunks(path, &bytes)
+}
+
+/// 读取 mark chunk 中的标记
+pub fn read_markers<P: AsRef<Path>>(path: P) -> Result<Vec<Marker>> {
+    let mut file = fs::File::open(path)?;
+    let chunks = read_chunks(&mut file)?;
+    let mut read_data = |kind: [u8; 4]| -> Result<Option<Vec<u8>>> {
+        let Some(chunk) = chunks.iter().find(|chunk| chunk.kind == kind) else {
+            return Ok(None);
+        };
+        let mut data = vec![0u8; chunk.size.max(0) as usize];
+        file.seek(SeekFrom::Start(chunk.data_offset()))?;
+        file.read_exact(&mut data)?;
+        Ok(Some(data))
+    };
+    let Some(mark) = read_data(CHUNK_MARK)? else {
+        return Ok(Vec::new());
+    };
+    let strg = read_data(CHUNK_STRG)?.unwrap_or_default();
+    let broken = || AudioError::with_msg("mark chunk is broken");
+    let be_u32 = |bytes: &[u8], at: usize| -> Option<u32> {
+        Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+    };
+    // id 对应的字符串
+    let label = |id: u32| -> Option<String> {
+        let count = be_u32(&strg, 0)? as usize;
+        let strings = strg.get(4 + count * 12..)?;
+        (0..count).find_map(|index| {
+            let entry = 4 + index * 12;
+            if be_u32(&strg, entry)? != id {
+                return None;
+            }
+            let offset = i64::from_be_bytes(strg.get(entry + 4..entry + 12)?.try_into().ok()?);
+            let text = strings.get(offset as usize..)?;
+            let end = text
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(text.len());
+            Some(String::from_utf8_lossy(&text[..end]).into_owned())
+        })
+    };
+    let count = be_u32(&mark, 4).ok_or_else(broken)? as usize;
+    let mut markers = Vec::with_capacity(count);
+    for index in 0..count {
+        let at = 8 + index * MARKER_SIZE;
+        let frame = mark
+            .get(at + 4..at + 12)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(f64::from_be_bytes)
+            .ok_or_else(broken)?;
+        let id = be_u32(&mark, at + 12).ok_or_else(broken)?;
+        markers.push(Marker {
+            frame: frame as u64,
+            label: label(id).unwrap_or_default(),
+        });
+    }
+    Ok(markers)
+}
+
+/// data chunk 之后的所有 chunk，原样返回
+/// 重新写入音频数据后，用 append_chunks 恢复 info、mark 等 chunk
+pub fn trailing_chunks<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let length = file.metadata()?.len();
+    let chunks = read_chunks(&mut file)?;
+    let Some(data) = chunks.iter().find(|chunk| chunk.kind == CHUNK_DATA) else {
+        return Err(AudioError::with_msg("no data chunk"));
+    };
+    if data.size < 0 {
+        return Ok(Vec::new());
+    }
+    let start = data.data_offset() + data.size as u64;
+    let mut bytes = vec![0u8; length.saturating_sub(start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// 在文件末尾追加完整的 chunk，data chunk 的大小必须已经确定
+pub fn append_chunks<P: AsRef<Path>>(path: P, bytes: &[u8]) -> Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let chunks = read_chunks(&mut file)?;
+    if chunks.iter().any(|chunk| chunk.size < 0) {
+        return Err(AudioError::with_msg("data chunk size is unknown"));
+    }
+    file.seek(SeekFrom::End(0))?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 文件头 + desc chunk + data chunk
+    fn caf_bytes(data_size: i64, audio: &[u8]) -> Vec<u8> {
+        let mut bytes = b"caff".to_vec();
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&0u16.to_be_bytes());
+        bytes.extend_from_slice(&CHUNK_DESC);
+        bytes.extend_from_slice(&32i64.to_be_bytes());
+        bytes.extend_from_slice(&[0u8; 32]);
+        bytes.extend_from_slice(&CHUNK_DATA);
+        bytes.extend_from_slice(&data_size.to_be_bytes());
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes.extend_from_slice(audio);
+        bytes
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let bytes = caf_bytes(-1, &[1, 2, 3, 4]);
+        let chunks = read_chunks(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].kind, CHUNK_DESC);
+        assert_eq!(chunks[1].offset, 52);
+        assert_eq!(audio_data_size(&chunks[1], bytes.len() as u64), 4);
+        assert!(read_chunks(&mut std::io::Cursor::new(b"RIFF0000")).is_err());
+    }
+
+    #[test]
+    fn test_truncate_frames() {
+        let path = std::env::temp_dir().join(format!("resound-caf-{}.caf", std::process::id()));
+        let audio = (0u8..16).collect::<Vec<_>>();
+        fs::write(&path, caf_bytes(4 + 16, &audio)).unwrap();
+        // 每帧 4 字节，保留 3 帧
+        truncate_frames(&path, 3, 4).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 80);
+        let chunks = read_chunks(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(chunks[1].size, 16);
+        assert_eq!(&bytes[68..], &audio[..12]);
+        // 超过现有帧数时不修改
+        truncate_frames(&path, 10, 4).unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 80);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recover() {
+        let path = std::env::temp_dir().join(format!("resound-recover-{}.caf", std::process::id()));
+        // 每个 packet 4 字节 1 帧，最后一个 packet 不完整
+        let mut bytes = caf_bytes(-1, &[0u8; 10]);
+        bytes[36..40].copy_from_slice(&4u32.to_be_bytes());
+        bytes[40..44].copy_from_slice(&1u32.to_be_bytes());
+        fs::write(&path, &bytes).unwrap();
+        let recovery = recover(&path).unwrap();
+        assert_eq!(
+            recovery,
+            Recovery {
+                frames: 2,
+                removed: 2,
+                repaired: true,
+            }
+        );
+        let chunks = read_chunks(&mut fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(chunks[1].size, 12);
+        // 已经完整的文件不修改
+        assert!(!recover(&path).unwrap().repaired);
+        // 写入 info chunk 时中断
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&CHUNK_INFO).unwrap();
+        file.write_all(&100i64.to_be_bytes()).unwrap();
+        file.write_all(&[0u8; 5]).unwrap();
+        drop(file);
+        assert_eq!(recover(&path).unwrap().removed, 17);
+        assert_eq!(fs::read(&path).unwrap().len(), 76);
+        // data chunk 的大小超出文件
+        bytes[56..64].copy_from_slice(&104i64.to_be_bytes());
+        fs::write(&path, &bytes[..76]).unwrap();
+        assert_eq!(recover(&path).unwrap().frames, 2);
+        assert_eq!(fs::read(&path).unwrap().len(), 76);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_info() {
+        let path = std::env::temp_dir().join(format!("resound-info-{}.caf", std::process::id()));
+        fs::write(&path, caf_bytes(4 + 8, &[0u8; 8])).unwrap();
+        assert!(read_info(&path).unwrap().is_empty());
+        let entry = |key: &str, value: &str| (key.to_string(), value.to_string());
+        write_info(
+            &path,
+            &[entry("title", "a"), entry("source", "com.example")],
+        )
+        .unwrap();
+        // 第二次合并，替换原来的 info chunk
+        write_info(&path, &[entry("title", "b"), entry("end", "now")]).unwrap();
+        assert_eq!(
+            read_info(&path).unwrap(),
+            vec![
+                entry("title", "b"),
+                entry("source", "com.example"),
+                entry("end", "now")
+            ]
+        );
+        let chunks = read_chunks(&mut fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[1].size, 12);
+        // info chunk 之后 data chunk 不是最后一个，不能再截断
+        assert!(truncate_frames(&path, 1, 4).is_err());
+        fs::remove_file(&path).unwrap();
+        // data chunk 大小不确定时不能写入
+        fs::write(&path, caf_bytes(-1, &[0u8; 8])).unwrap();
+        assert!(write_info(&path, &[entry("title", "a")]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_markers() {
+        let path = std::env::temp_dir().join(format!("resound-mark-{}.caf", std::process::id()));
+        fs::write(&path, caf_bytes(4 + 8, &[0u8; 8])).unwrap();
+        assert!(read_markers(&path).unwrap().is_empty());
+        let markers = vec![
+            Marker {
+                frame: 0,
+                label: "start".to_string(),
+            },
+            Marker {
+                frame: 48_000,
+                label: String::new(),
+            },
+        ];
+        write_markers(&path, &markers).unwrap();
+        write_info(&path, &[("title".to_string(), "a".to_string())]).unwrap();
+        assert_eq!(read_markers(&path).unwrap(), markers);
+        // data 之后的 chunk 可以复制到另一个文件
+        let trailing = trailing_chunks(&path).unwrap();
+        let copy = path.with_extension("copy.caf");
+        fs::write(&copy, caf_bytes(4 + 8, &[1u8; 8])).unwrap();
+        append_chunks(&copy, &trailing).unwrap();
+        assert_eq!(read_markers(&copy).unwrap(), markers);
+        assert_eq!(read_info(&copy).unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&copy).unwrap();
+    }
+
+    #[test]
+    fn test_mark_chunk_layout() {
+        let path = std::env::temp_dir().join(format!("resound-layout-{}.caf", std::process::id()));
+        fs::write(&path, caf_bytes(4 + 8, &[0u8; 8])).unwrap();
+        let markers = [
+            Marker {
+                frame: 10,
+                label: "a".to_string(),
+            },
+            Marker {
+                frame: 20,
+                label: "b".to_string(),
+            },
+        ];
+        write_markers(&path, &markers).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        let chunks = read_chunks(&mut std::io::Cursor::new(&bytes)).unwrap();
+        let mark = chunks
+            .iter()
+            .find(|chunk| chunk.kind == CHUNK_MARK)
+            .unwrap();
+        // CAFMarkerChunk: mSMPTE_TimeType(u32) + mNumberMarkers(u32) + 每个 28 字节的 CAFMarker
+        assert_eq!(mark.size, 8 + 2 * 28);
+        let data = &bytes[mark.data_offset() as usize..];
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&0u32.to_be_bytes());
+        expected.extend_from_slice(&2u32.to_be_bytes());
+        for (id, frame) in [(1u32, 10f64), (2, 20.0)] {
+            // mType
+            expected.extend_from_slice(&[0u8; 4]);
+            // mFramePosition
+            expected.extend_from_slice(&frame.to_be_bytes());
+            // mMarkerID
+            expected.extend_from_slice(&id.to_be_bytes());
+            // mSMPTETime
+            expected.extend_from_slice(&[0u8; 8]);
+            // mChannel
+            expected.extend_from_slice(&0u32.to_be_bytes());
+        }
+        assert_eq!(&data[..expected.len()], &expected[..]);
+        assert_eq!(read_markers(&path).unwrap(), markers);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -31,8 +31,10 @@ macro_rules! eprintln_status {
 }
 
 pub mod aggregate_device;
+pub mod buffer;
 pub mod device;
 pub mod ext_audio_file;
+pub mod format;
 pub mod process;
 pub mod stream;
 pub mod tap;
@@ -49,8 +49,9 @@ pub struct AudioAggregateDeviceBuilder {
     private: Option<bool>,
 
     // core audio 框架要求传入一个 CFDictionaries 的 CFArray。
-    // 目前，CFDictionaries 的 key 只知道一个： uid ， 所以使用 Vec<String> 表示
-    tap_list: Option<Vec<String>>,
+    // 目前，CFDictionaries 的 key 只知道一个： uid ，创建时查询每个tap的uid
+    // 持有tap的所有权，创建成功后移交给AudioAggregateDevice，保证tap比device后删除
+    taps: Vec<tap::AudioTap>,
 }
 
 impl AudioAggregateDeviceBuilder {
@@ -60,7 +61,7 @@ impl AudioAggregateDeviceBuilder {
             uid: uid.into(),
             main_sub_device: None,
             private: None,
-            tap_list: None,
+            taps: Vec::new(),
         }
     }
 
@@ -69,22 +70,27 @@ impl AudioAggregateDeviceBuilder {
         self
     }
 
-    pub fn tap_list(mut self, tap_list: Vec<String>) -> Self {
-        self.tap_list = Some(tap_list);
+    /// tap 的所有权交给 aggregate device，
+    /// aggregate device 删除后，才会删除 tap
+    pub fn taps(mut self, taps: Vec<tap::AudioTap>) -> Self {
+        self.taps = taps;
         self
     }
 
     pub fn build(self) -> Result<AudioAggregateDevice> {
-        AudioAggregateDevice::create(&self)
+        AudioAggregateDevice::create(self)
     }
 }
 
-/// encapsulation of tap
+/// encapsulation of aggregate device
+/// 持有 tap，drop时先删除device，再删除tap
 #[derive(Debug)]
 pub struct AudioAggregateDevice {
     /// false: 当实例释放时，不删除device, true: 当实例释放时，删除device
     destroy: bool,
     pub audio_device_id: AudioDeviceID,
+    // 字段的drop在Drop::drop之后执行，所以tap一定在device删除后才删除
+    taps: Vec<tap::AudioTap>,
 }
 
 impl Deref for AudioAggregateDevice {
@@ -102,7 +108,12 @@ impl AudioAggregateDevice {
         AudioAggregateDeviceBuilder::new(name, uid)
     }
 
-    fn create(builder: &AudioAggregateDeviceBuilder) -> Result<AudioAggregateDevice> {
+    /// taps of this device
+    pub fn taps(&self) -> &[tap::AudioTap] {
+        &self.taps
+    }
+
+    fn create(builder: AudioAggregateDeviceBuilder) -> Result<AudioAggregateDevice> {
         let mut keys = Vec::with_capacity(2);
         let mut values = Vec::with_capacity(2);
 
@@ -124,8 +135,14 @@ impl AudioAggregateDevice {
             let private = if private { 1 } else { 0 };
             values.push(create_cf_number_ref(private) as *const c_void);
         }
-        if let Some(tap_list) = &builder.tap_list {
-            let tap_list = tap_list
+        if !builder.taps.is_empty() {
+            // 先查询所有uid，失败时还没有创建core foundation实例，不会内存泄漏
+            let tap_uid_vec = builder
+                .taps
+                .iter()
+                .map(tap::query_uid)
+                .collect::<Result<Vec<String>>>()?;
+            let tap_list = tap_uid_vec
                 .iter()
                 .map(|tap_uid| {
                     let mut keys = [create_cf_string_ref(tap::K_AUDIO_SUB_TAP_UIDKEY)];
@@ -146,16 +163,17 @@ impl AudioAggregateDevice {
         Ok(AudioAggregateDevice {
             destroy: true,
             audio_device_id: aggregate_device_id,
+            taps: builder.taps,
         })
     }
 }
 
-// todo 怎么保证 AggregateDevice 删除前，tap不会被删除
+// 先删除 AggregateDevice，之后 taps 字段 drop，再删除tap
 impl Drop for AudioAggregateDevice {
     fn drop(&mut self) {
         if self.destroy {
             let status = unsafe { AudioHardwareDestroyAggregateDevice(self.audio_device_id) };
-            eprintln_status!("destroy process tap fail", status);
+            eprintln_status!("destroy aggregate device fail", status);
         }
     }
 }
@@ -0,0 +1,617 @@
+//! AudioBufferList of core audio
+//! 提供类型安全的采样视图，处理逻辑不需要直接操作 mBuffers
+
+use std::{
+    alloc::{self, Layout},
+    marker::PhantomData,
+    mem, ops,
+    ptr::{self, NonNull},
+    slice,
+};
+
+use coreaudio_sys::{AudioBuffer, AudioBufferList};
+
+use crate::{
+    AudioStreamBasicDescription,
+    aoerror::{AudioError, Result},
+};
+
+/// 线性PCM的采样类型
+///
+/// # Safety
+/// 实现类型必须是 plain old data：没有padding，任意字节都是合法值
+pub unsafe trait Sample: Copy + Send + 'static {
+    /// 每个采样的有效位数
+    const BITS: u32;
+    /// true: 浮点数，false: 有符号整数
+    const FLOAT: bool;
+
+    /// 转换为 [-1.0, 1.0] 范围的浮点数
+    fn to_f32(self) -> f32;
+}
+
+unsafe impl Sample for f32 {
+    const BITS: u32 = 32;
+    const FLOAT: bool = true;
+
+    #[inline]
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+unsafe impl Sample for i16 {
+    const BITS: u32 = 16;
+    const FLOAT: bool = false;
+
+    #[inline]
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
+    }
+}
+
+unsafe impl Sample for i32 {
+    const BITS: u32 = 32;
+    const FLOAT: bool = false;
+
+    #[inline]
+    fn to_f32(self) -> f32 {
+        (self as f64 / 2147483648.0) as f32
+    }
+}
+
+/// 24位有符号整数，3个字节紧密排列，小端序
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct I24([u8; 3]);
+
+impl I24 {
+    pub const MAX: i32 = (1 << 23) - 1;
+    pub const MIN: i32 = -(1 << 23);
+
+    /// 超出范围的值会被截断到 [MIN, MAX]
+    pub fn from_i32(value: i32) -> I24 {
+        let value = value.clamp(Self::MIN, Self::MAX);
+        let bytes = value.to_le_bytes();
+        I24([bytes[0], bytes[1], bytes[2]])
+    }
+
+    pub fn to_i32(self) -> i32 {
+        let [b0, b1, b2] = self.0;
+        // 左移到高位，再算术右移，扩展符号位
+        i32::from_le_bytes([0, b0, b1, b2]) >> 8
+    }
+}
+
+unsafe impl Sample for I24 {
+    const BITS: u32 = 24;
+    const FLOAT: bool = false;
+
+    #[inline]
+    fn to_f32(self) -> f32 {
+        self.to_i32() as f32 / 8388608.0
+    }
+}
+
+/// AudioBufferList 中的所有 AudioBuffer
+/// coreaudio_sys 把 mBuffers 定义为长度1的数组，实际长度是 mNumberBuffers
+///
+/// # Safety
+/// list 后面必须紧跟着 mNumberBuffers 个 AudioBuffer，例如 core audio 传给 io proc 的 list
+/// 或者 AudioBufferListBuf，在 'a 期间有效
+pub unsafe fn buffers(list: &AudioBufferList) -> &[AudioBuffer] {
+    unsafe { slice::from_raw_parts(list.mBuffers.as_ptr(), list.mNumberBuffers as usize) }
+}
+
+/// 按 stream 拆分 AudioBuffer
+/// aggregate device 的 io proc 中，所有 stream 的 buffer 按顺序放在一个 AudioBufferList 中，
+/// 交错格式的 stream 占用1个buffer，非交错格式的 stream 每个声道占用1个buffer
+/// buffer 数量不足时，返回 None
+pub fn split_by_stream<'a>(
+    buffers: &'a [AudioBuffer],
+    desc_list: &[AudioStreamBasicDescription],
+) -> Vec<Option<&'a [AudioBuffer]>> {
+    let mut offset = 0;
+    desc_list
+        .iter()
+        .map(|desc| {
+            let count = if is_interleaved(desc) {
+                1
+            } else {
+                desc.mChannelsPerFrame as usize
+            };
+            let stream_buffers = buffers.get(offset..offset + count);
+            offset += count;
+            stream_buffers
+        })
+        .collect()
+}
+
+/// 数据可以读取的一组 AudioBuffer
+/// 创建时由调用者保证每个 mData 在 'a 期间可以读取 mDataByteSize 字节，
+/// 之后的 AudioBuffers、Block::read_stream 都是安全的，unsafe 只在拥有 list 的地方，例如 io proc 的 trampoline
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AudioBufferListRef<'a> {
+    buffers: &'a [AudioBuffer],
+}
+
+impl<'a> AudioBufferListRef<'a> {
+    /// # Safety
+    /// 同 buffers，另外每个 AudioBuffer 的 mData 在 'a 期间可以读取 mDataByteSize 字节，并且不会被修改
+    pub unsafe fn from_list(list: &'a AudioBufferList) -> AudioBufferListRef<'a> {
+        AudioBufferListRef {
+            buffers: unsafe { buffers(list) },
+        }
+    }
+
+    /// # Safety
+    /// 每个 AudioBuffer 的 mData 在 'a 期间可以读取 mDataByteSize 字节，并且不会被修改
+    pub unsafe fn from_buffers(buffers: &'a [AudioBuffer]) -> AudioBufferListRef<'a> {
+        AudioBufferListRef { buffers }
+    }
+
+    pub fn buffers(&self) -> &'a [AudioBuffer] {
+        self.buffers
+    }
+
+    /// buffer 数量
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    /// 同 split_by_stream，拆分出的 buffer 仍然可以读取
+    /// 不分配内存，可以在 io proc 中使用
+    pub fn split_by_stream<'d>(
+        &self,
+        desc_list: &'d [AudioStreamBasicDescription],
+    ) -> impl Iterator<Item = Option<AudioBufferListRef<'a>>> + use<'a, 'd> {
+        let buffers = self.buffers;
+        let mut offset = 0;
+        desc_list.iter().map(move |desc| {
+            let count = if is_interleaved(desc) {
+                1
+            } else {
+                desc.mChannelsPerFrame as usize
+            };
+            let stream_buffers = buffers.get(offset..offset + count);
+            offset += count;
+            stream_buffers.map(|buffers| AudioBufferListRef { buffers })
+        })
+    }
+}
+
+/// 帧数，由 ASBD 的 mBytesPerFrame 计算
+/// 非交错格式时，mBytesPerFrame 是一个buffer中一帧的字节数，所以对交错、非交错格式都适用
+pub fn frame_count(buffers: &[AudioBuffer], desc: &AudioStreamBasicDescription) -> u32 {
+    match buffers.first() {
+        Some(buffer) if desc.mBytesPerFrame > 0 => buffer.mDataByteSize / desc.mBytesPerFrame,
+        _ => 0,
+    }
+}
+
+#[inline]
+fn is_interleaved(desc: &AudioStreamBasicDescription) -> bool {
+    desc.mFormatFlags & coreaudio_sys::kAudioFormatFlagIsNonInterleaved == 0
+}
+
+// 检查 ASBD 是否和采样类型一致
+fn check_format<S: Sample>(desc: &AudioStreamBasicDescription) -> Result<()> {
+    if desc.mFormatID != coreaudio_sys::kAudioFormatLinearPCM {
+        return Err(AudioError::with_msg(format!(
+            "format id: {} is not linear pcm",
+            desc.mFormatID
+        )));
+    }
+    let flags = desc.mFormatFlags;
+    let float = flags & coreaudio_sys::kAudioFormatFlagIsFloat != 0;
+    if float != S::FLOAT {
+        return Err(AudioError::with_msg(format!(
+            "sample type mismatch, format is float: {float}"
+        )));
+    }
+    if !float && flags & coreaudio_sys::kAudioFormatFlagIsSignedInteger == 0 {
+        return Err(AudioError::with_msg("unsigned integer sample unsupported"));
+    }
+    if flags & coreaudio_sys::kAudioFormatFlagIsBigEndian != 0 {
+        return Err(AudioError::with_msg("big endian sample unsupported"));
+    }
+    if desc.mBitsPerChannel != S::BITS {
+        return Err(AudioError::with_msg(format!(
+            "bits per channel mismatch, format: {}, sample: {}",
+            desc.mBitsPerChannel,
+            S::BITS
+        )));
+    }
+    let channels_per_buffer = if is_interleaved(desc) {
+        desc.mChannelsPerFrame
+    } else {
+        1
+    };
+    if desc.mBytesPerFrame as usize != mem::size_of::<S>() * channels_per_buffer as usize {
+        return Err(AudioError::with_msg(format!(
+            "bytes per frame: {} mismatch channels: {}",
+            desc.mBytesPerFrame, desc.mChannelsPerFrame
+        )));
+    }
+    Ok(())
+}
+
+/// AudioBufferList 的类型安全视图
+/// 交错格式：每个buffer包含多个声道，按帧排列
+/// 非交错格式：每个buffer包含一个声道
+#[derive(Debug, Clone, Copy)]
+pub struct AudioBuffers<'a, S: Sample> {
+    buffers: &'a [AudioBuffer],
+    interleaved: bool,
+    frames: usize,
+    _sample: PhantomData<&'a [S]>,
+}
+
+impl<'a, S: Sample> AudioBuffers<'a, S> {
+    /// 使用一个 ASBD 描述整个 AudioBufferList
+    ///
+    /// # Safety
+    /// 同 AudioBufferListRef::from_list
+    pub unsafe fn new(
+        list: &'a AudioBufferList,
+        desc: &AudioStreamBasicDescription,
+    ) -> Result<AudioBuffers<'a, S>> {
+        Self::from_ref(unsafe { AudioBufferListRef::from_list(list) }, desc)
+    }
+
+    /// 使用一个 ASBD 描述一组 AudioBuffer
+    ///
+    /// # Safety
+    /// 同 AudioBufferListRef::from_buffers
+    pub unsafe fn from_buffers(
+        buffers: &'a [AudioBuffer],
+        desc: &AudioStreamBasicDescription,
+    ) -> Result<AudioBuffers<'a, S>> {
+        Self::from_ref(unsafe { AudioBufferListRef::from_buffers(buffers) }, desc)
+    }
+
+    /// 使用一个 ASBD 描述可以读取的 AudioBuffer，例如 split_by_stream 拆分出的一个 stream
+    pub fn from_ref(
+        list: AudioBufferListRef<'a>,
+        desc: &AudioStreamBasicDescription,
+    ) -> Result<AudioBuffers<'a, S>> {
+        let buffers = list.buffers;
+        check_format::<S>(desc)?;
+        let interleaved = is_interleaved(desc);
+        let frames = frame_count(buffers, desc) as usize;
+        for buffer in buffers {
+            if !interleaved && buffer.mNumberChannels != 1 {
+                return Err(AudioError::with_msg(
+                    "non interleaved buffer must have one channel",
+                ));
+            }
+            let bytes = mem::size_of::<S>() * buffer.mNumberChannels as usize * frames;
+            if buffer.mDataByteSize as usize != bytes {
+                return Err(AudioError::with_msg(
+                    "the frame count of buffers are different",
+                ));
+            }
+            if bytes > 0 {
+                if buffer.mData.is_null() {
+                    return Err(AudioError::with_msg("buffer data is null"));
+                }
+                if !(buffer.mData as usize).is_multiple_of(mem::align_of::<S>()) {
+                    return Err(AudioError::with_msg("buffer data is not aligned"));
+                }
+            }
+        }
+        Ok(AudioBuffers {
+            buffers,
+            interleaved,
+            frames,
+            _sample: PhantomData,
+        })
+    }
+
+    /// 帧数
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// 所有buffer的声道数之和
+    pub fn channels(&self) -> usize {
+        self.buffers
+            .iter()
+            .map(|buffer| buffer.mNumberChannels as usize)
+            .sum()
+    }
+
+    pub fn is_interleaved(&self) -> bool {
+        self.interleaved
+    }
+
+    /// buffer 数量
+    pub fn len(&self) -> usize {
+        self.buffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty()
+    }
+
+    /// 第 index 个buffer
+    pub fn buffer(&self, index: usize) -> Option<BufferView<'a, S>> {
+        self.buffers.get(index).map(|buffer| {
+            let channels = buffer.mNumberChannels as usize;
+            let samples = if self.frames == 0 || channels == 0 {
+                &[][..]
+            } else {
+                // AudioBufferListRef 保证可以读取，from_ref 已经检查了长度、对齐、非空
+                unsafe { slice::from_raw_parts(buffer.mData as *const S, self.frames * channels) }
+            };
+            BufferView { channels, samples }
+        })
+    }
+
+    /// 遍历所有buffer
+    pub fn iter(&self) -> impl Iterator<Item = BufferView<'a, S>> + '_ {
+        (0..self.buffers.len()).filter_map(|index| self.buffer(index))
+    }
+
+    /// 第 frame 帧，第 channel 声道（跨buffer编号）的采样
+    pub fn sample(&self, frame: usize, channel: usize) -> Option<S> {
+        let mut channel = channel;
+        for buffer in self.iter() {
+            if channel < buffer.channels() {
+                return buffer.frame(frame).map(|samples| samples[channel]);
+            }
+            channel -= buffer.channels();
+        }
+        None
+    }
+
+    /// 第 channel 声道（跨buffer编号）的所有采样
+    pub fn channel(&self, channel: usize) -> impl Iterator<Item = S> + '_ {
+        let frames = if channel < self.channels() {
+            self.frames
+        } else {
+            0
+        };
+        (0..frames).filter_map(move |frame| self.sample(frame, channel))
+    }
+}
+
+/// 一个 AudioBuffer 的视图
+#[derive(Debug, Clone, Copy)]
+pub struct BufferView<'a, S: Sample> {
+    channels: usize,
+    samples: &'a [S],
+}
+
+impl<'a, S: Sample> BufferView<'a, S> {
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn frames(&self) -> usize {
+        self.samples.len().checked_div(self.channels).unwrap_or(0)
+    }
+
+    /// 所有采样，交错格式按帧排列
+    pub fn samples(&self) -> &'a [S] {
+        self.samples
+    }
+
+    /// 一帧中所有声道的采样
+    pub fn frame(&self, frame: usize) -> Option<&'a [S]> {
+        let start = frame * self.channels;
+        self.samples.get(start..start + self.channels)
+    }
+}
+
+/// 拥有内存的 AudioBufferList，可以包含任意数量的 AudioBuffer
+/// 只拥有 AudioBuffer 数组，不拥有 mData 指向的数据
+#[derive(Debug)]
+pub struct AudioBufferListBuf {
+    ptr: NonNull<AudioBufferList>,
+    layout: Layout,
+}
+
+impl AudioBufferListBuf {
+    pub fn new(buffers: &[AudioBuffer]) -> AudioBufferListBuf {
+        let count = buffers.len().max(1);
+        let offset = mem::offset_of!(AudioBufferList, mBuffers);
+        let size = offset + count * mem::size_of::<AudioBuffer>();
+        let layout = Layout::from_size_align(size, mem::align_of::<AudioBufferList>())
+            .expect("AudioBufferList layout overflow");
+        unsafe {
+            let raw = alloc::alloc_zeroed(layout);
+            let Some(ptr) = NonNull::new(raw as *mut AudioBufferList) else {
+                alloc::handle_alloc_error(layout);
+            };
+            ptr::addr_of_mut!((*ptr.as_ptr()).mNumberBuffers).write(buffers.len() as u32);
+            let first = ptr::addr_of_mut!((*ptr.as_ptr()).mBuffers) as *mut AudioBuffer;
+            ptr::copy_nonoverlapping(buffers.as_ptr(), first, buffers.len());
+            AudioBufferListBuf { ptr, layout }
+        }
+    }
+}
+
+impl AudioBufferListBuf {
+    /// 所有 AudioBuffer，数组由 self 拥有，所以是安全的
+    pub fn buffers(&self) -> &[AudioBuffer] {
+        // new 中分配了 mNumberBuffers 个 AudioBuffer
+        unsafe { buffers(self) }
+    }
+}
+
+impl ops::Deref for AudioBufferListBuf {
+    type Target = AudioBufferList;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl Drop for AudioBufferListBuf {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr.as_ptr() as *mut u8, self.layout) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::c_void;
+
+    use super::*;
+
+    fn desc<S: Sample>(channels: u32, interleaved: bool) -> AudioStreamBasicDescription {
+        let mut flags = coreaudio_sys::kAudioFormatFlagIsPacked;
+        flags |= if S::FLOAT {
+            coreaudio_sys::kAudioFormatFlagIsFloat
+        } else {
+            coreaudio_sys::kAudioFormatFlagIsSignedInteger
+        };
+        let bytes = mem::size_of::<S>() as u32;
+        let bytes_per_frame = if interleaved {
+            bytes * channels
+        } else {
+            flags |= coreaudio_sys::kAudioFormatFlagIsNonInterleaved;
+            bytes
+        };
+        AudioStreamBasicDescription {
+            mSampleRate: 48000.0,
+            mFormatID: coreaudio_sys::kAudioFormatLinearPCM,
+            mFormatFlags: flags,
+            mBytesPerPacket: bytes_per_frame,
+            mFramesPerPacket: 1,
+            mBytesPerFrame: bytes_per_frame,
+            mChannelsPerFrame: channels,
+            mBitsPerChannel: S::BITS,
+            mReserved: 0,
+        }
+    }
+
+    fn audio_buffer<S: Sample>(channels: u32, data: &mut [S]) -> AudioBuffer {
+        AudioBuffer {
+            mNumberChannels: channels,
+            mDataByteSize: mem::size_of_val(data) as u32,
+            mData: data.as_mut_ptr() as *mut c_void,
+        }
+    }
+
+    // 测试中 mData 指向的数组比 list 先创建，在使用期间一直有效
+    fn view<'a, S: Sample>(
+        list: &'a AudioBufferListBuf,
+        desc: &AudioStreamBasicDescription,
+    ) -> Result<AudioBuffers<'a, S>> {
+        unsafe { AudioBuffers::new(list, desc) }
+    }
+
+    #[test]
+    fn test_interleaved_f32() {
+        let mut data = [0.0f32, 0.5, 0.1, 0.6, 0.2, 0.7];
+        let list = AudioBufferListBuf::new(&[audio_buffer(2, &mut data)]);
+        let audio_buffers = view::<f32>(&list, &desc::<f32>(2, true)).unwrap();
+        assert!(audio_buffers.is_interleaved());
+        assert_eq!(audio_buffers.frames(), 3);
+        assert_eq!(audio_buffers.channels(), 2);
+        assert_eq!(audio_buffers.sample(1, 1), Some(0.6));
+        assert_eq!(audio_buffers.sample(3, 0), None);
+        assert_eq!(audio_buffers.sample(0, 2), None);
+        assert_eq!(
+            audio_buffers.channel(0).collect::<Vec<f32>>(),
+            vec![0.0, 0.1, 0.2]
+        );
+        let buffer = audio_buffers.buffer(0).unwrap();
+        assert_eq!(buffer.frame(2), Some(&[0.2f32, 0.7][..]));
+    }
+
+    #[test]
+    fn test_non_interleaved_i16() {
+        let mut left = [1i16, 2, 3, 4];
+        let mut right = [-1i16, -2, -3, -4];
+        let list =
+            AudioBufferListBuf::new(&[audio_buffer(1, &mut left), audio_buffer(1, &mut right)]);
+        assert_eq!(list.mNumberBuffers, 2);
+        let audio_buffers = view::<i16>(&list, &desc::<i16>(2, false)).unwrap();
+        assert!(!audio_buffers.is_interleaved());
+        assert_eq!(audio_buffers.len(), 2);
+        assert_eq!(audio_buffers.frames(), 4);
+        assert_eq!(audio_buffers.channels(), 2);
+        assert_eq!(audio_buffers.sample(2, 1), Some(-3));
+        assert_eq!(
+            audio_buffers.channel(1).collect::<Vec<i16>>(),
+            vec![-1, -2, -3, -4]
+        );
+    }
+
+    #[test]
+    fn test_i24() {
+        assert_eq!(I24::from_i32(-1).to_i32(), -1);
+        assert_eq!(I24::from_i32(0x123456).to_i32(), 0x123456);
+        assert_eq!(I24::from_i32(i32::MAX).to_i32(), I24::MAX);
+        assert_eq!(I24::from_i32(i32::MIN).to_i32(), I24::MIN);
+        assert_eq!(I24::from_i32(I24::MIN).to_f32(), -1.0);
+
+        let mut data = [I24::from_i32(100), I24::from_i32(-100)];
+        let list = AudioBufferListBuf::new(&[audio_buffer(1, &mut data)]);
+        let audio_buffers = view::<I24>(&list, &desc::<I24>(1, true)).unwrap();
+        assert_eq!(audio_buffers.frames(), 2);
+        assert_eq!(audio_buffers.sample(1, 0).map(I24::to_i32), Some(-100));
+    }
+
+    #[test]
+    fn test_format_mismatch() {
+        let mut data = [0i32; 4];
+        let list = AudioBufferListBuf::new(&[audio_buffer(2, &mut data)]);
+        assert!(view::<f32>(&list, &desc::<i32>(2, true)).is_err());
+        assert!(view::<i16>(&list, &desc::<i32>(2, true)).is_err());
+        // 声道数和字节数不一致
+        let mut wrong_desc = desc::<i32>(2, true);
+        wrong_desc.mChannelsPerFrame = 1;
+        assert!(view::<i32>(&list, &wrong_desc).is_err());
+        assert!(view::<i32>(&list, &desc::<i32>(2, true)).is_ok());
+    }
+
+    #[test]
+    fn test_frames_different() {
+        let mut left = [0.0f32; 4];
+        let mut right = [0.0f32; 3];
+        let list =
+            AudioBufferListBuf::new(&[audio_buffer(1, &mut left), audio_buffer(1, &mut right)]);
+        assert!(view::<f32>(&list, &desc::<f32>(2, false)).is_err());
+    }
+
+    #[test]
+    fn test_split_by_stream() {
+        let mut stereo = [0.0f32; 4];
+        let mut left = [0.0f32; 2];
+        let mut right = [0.0f32; 2];
+        let list = AudioBufferListBuf::new(&[
+            audio_buffer(2, &mut stereo),
+            audio_buffer(1, &mut left),
+            audio_buffer(1, &mut right),
+        ]);
+        let desc_list = [
+            desc::<f32>(2, true),
+            desc::<f32>(2, false),
+            desc::<f32>(2, true),
+        ];
+        let streams = split_by_stream(list.buffers(), &desc_list);
+        assert_eq!(streams.len(), 3);
+        assert_eq!(streams[0].map(|buffers| buffers.len()), Some(1));
+        assert_eq!(streams[1].map(|buffers| buffers.len()), Some(2));
+        assert!(streams[2].is_none());
+        let frames = frame_count(streams[1].unwrap(), &desc_list[1]);
+        assert_eq!(frames, 2);
+    }
+
+    #[test]
+    fn test_empty_buffer_list() {
+        let list = AudioBufferListBuf::new(&[]);
+        let audio_buffers = view::<f32>(&list, &desc::<f32>(2, true)).unwrap();
+        assert!(audio_buffers.is_empty());
+        assert_eq!(audio_buffers.frames(), 0);
+        assert_eq!(audio_buffers.channel(0).count(), 0);
+    }
+}
@@ -1,50 +1,161 @@
 //! device of core auido
 
-use std::{ffi, panic};
+use std::{
+    cell::UnsafeCell,
+    ffi,
+    marker::{PhantomData, PhantomPinned},
+    panic,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+};
 
 use coreaudio_sys::{
     AudioBufferList, AudioDeviceCreateIOProcID, AudioDeviceDestroyIOProcID, AudioDeviceID,
     AudioDeviceStart, AudioDeviceStop, AudioObjectID, AudioTimeStamp, OSStatus,
 };
 
-use crate::aoerror::Result;
+use crate::{
+    aoerror::{AudioError, Result},
+    core_audio::buffer::AudioBufferListRef,
+};
 
 /// encapsulation of AudioDeviceIOProc
 /// 与AudioIoProcHandler区别：AudioIoProc用于调用者实现自己的处理逻辑
-pub trait AudioIoProc {
+/// proc 在 core audio 的io线程中执行，所以要求 Send
+pub trait AudioIoProc: Send {
+    /// 控制线程发送给io proc的命令
+    type Command: Send;
+
     fn proc(
         &mut self,
         in_device: AudioObjectID,
         in_now: &AudioTimeStamp,
-        in_input_data: &AudioBufferList,
+        in_input_data: AudioBufferListRef<'_>,
         in_input_time: &AudioTimeStamp,
         out_output_data: &mut AudioBufferList,
         in_output_time: &AudioTimeStamp,
     ) -> OSStatus;
+
+    /// 处理控制线程发送的命令
+    /// 在io线程中执行，每次调用proc之前，按发送顺序处理所有待处理的命令
+    fn command(&mut self, _command: Self::Command) {}
+}
+
+/// io proc 运行统计
+/// io线程写，控制线程读，只使用原子操作
+#[derive(Debug, Default)]
+pub struct AudioIoProcStats {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    panics: AtomicU64,
+    commands: AtomicU64,
+}
+
+impl AudioIoProcStats {
+    /// proc 被调用的次数
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    /// proc 返回错误码的次数
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// proc panic 的次数
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// 已经处理的命令数量
+    pub fn commands(&self) -> u64 {
+        self.commands.load(Ordering::Relaxed)
+    }
+}
+
+// io proc 运行需要的全部状态
+// 分配在堆上并且pin住，地址作为 inClientData 交给 core audio，
+// 所以 AudioIoProcHandler 被move后，core audio 持有的指针仍然有效
+struct AudioIoProcContext<T: AudioIoProc> {
+    // 只在io线程中可变借用，io proc 停止后，才允许控制线程访问
+    audio_io_proc: UnsafeCell<T>,
+    // 只在io线程中使用
+    command_rx: mpsc::Receiver<T::Command>,
+    stats: Arc<AudioIoProcStats>,
+    _pin: PhantomPinned,
 }
 
 /// encapsulation of AudioDeviceIOProcId
 /// 与AudioIoProc区别： AudioIoProcHandler是Audio的struct，用于生命周期控制
-pub struct AudioIoProcHandler<T: AudioIoProc> {
+/// 借用device，保证device（例如AudioAggregateDevice）删除前，io proc已经停止、删除
+pub struct AudioIoProcHandler<'a, T: AudioIoProc> {
     // device id
     audio_device_id: AudioDeviceID,
-    // 调用者执行的方法
-    audio_io_proc: T,
+    // 调用者执行的方法，以及和控制线程共享的状态
+    context: Pin<Box<AudioIoProcContext<T>>>,
+    command_tx: mpsc::Sender<T::Command>,
     io_proc_id: coreaudio_sys::AudioDeviceIOProcID,
     // true: running false: no running
     is_run: bool,
+    // 借用device，不能比device活得更久
+    _device: PhantomData<&'a AudioDeviceID>,
 }
 
-impl<T: AudioIoProc> AudioIoProcHandler<T> {
-    pub fn new(audio_device_id: &AudioDeviceID, audio_io_proc: T) -> AudioIoProcHandler<T> {
+impl<'a, T: AudioIoProc> AudioIoProcHandler<'a, T> {
+    pub fn new(audio_device_id: &'a AudioDeviceID, audio_io_proc: T) -> AudioIoProcHandler<'a, T> {
+        let (command_tx, command_rx) = mpsc::channel();
+        let context = Box::pin(AudioIoProcContext {
+            audio_io_proc: UnsafeCell::new(audio_io_proc),
+            command_rx,
+            stats: Arc::new(AudioIoProcStats::default()),
+            _pin: PhantomPinned,
+        });
         AudioIoProcHandler {
             audio_device_id: *audio_device_id,
-            audio_io_proc,
+            context,
+            command_tx,
             io_proc_id: None,
             is_run: false,
+            _device: PhantomData,
         }
     }
 
+    /// 运行统计，可以在其它线程中读取
+    pub fn stats(&self) -> Arc<AudioIoProcStats> {
+        Arc::clone(&self.context.stats)
+    }
+
+    /// 命令发送端，可以clone到其它线程
+    pub fn command_sender(&self) -> mpsc::Sender<T::Command> {
+        self.command_tx.clone()
+    }
+
+    /// 发送命令，io proc 下次被调用时处理
+    pub fn send(&self, command: T::Command) -> Result<()> {
+        self.command_tx
+            .send(command)
+            .map_err(|_| AudioError::with_msg("io proc command channel closed"))
+    }
+
+    /// io proc 没有运行时，才能访问调用者的处理逻辑
+    pub fn audio_io_proc_mut(&mut self) -> Option<&mut T> {
+        if self.is_run {
+            return None;
+        }
+        // 没有运行，core audio 不会调用 trampoline，这里是唯一的借用
+        Some(unsafe { &mut *self.context.audio_io_proc.get() })
+    }
+
+    // 传给 core audio 的 inClientData
+    fn client_data(&self) -> *mut ffi::c_void {
+        let context: &AudioIoProcContext<T> = &self.context;
+        context as *const AudioIoProcContext<T> as *mut ffi::c_void
+    }
+
     // AudioDeviceCreateIOProcID
     fn init_io_proc_id(&mut self) -> Result<()> {
         let mut out_ioproc_id =
@@ -53,7 +164,7 @@ impl<T: AudioIoProc> AudioIoProcHandler<T> {
             AudioDeviceCreateIOProcID(
                 self.audio_device_id,
                 Some(Self::audio_io_proc_trampoline),
-                &mut self.audio_io_proc as *mut T as *mut ffi::c_void,
+                self.client_data(),
                 out_ioproc_id.as_mut_ptr(),
             )
         };
@@ -79,6 +190,8 @@ impl<T: AudioIoProc> AudioIoProcHandler<T> {
         Ok(())
     }
 
+    // AudioDeviceStop
+    // 返回后，core audio 不会再调用 io proc
     pub fn stop(&mut self) -> Result<()> {
         if !self.is_run {
             return Ok(());
@@ -106,22 +219,43 @@ impl<T: AudioIoProc> AudioIoProcHandler<T> {
             return coreaudio_sys::kAudioHardwareUnspecifiedError as OSStatus;
         }
 
-        // 将 inClientData 转换回 &mut T
-        let audio_io_proc = unsafe { &mut *(in_client_data as *mut T) };
-        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| unsafe {
-            audio_io_proc.proc(
-                in_device,
-                &(*in_now),
-                &(*in_input_data),
-                &(*in_input_time),
-                &mut (*out_output_data),
-                &(*in_output_time),
-            )
+        // inClientData 指向 pin 住的 AudioIoProcContext，在 io proc 删除前一直有效
+        let context = unsafe { &*(in_client_data as *const AudioIoProcContext<T>) };
+        let stats = &context.stats;
+        stats.calls.fetch_add(1, Ordering::Relaxed);
+        // core audio 不会并发调用同一个 io proc，运行期间控制线程也不会访问，这里是唯一的可变借用
+        let audio_io_proc = unsafe { &mut *context.audio_io_proc.get() };
+        // core audio 拥有输入的 list，回调期间 mData 可以读取，没有输入时是空的
+        let input = match in_input_data.is_null() {
+            true => AudioBufferListRef::default(),
+            false => unsafe { AudioBufferListRef::from_list(&*in_input_data) },
+        };
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            while let Ok(command) = context.command_rx.try_recv() {
+                audio_io_proc.command(command);
+                stats.commands.fetch_add(1, Ordering::Relaxed);
+            }
+            unsafe {
+                audio_io_proc.proc(
+                    in_device,
+                    &(*in_now),
+                    input,
+                    &(*in_input_time),
+                    &mut (*out_output_data),
+                    &(*in_output_time),
+                )
+            }
         }));
 
         match result {
-            Ok(status) => status,
+            Ok(status) => {
+                if status != crate::core_audio::K_AUDIO_HARDWARE_NO_ERROR {
+                    stats.errors.fetch_add(1, Ordering::Relaxed);
+                }
+                status
+            }
             Err(error) => {
+                stats.panics.fetch_add(1, Ordering::Relaxed);
                 if let Some(s) = error.downcast_ref::<&str>() {
                     eprintln!("Panic occurred in audio_io_proc: {}", s);
                 } else {
@@ -133,7 +267,7 @@ impl<T: AudioIoProc> AudioIoProcHandler<T> {
     }
 }
 
-impl<T: AudioIoProc> Drop for AudioIoProcHandler<T> {
+impl<T: AudioIoProc> Drop for AudioIoProcHandler<'_, T> {
     fn drop(&mut self) {
         if self.is_run {
             let _ = self.stop();
@@ -145,3 +279,163 @@ impl<T: AudioIoProc> Drop for AudioIoProcHandler<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{ptr, thread};
+
+    use super::*;
+
+    // 记录收到的命令和调用次数
+    struct FakeProc {
+        calls: u32,
+        commands: Vec<u32>,
+        // proc 被调用时，已经处理的命令数量
+        commands_before_proc: Vec<usize>,
+        panic: bool,
+        status: OSStatus,
+    }
+
+    impl FakeProc {
+        fn new() -> Self {
+            FakeProc {
+                calls: 0,
+                commands: Vec::new(),
+                commands_before_proc: Vec::new(),
+                panic: false,
+                status: crate::core_audio::K_AUDIO_HARDWARE_NO_ERROR,
+            }
+        }
+    }
+
+    impl AudioIoProc for FakeProc {
+        type Command = u32;
+
+        fn proc(
+            &mut self,
+            _in_device: AudioObjectID,
+            _in_now: &AudioTimeStamp,
+            _in_input_data: AudioBufferListRef<'_>,
+            _in_input_time: &AudioTimeStamp,
+            _out_output_data: &mut AudioBufferList,
+            _in_output_time: &AudioTimeStamp,
+        ) -> OSStatus {
+            self.calls += 1;
+            self.commands_before_proc.push(self.commands.len());
+            if self.panic {
+                panic!("fake proc panic");
+            }
+            self.status
+        }
+
+        fn command(&mut self, command: Self::Command) {
+            self.commands.push(command);
+        }
+    }
+
+    // 模拟 core audio：在另一个线程中，用 inClientData 调用 trampoline
+    fn drive<T: AudioIoProc>(client_data: usize, times: usize) -> Vec<OSStatus> {
+        thread::spawn(move || {
+            let time_stamp = AudioTimeStamp::default();
+            let input = AudioBufferList::default();
+            let mut output = AudioBufferList::default();
+            (0..times)
+                .map(|_| unsafe {
+                    AudioIoProcHandler::<T>::audio_io_proc_trampoline(
+                        1,
+                        &time_stamp,
+                        &input,
+                        &time_stamp,
+                        &mut output,
+                        &time_stamp,
+                        client_data as *mut ffi::c_void,
+                    )
+                })
+                .collect()
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn test_trampoline_call_proc() {
+        let device_id = 1;
+        let mut handler = AudioIoProcHandler::new(&device_id, FakeProc::new());
+        let status_vec = drive::<FakeProc>(handler.client_data() as usize, 3);
+        assert_eq!(
+            status_vec,
+            vec![crate::core_audio::K_AUDIO_HARDWARE_NO_ERROR; 3]
+        );
+        assert_eq!(handler.stats().calls(), 3);
+        assert_eq!(handler.stats().errors(), 0);
+        assert_eq!(handler.audio_io_proc_mut().unwrap().calls, 3);
+    }
+
+    #[test]
+    fn test_trampoline_after_handler_move() {
+        let device_id = 1;
+        let handler = AudioIoProcHandler::new(&device_id, FakeProc::new());
+        let client_data = handler.client_data() as usize;
+        // move 到堆上，client data 仍然指向同一个 context
+        let mut handler = Box::new(handler);
+        assert_eq!(client_data, handler.client_data() as usize);
+        drive::<FakeProc>(client_data, 2);
+        assert_eq!(handler.audio_io_proc_mut().unwrap().calls, 2);
+    }
+
+    #[test]
+    fn test_trampoline_command_before_proc() {
+        let device_id = 1;
+        let mut handler = AudioIoProcHandler::new(&device_id, FakeProc::new());
+        handler.send(1).unwrap();
+        let sender = handler.command_sender();
+        thread::spawn(move || sender.send(2).unwrap())
+            .join()
+            .unwrap();
+        drive::<FakeProc>(handler.client_data() as usize, 1);
+        handler.send(3).unwrap();
+        drive::<FakeProc>(handler.client_data() as usize, 1);
+
+        assert_eq!(handler.stats().commands(), 3);
+        let fake_proc = handler.audio_io_proc_mut().unwrap();
+        assert_eq!(fake_proc.commands, vec![1, 2, 3]);
+        assert_eq!(fake_proc.commands_before_proc, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_trampoline_error_status() {
+        let device_id = 1;
+        let mut fake_proc = FakeProc::new();
+        fake_proc.status = crate::core_audio::K_AUDIO_HARDWARE_ILLEGAL_OPERATION_ERROR;
+        let handler = AudioIoProcHandler::new(&device_id, fake_proc);
+        let status_vec = drive::<FakeProc>(handler.client_data() as usize, 2);
+        assert_eq!(
+            status_vec,
+            vec![crate::core_audio::K_AUDIO_HARDWARE_ILLEGAL_OPERATION_ERROR; 2]
+        );
+        assert_eq!(handler.stats().errors(), 2);
+    }
+
+    #[test]
+    fn test_trampoline_catch_panic() {
+        let device_id = 1;
+        let mut fake_proc = FakeProc::new();
+        fake_proc.panic = true;
+        let handler = AudioIoProcHandler::new(&device_id, fake_proc);
+        let status_vec = drive::<FakeProc>(handler.client_data() as usize, 1);
+        assert_eq!(
+            status_vec,
+            vec![crate::core_audio::K_AUDIO_HARDWARE_UNSPECIFIED_ERROR]
+        );
+        assert_eq!(handler.stats().panics(), 1);
+    }
+
+    #[test]
+    fn test_trampoline_null_client_data() {
+        let status_vec = drive::<FakeProc>(ptr::null_mut::<ffi::c_void>() as usize, 1);
+        assert_eq!(
+            status_vec,
+            vec![crate::core_audio::K_AUDIO_HARDWARE_UNSPECIFIED_ERROR]
+        );
+    }
+}
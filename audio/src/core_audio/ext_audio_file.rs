@@ -1,25 +1,121 @@
 //! ExtAudioFileRef of core audio
 
+use std::ffi::c_void;
 use std::ptr;
 use std::{fs, path};
 
 use crate::AudioStreamBasicDescription;
 use crate::aoerror::AudioError;
 use crate::aoerror::Result;
+use crate::core_audio::buffer::{self, AudioBufferListRef};
 use crate::foundation::create_cf_url_ref;
-use coreaudio_sys::{AudioBufferList, ExtAudioFileRef};
+use coreaudio_sys::{AudioBuffer, AudioBufferList, ExtAudioFileRef};
+
+// AudioFormat.h 中 kAppleLosslessFormatFlag_xxBitSourceData，flac 也使用这些 flag 表示采样位数
+const LOSSLESS_FLAG_16_BIT: u32 = 1;
+const LOSSLESS_FLAG_24_BIT: u32 = 3;
+const LOSSLESS_FLAG_32_BIT: u32 = 4;
+
+/// 音频文件的容器格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileType {
+    #[default]
+    Caf,
+    Wav,
+    Flac,
+}
+
+impl FileType {
+    pub const ALL: [FileType; 3] = [FileType::Caf, FileType::Wav, FileType::Flac];
+
+    /// 按扩展名确定格式，不区分大小写
+    pub fn from_extension(ext: &str) -> Option<FileType> {
+        FileType::ALL
+            .into_iter()
+            .find(|file_type| file_type.extension().eq_ignore_ascii_case(ext))
+    }
+
+    pub fn from_path<P: AsRef<path::Path>>(path: P) -> Option<FileType> {
+        FileType::from_extension(path.as_ref().extension()?.to_str()?)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            FileType::Caf => "caf",
+            FileType::Wav => "wav",
+            FileType::Flac => "flac",
+        }
+    }
+
+    fn audio_file_type_id(self) -> u32 {
+        match self {
+            FileType::Caf => coreaudio_sys::kAudioFileCAFType,
+            FileType::Wav => coreaudio_sys::kAudioFileWAVEType,
+            FileType::Flac => coreaudio_sys::kAudioFileFLACType,
+        }
+    }
+
+    /// 写入 client 格式的线性 PCM 时，文件中数据的格式
+    /// caf、wav 直接保存 PCM，flac 只支持整数采样
+    pub fn file_format(
+        self,
+        client: &AudioStreamBasicDescription,
+    ) -> Result<AudioStreamBasicDescription> {
+        if self != FileType::Flac {
+            return Ok(*client);
+        }
+        let float = client.mFormatFlags & coreaudio_sys::kAudioFormatFlagIsFloat != 0;
+        let flags = match (float, client.mBitsPerChannel) {
+            (false, 16) => LOSSLESS_FLAG_16_BIT,
+            (false, 24) => LOSSLESS_FLAG_24_BIT,
+            (false, 32) => LOSSLESS_FLAG_32_BIT,
+            _ => {
+                return Err(AudioError::with_msg(format!(
+                    "flac 不支持 {} 位{}采样",
+                    client.mBitsPerChannel,
+                    if float { "浮点" } else { "" }
+                )));
+            }
+        };
+        // 其它字段由 core audio 填写
+        Ok(AudioStreamBasicDescription {
+            mSampleRate: client.mSampleRate,
+            mFormatID: coreaudio_sys::kAudioFormatFLAC,
+            mFormatFlags: flags,
+            mBytesPerPacket: 0,
+            mFramesPerPacket: 0,
+            mBytesPerFrame: 0,
+            mChannelsPerFrame: client.mChannelsPerFrame,
+            mBitsPerChannel: 0,
+            mReserved: 0,
+        })
+    }
+}
 
 /// encapsulation of ExtAudioFileRef
 #[derive(Debug)]
 pub struct AudioExtAudioFile {
     ext_audio_file_ref: ExtAudioFileRef,
     path: path::PathBuf,
+    // 写入、读取数据的格式(client data format)，用于计算帧数
+    stream_desc: AudioStreamBasicDescription,
 }
 
 impl AudioExtAudioFile {
+    /// 创建 caf 文件，stream_desc 是文件中数据的格式，也是写入数据的格式
     pub fn create<P: AsRef<path::Path>>(
         path_aef: P,
         stream_desc: &AudioStreamBasicDescription,
+    ) -> Result<Self> {
+        Self::create_with_type(path_aef, FileType::Caf, stream_desc)
+    }
+
+    /// 创建 file_type 格式的文件，stream_desc 是文件中数据的格式
+    /// 和写入数据的格式不同时，使用 set_client_format 设置写入的格式
+    pub fn create_with_type<P: AsRef<path::Path>>(
+        path_aef: P,
+        file_type: FileType,
+        stream_desc: &AudioStreamBasicDescription,
     ) -> Result<Self> {
         let path = path_aef.as_ref();
         if path.try_exists()? {
@@ -35,7 +131,7 @@ impl AudioExtAudioFile {
         let status = unsafe {
             coreaudio_sys::ExtAudioFileCreateWithURL(
                 cf_url,
-                coreaudio_sys::kAudioFileCAFType,
+                file_type.audio_file_type_id(),
                 stream_desc,
                 ptr::null(),
                 coreaudio_sys::kAudioFileFlags_EraseFile,
@@ -48,17 +144,143 @@ impl AudioExtAudioFile {
         Ok(AudioExtAudioFile {
             ext_audio_file_ref,
             path: path.to_path_buf(),
+            stream_desc: *stream_desc,
+        })
+    }
+
+    /// 打开已经存在的文件，用于读取
+    /// 读取的格式默认和文件的格式一致，可以使用 set_client_format 修改
+    pub fn open<P: AsRef<path::Path>>(path_aef: P) -> Result<Self> {
+        let path = path_aef.as_ref();
+        if !path.try_exists()? {
+            return Err(AudioError::with_msg("文件不存在"))?;
+        }
+        let cf_url = create_cf_url_ref(path, false)?;
+
+        let mut ext_audio_file_ref = std::mem::MaybeUninit::<ExtAudioFileRef>::uninit();
+        let status =
+            unsafe { coreaudio_sys::ExtAudioFileOpenURL(cf_url, ext_audio_file_ref.as_mut_ptr()) };
+        check_status!("open ext audio file fail", status);
+        let ext_audio_file_ref = unsafe { ext_audio_file_ref.assume_init() };
+
+        let stream_desc = match file_format(ext_audio_file_ref) {
+            Ok(stream_desc) => stream_desc,
+            Err(error) => {
+                let status = unsafe { coreaudio_sys::ExtAudioFileDispose(ext_audio_file_ref) };
+                eprintln_status!("core audio dispose ext audio file fail", status);
+                return Err(error);
+            }
+        };
+        Ok(AudioExtAudioFile {
+            ext_audio_file_ref,
+            path: path.to_path_buf(),
+            stream_desc,
         })
     }
 
-    pub fn write_audio_buffer_list_async(&mut self, io_data: &AudioBufferList) -> Result<()> {
-        let float32_size = std::mem::size_of::<coreaudio_sys::Float32>() as u32;
-        let buffer = &io_data.mBuffers[0];
+    /// 文件中数据的格式
+    pub fn file_format(&self) -> Result<AudioStreamBasicDescription> {
+        file_format(self.ext_audio_file_ref)
+    }
+
+    /// 写入、读取数据的格式
+    pub fn client_format(&self) -> &AudioStreamBasicDescription {
+        &self.stream_desc
+    }
+
+    /// 设置写入、读取数据的格式，和文件格式不同时由 core audio 转换
+    pub fn set_client_format(&mut self, stream_desc: &AudioStreamBasicDescription) -> Result<()> {
+        let status = unsafe {
+            coreaudio_sys::ExtAudioFileSetProperty(
+                self.ext_audio_file_ref,
+                coreaudio_sys::kExtAudioFileProperty_ClientDataFormat,
+                std::mem::size_of::<AudioStreamBasicDescription>() as u32,
+                stream_desc as *const AudioStreamBasicDescription as *const c_void,
+            )
+        };
+        check_status!("set ext audio file client data format fail", status);
+        self.stream_desc = *stream_desc;
+        Ok(())
+    }
+
+    /// 文件的总帧数
+    pub fn length_frames(&self) -> Result<i64> {
+        let mut frames: i64 = 0;
+        let mut size = std::mem::size_of::<i64>() as u32;
+        let status = unsafe {
+            coreaudio_sys::ExtAudioFileGetProperty(
+                self.ext_audio_file_ref,
+                coreaudio_sys::kExtAudioFileProperty_FileLengthFrames,
+                &mut size,
+                &mut frames as *mut i64 as *mut c_void,
+            )
+        };
+        check_status!("get ext audio file length fail", status);
+        Ok(frames)
+    }
+
+    /// 读取交错排列的数据，client 格式必须是交错格式
+    /// 最多读取 data 能容纳的帧数，返回读取的帧数，0 表示已经读完
+    pub fn read_interleaved(&mut self, data: &mut [u8]) -> Result<usize> {
+        let bytes_per_frame = self.stream_desc.mBytesPerFrame as usize;
+        if bytes_per_frame == 0 {
+            return Err(AudioError::with_msg("不支持读取压缩格式的数据"));
+        }
+        let mut frames = (data.len() / bytes_per_frame) as u32;
+        let mut io_data = AudioBufferList {
+            mNumberBuffers: 1,
+            mBuffers: [AudioBuffer {
+                mNumberChannels: self.stream_desc.mChannelsPerFrame,
+                mDataByteSize: frames * bytes_per_frame as u32,
+                mData: data.as_mut_ptr() as *mut c_void,
+            }],
+        };
+        let status = unsafe {
+            coreaudio_sys::ExtAudioFileRead(self.ext_audio_file_ref, &mut frames, &mut io_data)
+        };
+        check_status!("ext audio file read fail", status);
+        Ok(frames as usize)
+    }
+
+    /// 写入一个 stream 的所有buffer
+    /// 交错格式只有一个buffer，非交错格式每个声道一个buffer
+    pub fn write_buffers(&mut self, buffers: AudioBufferListRef<'_>) -> Result<()> {
+        // AudioBufferListRef 保证 mData 可以读取
+        if let [buffer] = buffers.buffers() {
+            let io_data = AudioBufferList {
+                mNumberBuffers: 1,
+                mBuffers: [*buffer],
+            };
+            unsafe { self.write_audio_buffer_list(&io_data) }
+        } else {
+            let io_data = buffer::AudioBufferListBuf::new(buffers.buffers());
+            unsafe { self.write_audio_buffer_list(&io_data) }
+        }
+    }
+
+    /// 写入交错排列的字节，格式和创建文件时的格式一致
+    pub fn write_interleaved(&mut self, data: &[u8]) -> Result<()> {
+        let buffer = [AudioBuffer {
+            mNumberChannels: self.stream_desc.mChannelsPerFrame,
+            mDataByteSize: data.len() as u32,
+            // core audio 只读取数据，不会修改
+            mData: data.as_ptr() as *mut c_void,
+        }];
+        // mData 指向 data，在写入期间有效
+        self.write_buffers(unsafe { AudioBufferListRef::from_buffers(&buffer) })
+    }
+
+    /// 同步写入，返回时数据已经交给系统，会阻塞，不能在 io 线程中调用
+    ///
+    /// # Safety
+    /// 同 AudioBufferListRef::from_list，core audio 会读取所有 buffer 的数据
+    pub unsafe fn write_audio_buffer_list(&mut self, io_data: &AudioBufferList) -> Result<()> {
+        // 帧数由创建文件时的格式计算，不假设采样类型
         let number_frames_to_record =
-            buffer.mDataByteSize / (buffer.mNumberChannels * float32_size);
+            buffer::frame_count(unsafe { buffer::buffers(io_data) }, &self.stream_desc);
 
         let status = unsafe {
-            coreaudio_sys::ExtAudioFileWriteAsync(
+            coreaudio_sys::ExtAudioFileWrite(
                 self.ext_audio_file_ref,
                 number_frames_to_record,
                 io_data,
@@ -67,8 +289,33 @@ impl AudioExtAudioFile {
         check_status!("ext audio file write fail", status);
         Ok(())
     }
+
+    /// 把已经写入的数据同步到磁盘，异常退出时只丢失最后一次同步之后的数据
+    pub fn flush(&mut self) -> Result<()> {
+        fs::File::open(&self.path)?.sync_data()?;
+        Ok(())
+    }
 }
 
+fn file_format(ext_audio_file_ref: ExtAudioFileRef) -> Result<AudioStreamBasicDescription> {
+    let mut stream_desc = std::mem::MaybeUninit::<AudioStreamBasicDescription>::uninit();
+    let mut size = std::mem::size_of::<AudioStreamBasicDescription>() as u32;
+    let status = unsafe {
+        coreaudio_sys::ExtAudioFileGetProperty(
+            ext_audio_file_ref,
+            coreaudio_sys::kExtAudioFileProperty_FileDataFormat,
+            &mut size,
+            stream_desc.as_mut_ptr() as *mut c_void,
+        )
+    };
+    check_status!("get ext audio file data format fail", status);
+    Ok(unsafe { stream_desc.assume_init() })
+}
+
+// ExtAudioFileRef 没有和线程绑定，写入需要 &mut self，不会被并发使用
+// 所以可以交给录音线程写入
+unsafe impl Send for AudioExtAudioFile {}
+
 impl Drop for AudioExtAudioFile {
     fn drop(&mut self) {
         let status = unsafe { coreaudio_sys::ExtAudioFileDispose(self.ext_audio_file_ref) };
@@ -81,3 +328,31 @@ impl AsRef<path::Path> for AudioExtAudioFile {
         self.path.as_path()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::caf;
+    use crate::dsp::convert::SampleFormat;
+
+    #[test]
+    fn test_flush() {
+        let path = std::env::temp_dir().join(format!("resound-flush-{}.caf", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let desc = SampleFormat::S16
+            .stream_format(48000.0, 2)
+            .to_basic_description();
+        let mut file = AudioExtAudioFile::create(&path, &desc).unwrap();
+        let data = vec![1u8; 1000 * desc.mBytesPerFrame as usize];
+        file.write_interleaved(&data).unwrap();
+        file.flush().unwrap();
+        // 没有关闭文件，从磁盘读取已经写入的数据
+        let mut reader = fs::File::open(&path).unwrap();
+        let length = reader.metadata().unwrap().len();
+        let chunks = caf::read_chunks(&mut reader).unwrap();
+        let data_chunk = chunks.iter().find(|chunk| &chunk.kind == b"data").unwrap();
+        assert_eq!(caf::audio_data_size(data_chunk, length), data.len() as u64);
+        drop(file);
+        fs::remove_file(&path).unwrap();
+    }
+}
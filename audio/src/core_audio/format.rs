@@ -0,0 +1,446 @@
+//! AudioStreamBasicDescription of core audio
+//! rust 风格的格式描述，用于判断采样类型、声道数、是否交错等
+
+use std::{fmt, ops};
+
+use crate::{
+    AudioStreamBasicDescription,
+    aoerror::{AudioError, Result},
+};
+
+/// AudioFormatID
+/// 只列出常用的格式，其它格式保留原始值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatId {
+    LinearPcm,
+    Flac,
+    Opus,
+    Aac,
+    Other(u32),
+}
+
+impl From<u32> for FormatId {
+    fn from(value: u32) -> Self {
+        match value {
+            coreaudio_sys::kAudioFormatLinearPCM => FormatId::LinearPcm,
+            coreaudio_sys::kAudioFormatFLAC => FormatId::Flac,
+            coreaudio_sys::kAudioFormatOpus => FormatId::Opus,
+            coreaudio_sys::kAudioFormatMPEG4AAC => FormatId::Aac,
+            other => FormatId::Other(other),
+        }
+    }
+}
+
+impl From<FormatId> for u32 {
+    fn from(value: FormatId) -> Self {
+        match value {
+            FormatId::LinearPcm => coreaudio_sys::kAudioFormatLinearPCM,
+            FormatId::Flac => coreaudio_sys::kAudioFormatFLAC,
+            FormatId::Opus => coreaudio_sys::kAudioFormatOpus,
+            FormatId::Aac => coreaudio_sys::kAudioFormatMPEG4AAC,
+            FormatId::Other(other) => other,
+        }
+    }
+}
+
+impl fmt::Display for FormatId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatId::LinearPcm => write!(f, "lpcm"),
+            FormatId::Flac => write!(f, "flac"),
+            FormatId::Opus => write!(f, "opus"),
+            FormatId::Aac => write!(f, "aac"),
+            // 四字符代码，不可打印时显示数字
+            FormatId::Other(other) => {
+                let bytes = other.to_be_bytes();
+                if bytes
+                    .iter()
+                    .all(|byte| byte.is_ascii_graphic() || *byte == b' ')
+                {
+                    write!(f, "'{}'", String::from_utf8_lossy(&bytes))
+                } else {
+                    write!(f, "{}", other)
+                }
+            }
+        }
+    }
+}
+
+/// AudioFormatFlags
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct FormatFlags(pub u32);
+
+impl FormatFlags {
+    pub const FLOAT: FormatFlags = FormatFlags(coreaudio_sys::kAudioFormatFlagIsFloat);
+    pub const BIG_ENDIAN: FormatFlags = FormatFlags(coreaudio_sys::kAudioFormatFlagIsBigEndian);
+    pub const SIGNED_INTEGER: FormatFlags =
+        FormatFlags(coreaudio_sys::kAudioFormatFlagIsSignedInteger);
+    pub const PACKED: FormatFlags = FormatFlags(coreaudio_sys::kAudioFormatFlagIsPacked);
+    pub const ALIGNED_HIGH: FormatFlags = FormatFlags(coreaudio_sys::kAudioFormatFlagIsAlignedHigh);
+    pub const NON_INTERLEAVED: FormatFlags =
+        FormatFlags(coreaudio_sys::kAudioFormatFlagIsNonInterleaved);
+    pub const NON_MIXABLE: FormatFlags = FormatFlags(coreaudio_sys::kAudioFormatFlagIsNonMixable);
+
+    const NAMES: [(FormatFlags, &'static str); 7] = [
+        (Self::FLOAT, "float"),
+        (Self::BIG_ENDIAN, "big-endian"),
+        (Self::SIGNED_INTEGER, "signed-integer"),
+        (Self::PACKED, "packed"),
+        (Self::ALIGNED_HIGH, "aligned-high"),
+        (Self::NON_INTERLEAVED, "non-interleaved"),
+        (Self::NON_MIXABLE, "non-mixable"),
+    ];
+
+    #[inline]
+    pub fn contains(self, other: FormatFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// 已知标志的名称
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+impl ops::BitOr for FormatFlags {
+    type Output = FormatFlags;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        FormatFlags(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for FormatFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "FormatFlags({:#x}: {})",
+            self.0,
+            self.names().join(" | ")
+        )
+    }
+}
+
+/// 线性PCM的采样类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleType {
+    Float,
+    SignedInteger,
+    UnsignedInteger,
+}
+
+/// 格式描述
+/// 和 AudioStreamBasicDescription 一一对应，可以互相转换
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamFormat {
+    pub sample_rate: f64,
+    pub format_id: FormatId,
+    pub flags: FormatFlags,
+    pub bytes_per_packet: u32,
+    pub frames_per_packet: u32,
+    pub bytes_per_frame: u32,
+    pub channels: u32,
+    pub bits_per_channel: u32,
+}
+
+impl StreamFormat {
+    /// 紧密排列、小端序的线性PCM格式
+    pub fn linear_pcm(
+        sample_rate: f64,
+        sample_type: SampleType,
+        bits_per_channel: u32,
+        channels: u32,
+        interleaved: bool,
+    ) -> StreamFormat {
+        let mut flags = FormatFlags::PACKED;
+        match sample_type {
+            SampleType::Float => flags = flags | FormatFlags::FLOAT,
+            SampleType::SignedInteger => flags = flags | FormatFlags::SIGNED_INTEGER,
+            SampleType::UnsignedInteger => {}
+        }
+        if !interleaved {
+            flags = flags | FormatFlags::NON_INTERLEAVED;
+        }
+        let bytes_per_sample = bits_per_channel.div_ceil(8);
+        // 非交错格式，一个buffer只有一个声道
+        let bytes_per_frame = if interleaved {
+            bytes_per_sample * channels
+        } else {
+            bytes_per_sample
+        };
+        StreamFormat {
+            sample_rate,
+            format_id: FormatId::LinearPcm,
+            flags,
+            bytes_per_packet: bytes_per_frame,
+            frames_per_packet: 1,
+            bytes_per_frame,
+            channels,
+            bits_per_channel,
+        }
+    }
+
+    pub fn is_linear_pcm(&self) -> bool {
+        self.format_id == FormatId::LinearPcm
+    }
+
+    /// 只对线性PCM有意义
+    pub fn sample_type(&self) -> SampleType {
+        if self.flags.contains(FormatFlags::FLOAT) {
+            SampleType::Float
+        } else if self.flags.contains(FormatFlags::SIGNED_INTEGER) {
+            SampleType::SignedInteger
+        } else {
+            SampleType::UnsignedInteger
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        self.is_linear_pcm() && self.sample_type() == SampleType::Float
+    }
+
+    pub fn is_interleaved(&self) -> bool {
+        !self.flags.contains(FormatFlags::NON_INTERLEAVED)
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        self.flags.contains(FormatFlags::BIG_ENDIAN)
+    }
+
+    pub fn is_packed(&self) -> bool {
+        self.flags.contains(FormatFlags::PACKED)
+    }
+
+    /// 一个采样占用的字节数，只对线性PCM有意义
+    pub fn bytes_per_sample(&self) -> u32 {
+        self.bytes_per_frame
+            .checked_div(self.channels_per_buffer())
+            .unwrap_or(0)
+    }
+
+    /// 一个buffer中的声道数，交错格式是所有声道，非交错格式是1
+    pub fn channels_per_buffer(&self) -> u32 {
+        if self.is_interleaved() {
+            self.channels
+        } else {
+            1
+        }
+    }
+
+    /// io proc 中，这个格式占用的buffer数量
+    pub fn buffer_count(&self) -> u32 {
+        if self.is_interleaved() {
+            1
+        } else {
+            self.channels
+        }
+    }
+
+    /// 检查各个字段是否一致
+    pub fn validate(&self) -> Result<()> {
+        if self.sample_rate.is_nan() || self.sample_rate <= 0.0 {
+            return Err(AudioError::with_msg(format!(
+                "invalid sample rate: {}",
+                self.sample_rate
+            )));
+        }
+        if self.channels == 0 {
+            return Err(AudioError::with_msg("channels is 0"));
+        }
+        if !self.is_linear_pcm() {
+            // 压缩格式的包大小可变，不做进一步检查
+            return Ok(());
+        }
+        if self.bits_per_channel == 0 {
+            return Err(AudioError::with_msg("bits per channel is 0"));
+        }
+        if self.frames_per_packet != 1 {
+            return Err(AudioError::with_msg(format!(
+                "linear pcm frames per packet must be 1, but {}",
+                self.frames_per_packet
+            )));
+        }
+        if self.bytes_per_packet != self.bytes_per_frame {
+            return Err(AudioError::with_msg(format!(
+                "bytes per packet: {} != bytes per frame: {}",
+                self.bytes_per_packet, self.bytes_per_frame
+            )));
+        }
+        let channels_per_buffer = self.channels_per_buffer();
+        if !self.bytes_per_frame.is_multiple_of(channels_per_buffer) {
+            return Err(AudioError::with_msg(format!(
+                "bytes per frame: {} is not a multiple of channels: {}",
+                self.bytes_per_frame, channels_per_buffer
+            )));
+        }
+        let min_bytes_per_sample = self.bits_per_channel.div_ceil(8);
+        let bytes_per_sample = self.bytes_per_sample();
+        if bytes_per_sample < min_bytes_per_sample
+            || (self.is_packed() && bytes_per_sample != min_bytes_per_sample)
+        {
+            return Err(AudioError::with_msg(format!(
+                "bytes per frame: {} mismatch bits per channel: {} and channels: {}",
+                self.bytes_per_frame, self.bits_per_channel, channels_per_buffer
+            )));
+        }
+        if self.sample_type() == SampleType::Float
+            && self.bits_per_channel != 32
+            && self.bits_per_channel != 64
+        {
+            return Err(AudioError::with_msg(format!(
+                "float bits per channel must be 32 or 64, but {}",
+                self.bits_per_channel
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn to_basic_description(&self) -> AudioStreamBasicDescription {
+        AudioStreamBasicDescription {
+            mSampleRate: self.sample_rate,
+            mFormatID: self.format_id.into(),
+            mFormatFlags: self.flags.0,
+            mBytesPerPacket: self.bytes_per_packet,
+            mFramesPerPacket: self.frames_per_packet,
+            mBytesPerFrame: self.bytes_per_frame,
+            mChannelsPerFrame: self.channels,
+            mBitsPerChannel: self.bits_per_channel,
+            mReserved: 0,
+        }
+    }
+}
+
+impl From<&AudioStreamBasicDescription> for StreamFormat {
+    fn from(value: &AudioStreamBasicDescription) -> Self {
+        StreamFormat {
+            sample_rate: value.mSampleRate,
+            format_id: FormatId::from(value.mFormatID),
+            flags: FormatFlags(value.mFormatFlags),
+            bytes_per_packet: value.mBytesPerPacket,
+            frames_per_packet: value.mFramesPerPacket,
+            bytes_per_frame: value.mBytesPerFrame,
+            channels: value.mChannelsPerFrame,
+            bits_per_channel: value.mBitsPerChannel,
+        }
+    }
+}
+
+impl From<&StreamFormat> for AudioStreamBasicDescription {
+    fn from(value: &StreamFormat) -> Self {
+        value.to_basic_description()
+    }
+}
+
+// 例如： 48000 Hz, 2 ch, float32, interleaved
+impl fmt::Display for StreamFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} Hz, {} ch, ", self.sample_rate, self.channels)?;
+        if !self.is_linear_pcm() {
+            return write!(f, "{}", self.format_id);
+        }
+        let sample_type = match self.sample_type() {
+            SampleType::Float => "float",
+            SampleType::SignedInteger => "int",
+            SampleType::UnsignedInteger => "uint",
+        };
+        write!(f, "{}{}", sample_type, self.bits_per_channel)?;
+        if self.is_big_endian() {
+            write!(f, " big-endian")?;
+        }
+        if self.is_interleaved() {
+            write!(f, ", interleaved")
+        } else {
+            write!(f, ", non-interleaved")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basic_description_round_trip() {
+        let desc = AudioStreamBasicDescription {
+            mSampleRate: 48000.0,
+            mFormatID: coreaudio_sys::kAudioFormatLinearPCM,
+            mFormatFlags: coreaudio_sys::kAudioFormatFlagIsFloat
+                | coreaudio_sys::kAudioFormatFlagIsPacked,
+            mBytesPerPacket: 8,
+            mFramesPerPacket: 1,
+            mBytesPerFrame: 8,
+            mChannelsPerFrame: 2,
+            mBitsPerChannel: 32,
+            mReserved: 0,
+        };
+        let format = StreamFormat::from(&desc);
+        assert!(format.is_float());
+        assert!(format.is_interleaved());
+        assert_eq!(format.sample_type(), SampleType::Float);
+        assert_eq!(format.bytes_per_sample(), 4);
+        assert!(format.validate().is_ok());
+        let round_trip = format.to_basic_description();
+        assert_eq!(format!("{:?}", round_trip), format!("{:?}", desc));
+        assert_eq!(
+            format,
+            StreamFormat::linear_pcm(48000.0, SampleType::Float, 32, 2, true)
+        );
+    }
+
+    #[test]
+    fn test_non_interleaved() {
+        let format = StreamFormat::linear_pcm(44100.0, SampleType::SignedInteger, 16, 2, false);
+        assert!(!format.is_interleaved());
+        assert_eq!(format.bytes_per_frame, 2);
+        assert_eq!(format.channels_per_buffer(), 1);
+        assert_eq!(format.buffer_count(), 2);
+        assert!(format.validate().is_ok());
+        assert_eq!(format.to_string(), "44100 Hz, 2 ch, int16, non-interleaved");
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut format = StreamFormat::linear_pcm(48000.0, SampleType::SignedInteger, 24, 2, true);
+        assert_eq!(format.bytes_per_frame, 6);
+        assert!(format.validate().is_ok());
+        // 字节数和声道数不一致
+        format.channels = 4;
+        assert!(format.validate().is_err());
+        // 24位采样放在4个字节中，不是紧密排列
+        let mut format = StreamFormat::linear_pcm(48000.0, SampleType::SignedInteger, 24, 2, true);
+        format.bytes_per_frame = 8;
+        format.bytes_per_packet = 8;
+        assert!(format.validate().is_err());
+        format.flags = FormatFlags::SIGNED_INTEGER | FormatFlags::ALIGNED_HIGH;
+        assert!(format.validate().is_ok());
+        let mut format = StreamFormat::linear_pcm(48000.0, SampleType::Float, 16, 2, true);
+        assert!(format.validate().is_err());
+        format.bits_per_channel = 32;
+        format.bytes_per_frame = 8;
+        format.bytes_per_packet = 8;
+        assert!(format.validate().is_ok());
+        format.sample_rate = 0.0;
+        assert!(format.validate().is_err());
+    }
+
+    #[test]
+    fn test_flags() {
+        let flags = FormatFlags::FLOAT | FormatFlags::PACKED;
+        assert!(flags.contains(FormatFlags::FLOAT));
+        assert!(!flags.contains(FormatFlags::NON_INTERLEAVED));
+        assert_eq!(flags.names(), vec!["float", "packed"]);
+    }
+
+    #[test]
+    fn test_display_compressed() {
+        let mut format = StreamFormat::linear_pcm(48000.0, SampleType::Float, 32, 2, true);
+        format.format_id = FormatId::Flac;
+        assert_eq!(format.to_string(), "48000 Hz, 2 ch, flac");
+        format.format_id = FormatId::from(u32::from_be_bytes(*b"alac"));
+        assert_eq!(format.to_string(), "48000 Hz, 2 ch, 'alac'");
+    }
+}
@@ -6,6 +6,7 @@ use std::mem;
 use coreaudio_sys::{AudioObjectID, AudioStreamID};
 
 use crate::aoerror::Result;
+use crate::core_audio::format::StreamFormat;
 use crate::core_audio::{build_property_address, get_property_data_list};
 use crate::{AudioStreamBasicDescription, get_or_try_init};
 
@@ -18,11 +19,20 @@ pub struct AudioStream {
 }
 
 impl AudioStream {
+    pub fn get_id(&self) -> AudioStreamID {
+        self.audio_stream_id
+    }
+
     pub fn get_basic_description(&self) -> Result<&AudioStreamBasicDescription> {
         get_or_try_init(&self.basic_description, || {
             basic_description(&self.audio_stream_id)
         })
     }
+
+    /// rust 风格的格式描述
+    pub fn get_format(&self) -> Result<StreamFormat> {
+        self.get_basic_description().map(StreamFormat::from)
+    }
 }
 
 impl From<AudioStreamID> for AudioStream {
@@ -0,0 +1,125 @@
+//! cue sheet
+//! 每个 track 是一个单独的文件，INDEX 01 都是文件的开始位置，
+//! track 在整个录音中的位置记录在 REM 中
+
+use std::fmt::Write;
+use std::time::Duration;
+
+/// 一个 track
+#[derive(Debug, Clone)]
+pub struct CueTrack {
+    pub file: String,
+    pub title: Option<String>,
+    /// 在整个录音中的开始位置
+    pub start: Duration,
+    pub duration: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CueSheet {
+    pub title: Option<String>,
+    pub tracks: Vec<CueTrack>,
+}
+
+impl CueSheet {
+    pub fn new() -> CueSheet {
+        CueSheet::default()
+    }
+
+    pub fn push(&mut self, track: CueTrack) {
+        self.tracks.push(track);
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "REM COMMENT \"resound\"");
+        if let Some(title) = &self.title {
+            let _ = writeln!(text, "TITLE \"{}\"", escape(title));
+        }
+        for (index, track) in self.tracks.iter().enumerate() {
+            let _ = writeln!(text, "FILE \"{}\" WAVE", escape(&track.file));
+            let _ = writeln!(text, "  TRACK {:02} AUDIO", index + 1);
+            if let Some(title) = &track.title {
+                let _ = writeln!(text, "    TITLE \"{}\"", escape(title));
+            }
+            let _ = writeln!(text, "    REM START {}", timestamp(track.start));
+            if let Some(duration) = track.duration {
+                let _ = writeln!(text, "    REM DURATION {}", timestamp(duration));
+            }
+            let _ = writeln!(text, "    INDEX 01 00:00:00");
+        }
+        text
+    }
+}
+
+/// cue 中的时间格式 mm:ss:ff，一秒 75 帧
+pub fn cue_time(time: Duration) -> String {
+    let frames = (time.as_secs_f64() * 75.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        frames / 75 / 60,
+        frames / 75 % 60,
+        frames % 75
+    )
+}
+
+// REM 中使用毫秒精度，hh:mm:ss.mmm
+fn timestamp(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+// 双引号中不能出现双引号
+fn escape(value: &str) -> String {
+    value.replace('"', "'")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut sheet = CueSheet::new();
+        sheet.title = Some("live \"set\"".to_string());
+        sheet.push(CueTrack {
+            file: "resound-0-01.caf".to_string(),
+            title: None,
+            start: Duration::ZERO,
+            duration: Some(Duration::from_millis(61_500)),
+        });
+        sheet.push(CueTrack {
+            file: "resound-0-02.caf".to_string(),
+            title: Some("second".to_string()),
+            start: Duration::from_millis(3_725_042),
+            duration: None,
+        });
+        assert_eq!(
+            sheet.render(),
+            "REM COMMENT \"resound\"\n\
+             TITLE \"live 'set'\"\n\
+             FILE \"resound-0-01.caf\" WAVE\n\
+             \x20 TRACK 01 AUDIO\n\
+             \x20   REM START 00:00:00.000\n\
+             \x20   REM DURATION 00:01:01.500\n\
+             \x20   INDEX 01 00:00:00\n\
+             FILE \"resound-0-02.caf\" WAVE\n\
+             \x20 TRACK 02 AUDIO\n\
+             \x20   TITLE \"second\"\n\
+             \x20   REM START 01:02:05.042\n\
+             \x20   INDEX 01 00:00:00\n"
+        );
+    }
+
+    #[test]
+    fn test_cue_time() {
+        assert_eq!(cue_time(Duration::from_millis(61_500)), "01:01:38");
+        assert_eq!(cue_time(Duration::ZERO), "00:00:00");
+    }
+}
@@ -0,0 +1,268 @@
+//! audio processing
+//! io proc 收到的数据统一转换为交错排列的 f32，再由多个 Stage 依次处理
+
+pub mod convert;
+pub mod loudness;
+pub mod meter;
+pub mod resample;
+pub mod ring;
+pub mod silence;
+
+use crate::{
+    aoerror::{AudioError, Result},
+    core_audio::{
+        buffer::{AudioBufferListRef, AudioBuffers, I24, Sample},
+        format::{SampleType, StreamFormat},
+    },
+};
+
+/// 一段交错排列的 f32 采样
+#[derive(Debug, Clone, Default)]
+pub struct Block {
+    pub channels: usize,
+    pub sample_rate: f64,
+    /// 交错排列，长度是 channels 的整数倍
+    pub samples: Vec<f32>,
+}
+
+impl Block {
+    pub fn new(channels: usize, sample_rate: f64) -> Block {
+        Block {
+            channels,
+            sample_rate,
+            samples: Vec::new(),
+        }
+    }
+
+    /// 帧数
+    pub fn frames(&self) -> usize {
+        self.samples.len().checked_div(self.channels).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// 清空采样，保留已分配的内存
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// 使用 buffers 的数据替换当前内容，非交错格式会转换为交错排列
+    /// 容量足够时不分配内存
+    pub fn read_buffers<S: Sample>(&mut self, buffers: &AudioBuffers<'_, S>) {
+        self.channels = buffers.channels();
+        self.samples.clear();
+        if buffers.len() == 1 {
+            // 只有一个buffer，已经是交错排列
+            if let Some(buffer) = buffers.buffer(0) {
+                self.samples
+                    .extend(buffer.samples().iter().map(|sample| sample.to_f32()));
+            }
+            return;
+        }
+        self.samples.resize(buffers.frames() * self.channels, 0.0);
+        // 每个 buffer 的声道写入交错排列中对应的位置
+        let mut first_channel = 0;
+        for view in buffers.iter() {
+            let view_channels = view.channels();
+            for (frame, samples) in view
+                .samples()
+                .chunks_exact(view_channels.max(1))
+                .enumerate()
+            {
+                let start = frame * self.channels + first_channel;
+                for (out, sample) in self.samples[start..start + samples.len()]
+                    .iter_mut()
+                    .zip(samples)
+                {
+                    *out = sample.to_f32();
+                }
+            }
+            first_channel += view_channels;
+        }
+    }
+
+    /// 使用一个 stream 的数据替换当前内容
+    /// format 是 stream 的格式，支持 float32、int16、int24、int32
+    pub fn read_stream(
+        &mut self,
+        buffers: AudioBufferListRef<'_>,
+        format: &StreamFormat,
+    ) -> Result<()> {
+        let desc = format.to_basic_description();
+        self.sample_rate = format.sample_rate;
+        match (format.sample_type(), format.bytes_per_sample()) {
+            (SampleType::Float, 4) => {
+                self.read_buffers(&AudioBuffers::<f32>::from_ref(buffers, &desc)?)
+            }
+            (SampleType::SignedInteger, 2) => {
+                self.read_buffers(&AudioBuffers::<i16>::from_ref(buffers, &desc)?)
+            }
+            (SampleType::SignedInteger, 3) => {
+                self.read_buffers(&AudioBuffers::<I24>::from_ref(buffers, &desc)?)
+            }
+            (SampleType::SignedInteger, 4) => {
+                self.read_buffers(&AudioBuffers::<i32>::from_ref(buffers, &desc)?)
+            }
+            _ => {
+                return Err(AudioError::with_msg(format!(
+                    "unsupported stream format: {}",
+                    format
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 处理阶段
+/// 在录音线程中执行，所以要求 Send
+pub trait Stage: Send {
+    /// 原地处理，可以改变声道数、采样率、帧数
+    fn process(&mut self, block: &mut Block);
+
+    /// 结束时调用，输出缓存中剩余的数据
+    fn flush(&mut self, _block: &mut Block) {}
+}
+
+/// 按顺序执行的多个 Stage
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline { stages: Vec::new() }
+    }
+
+    pub fn push<T: Stage + 'static>(&mut self, stage: T) {
+        self.stages.push(Box::new(stage));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+}
+
+impl Stage for Pipeline {
+    fn process(&mut self, block: &mut Block) {
+        self.stages
+            .iter_mut()
+            .for_each(|stage| stage.process(block));
+    }
+
+    // 前一个 stage 缓存的数据，需要经过后面所有 stage 处理
+    fn flush(&mut self, block: &mut Block) {
+        for index in 0..self.stages.len() {
+            let (flushed, rest) = self.stages.split_at_mut(index + 1);
+            let mut tail = Block::new(block.channels, block.sample_rate);
+            flushed[index].flush(&mut tail);
+            if tail.is_empty() {
+                continue;
+            }
+            rest.iter_mut().for_each(|stage| stage.process(&mut tail));
+            block.channels = tail.channels;
+            block.sample_rate = tail.sample_rate;
+            block.samples.extend_from_slice(&tail.samples);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::c_void, mem};
+
+    use super::*;
+    use crate::{AudioBuffer, core_audio::buffer::AudioBufferListBuf};
+
+    fn audio_buffer<S: Sample>(channels: u32, data: &mut [S]) -> AudioBuffer {
+        AudioBuffer {
+            mNumberChannels: channels,
+            mDataByteSize: mem::size_of_val(data) as u32,
+            mData: data.as_mut_ptr() as *mut c_void,
+        }
+    }
+
+    #[test]
+    fn test_read_stream_non_interleaved() {
+        let mut left = [0i16, 16384];
+        let mut right = [-16384i16, -32768];
+        let list =
+            AudioBufferListBuf::new(&[audio_buffer(1, &mut left), audio_buffer(1, &mut right)]);
+        let format = StreamFormat::linear_pcm(44100.0, SampleType::SignedInteger, 16, 2, false);
+        let mut block = Block::default();
+        block
+            .read_stream(unsafe { AudioBufferListRef::from_list(&list) }, &format)
+            .unwrap();
+        assert_eq!(block.channels, 2);
+        assert_eq!(block.sample_rate, 44100.0);
+        assert_eq!(block.frames(), 2);
+        assert_eq!(block.samples, vec![0.0, -0.5, 0.5, -1.0]);
+    }
+
+    #[test]
+    fn test_read_stream_interleaved() {
+        let mut data = [0.25f32, -0.25, 0.5, -0.5];
+        let list = AudioBufferListBuf::new(&[audio_buffer(2, &mut data)]);
+        let format = StreamFormat::linear_pcm(48000.0, SampleType::Float, 32, 2, true);
+        let mut block = Block::default();
+        block
+            .read_stream(unsafe { AudioBufferListRef::from_list(&list) }, &format)
+            .unwrap();
+        assert_eq!(block.samples, data.to_vec());
+        // 不支持的格式
+        let format = StreamFormat::linear_pcm(48000.0, SampleType::Float, 64, 2, true);
+        assert!(
+            block
+                .read_stream(unsafe { AudioBufferListRef::from_list(&list) }, &format)
+                .is_err()
+        );
+    }
+
+    // 缓存一帧，flush 时输出
+    struct Delay {
+        last: Vec<f32>,
+    }
+
+    impl Stage for Delay {
+        fn process(&mut self, block: &mut Block) {
+            let channels = block.channels;
+            let mut samples = std::mem::take(&mut self.last);
+            let split = block.samples.len() - channels;
+            self.last = block.samples.split_off(split);
+            samples.append(&mut block.samples);
+            block.samples = samples;
+        }
+
+        fn flush(&mut self, block: &mut Block) {
+            block.samples.append(&mut self.last);
+        }
+    }
+
+    struct Gain(f32);
+
+    impl Stage for Gain {
+        fn process(&mut self, block: &mut Block) {
+            block
+                .samples
+                .iter_mut()
+                .for_each(|sample| *sample *= self.0);
+        }
+    }
+
+    #[test]
+    fn test_pipeline_flush() {
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Delay { last: Vec::new() });
+        pipeline.push(Gain(2.0));
+        let mut block = Block::new(1, 48000.0);
+        block.samples = vec![1.0, 2.0, 3.0];
+        pipeline.process(&mut block);
+        assert_eq!(block.samples, vec![2.0, 4.0]);
+        let mut block = Block::new(1, 48000.0);
+        pipeline.flush(&mut block);
+        assert_eq!(block.samples, vec![6.0]);
+    }
+}
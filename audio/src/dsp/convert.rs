@@ -0,0 +1,271 @@
+//! sample format and channel conversion
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    aoerror::AudioError,
+    core_audio::format::{SampleType, StreamFormat},
+};
+
+use super::{Block, Stage};
+
+/// 输出文件的采样格式
+/// 都是小端序、紧密排列的线性PCM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    S16,
+    S24,
+    S32,
+    F32,
+}
+
+impl SampleFormat {
+    pub fn bits(self) -> u32 {
+        match self {
+            SampleFormat::S16 => 16,
+            SampleFormat::S24 => 24,
+            SampleFormat::S32 | SampleFormat::F32 => 32,
+        }
+    }
+
+    /// 一个采样占用的字节数
+    pub fn bytes(self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    /// 交错排列的格式描述
+    pub fn stream_format(self, sample_rate: f64, channels: u32) -> StreamFormat {
+        let sample_type = match self {
+            SampleFormat::F32 => SampleType::Float,
+            _ => SampleType::SignedInteger,
+        };
+        StreamFormat::linear_pcm(sample_rate, sample_type, self.bits(), channels, true)
+    }
+
+    /// stream 格式对应的采样格式，不支持时返回 None
+    pub fn from_stream_format(format: &StreamFormat) -> Option<SampleFormat> {
+        if !format.is_linear_pcm() || format.is_big_endian() {
+            return None;
+        }
+        match (format.sample_type(), format.bits_per_channel) {
+            (SampleType::Float, 32) => Some(SampleFormat::F32),
+            (SampleType::SignedInteger, 16) => Some(SampleFormat::S16),
+            (SampleType::SignedInteger, 24) => Some(SampleFormat::S24),
+            (SampleType::SignedInteger, 32) => Some(SampleFormat::S32),
+            _ => None,
+        }
+    }
+
+    /// 编码为小端序字节，追加到 out
+    /// 整数格式超出 [-1.0, 1.0] 的采样会被截断
+    pub fn encode(self, samples: &[f32], out: &mut Vec<u8>) {
+        out.reserve(samples.len() * self.bytes());
+        match self {
+            SampleFormat::F32 => samples
+                .iter()
+                .for_each(|sample| out.extend_from_slice(&sample.to_le_bytes())),
+            SampleFormat::S16 => samples.iter().for_each(|sample| {
+                let value = quantize(*sample, 32768.0) as i16;
+                out.extend_from_slice(&value.to_le_bytes());
+            }),
+            SampleFormat::S24 => samples.iter().for_each(|sample| {
+                let value = quantize(*sample, 8388608.0) as i32;
+                out.extend_from_slice(&value.to_le_bytes()[..3]);
+            }),
+            SampleFormat::S32 => samples.iter().for_each(|sample| {
+                let value = quantize(*sample, 2147483648.0) as i32;
+                out.extend_from_slice(&value.to_le_bytes());
+            }),
+        }
+    }
+}
+
+// 缩放、四舍五入并截断到 [-scale, scale - 1]
+#[inline]
+fn quantize(sample: f32, scale: f64) -> f64 {
+    (sample as f64 * scale).round().clamp(-scale, scale - 1.0)
+}
+
+impl FromStr for SampleFormat {
+    type Err = AudioError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s16" => Ok(SampleFormat::S16),
+            "s24" => Ok(SampleFormat::S24),
+            "s32" => Ok(SampleFormat::S32),
+            "f32" => Ok(SampleFormat::F32),
+            _ => Err(AudioError::with_msg(format!(
+                "unsupported sample format: {s}, supported: s16, s24, s32, f32"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for SampleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SampleFormat::S16 => "s16",
+            SampleFormat::S24 => "s24",
+            SampleFormat::S32 => "s32",
+            SampleFormat::F32 => "f32",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 声道转换
+/// 没有 map 时：
+/// 输出1个声道，所有声道取平均，混音为单声道；
+/// 输入1个声道，复制到所有输出声道；
+/// 输出声道更多，按顺序循环复制输入声道；
+/// 输出声道更少，输入声道按输出声道数折叠后取平均。
+/// 有 map 时：输出第 i 个声道来自输入第 map[i] 个声道，超出输入声道数时为静音
+#[derive(Debug, Clone)]
+pub struct ChannelConvert {
+    channels: usize,
+    map: Option<Vec<usize>>,
+    scratch: Vec<f32>,
+}
+
+impl ChannelConvert {
+    /// 转换为 channels 个声道
+    pub fn new(channels: usize) -> ChannelConvert {
+        ChannelConvert {
+            channels,
+            map: None,
+            scratch: Vec::new(),
+        }
+    }
+
+    /// 按 map 重新排列声道，输出声道数是 map 的长度
+    pub fn with_map(map: Vec<usize>) -> ChannelConvert {
+        ChannelConvert {
+            channels: map.len(),
+            map: Some(map),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// 输出声道数
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+}
+
+impl Stage for ChannelConvert {
+    fn process(&mut self, block: &mut Block) {
+        let input_channels = block.channels;
+        let output_channels = self.channels;
+        if input_channels == 0 || (self.map.is_none() && input_channels == output_channels) {
+            return;
+        }
+        self.scratch.clear();
+        self.scratch.reserve(block.frames() * output_channels);
+        for frame in block.samples.chunks_exact(input_channels) {
+            match &self.map {
+                Some(map) => map
+                    .iter()
+                    .for_each(|&index| self.scratch.push(frame.get(index).copied().unwrap_or(0.0))),
+                None if output_channels == 1 => {
+                    self.scratch
+                        .push(frame.iter().sum::<f32>() / input_channels as f32);
+                }
+                None if output_channels > input_channels => (0..output_channels)
+                    .for_each(|channel| self.scratch.push(frame[channel % input_channels])),
+                None => (0..output_channels).for_each(|channel| {
+                    let (sum, count) = frame
+                        .iter()
+                        .skip(channel)
+                        .step_by(output_channels)
+                        .fold((0.0, 0), |(sum, count), sample| (sum + sample, count + 1));
+                    self.scratch.push(sum / count as f32);
+                }),
+            }
+        }
+        std::mem::swap(&mut block.samples, &mut self.scratch);
+        block.channels = output_channels;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(channels: usize, samples: Vec<f32>) -> Block {
+        Block {
+            channels,
+            sample_rate: 48000.0,
+            samples,
+        }
+    }
+
+    #[test]
+    fn test_downmix_mono() {
+        let mut block = block(2, vec![1.0, 0.0, 0.5, -0.5]);
+        ChannelConvert::new(1).process(&mut block);
+        assert_eq!(block.channels, 1);
+        assert_eq!(block.samples, vec![0.5, 0.0]);
+    }
+
+    #[test]
+    fn test_upmix() {
+        let mut mono = block(1, vec![0.1, 0.2]);
+        ChannelConvert::new(2).process(&mut mono);
+        assert_eq!(mono.channels, 2);
+        assert_eq!(mono.samples, vec![0.1, 0.1, 0.2, 0.2]);
+
+        let mut stereo = block(2, vec![0.1, 0.2]);
+        ChannelConvert::new(4).process(&mut stereo);
+        assert_eq!(stereo.samples, vec![0.1, 0.2, 0.1, 0.2]);
+    }
+
+    #[test]
+    fn test_fold_down() {
+        let mut quad = block(4, vec![0.1, 0.2, 0.3, 0.4]);
+        ChannelConvert::new(2).process(&mut quad);
+        assert_eq!(quad.channels, 2);
+        assert!((quad.samples[0] - 0.2).abs() < 1e-6);
+        assert!((quad.samples[1] - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_channel_map() {
+        let mut stereo = block(2, vec![0.1, 0.2, 0.3, 0.4]);
+        ChannelConvert::with_map(vec![1, 0, 5]).process(&mut stereo);
+        assert_eq!(stereo.channels, 3);
+        assert_eq!(stereo.samples, vec![0.2, 0.1, 0.0, 0.4, 0.3, 0.0]);
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut out = Vec::new();
+        SampleFormat::S16.encode(&[0.5, -1.0, 2.0], &mut out);
+        assert_eq!(out, [0x00, 0x40, 0x00, 0x80, 0xff, 0x7f]);
+
+        out.clear();
+        SampleFormat::S24.encode(&[-0.5, 1.0], &mut out);
+        assert_eq!(out, [0x00, 0x00, 0xc0, 0xff, 0xff, 0x7f]);
+
+        out.clear();
+        SampleFormat::S32.encode(&[-1.0], &mut out);
+        assert_eq!(out, i32::MIN.to_le_bytes());
+
+        out.clear();
+        SampleFormat::F32.encode(&[0.25], &mut out);
+        assert_eq!(out, 0.25f32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_sample_format() {
+        assert_eq!("s24".parse::<SampleFormat>().unwrap(), SampleFormat::S24);
+        assert!("u8".parse::<SampleFormat>().is_err());
+        let format = SampleFormat::S24.stream_format(48000.0, 2);
+        assert_eq!(format.bytes_per_frame, 6);
+        assert!(format.validate().is_ok());
+        assert_eq!(
+            SampleFormat::from_stream_format(&format),
+            Some(SampleFormat::S24)
+        );
+    }
+}
@@ -0,0 +1,389 @@
+//! EBU R128 / ITU-R BS.1770 loudness measurement
+//! K 加权滤波后，每 100ms 统计一次能量：
+//! momentary 是最近 400ms，short-term 是最近 3s，
+//! integrated 是所有 400ms 块经过绝对门限(-70 LUFS)和相对门限(-10 LU)后的平均值。
+//! true peak 使用 4 倍过采样后的最大值
+
+use std::{collections::VecDeque, f64::consts::PI};
+
+use crate::aoerror::{AudioError, Result};
+
+use super::{
+    Block, Stage,
+    resample::{Quality, Resampler},
+};
+
+// 100ms 的个数
+const MOMENTARY_STEPS: usize = 4;
+const SHORT_TERM_STEPS: usize = 30;
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+const TRUE_PEAK_OVERSAMPLE: u32 = 4;
+
+/// 测量结果，没有足够的数据时响度是负无穷
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoudnessSummary {
+    /// LUFS
+    pub integrated: f64,
+    /// LUFS
+    pub momentary_max: f64,
+    /// LUFS
+    pub short_term_max: f64,
+    /// dBTP
+    pub true_peak: f64,
+    /// dBFS
+    pub sample_peak: f64,
+}
+
+// 二阶 IIR 滤波器，Direct Form I
+#[derive(Debug, Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    #[inline]
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+// K 加权滤波器，系数按采样率计算，48kHz 时和 BS.1770 中的系数一致
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    // 高架滤波器，模拟头部的声学效果
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / sample_rate).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+    // 高通滤波器
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / sample_rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+    [shelf, high_pass]
+}
+
+// 声道权重，5.1 声道的环绕声道是 1.41，LFE 不参与计算
+fn channel_weight(channels: usize, channel: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+fn to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.log10()
+}
+
+/// 响度测量，可以作为 Stage 使用，不修改数据
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    channels: usize,
+    sample_rate: f64,
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    // 100ms 的帧数
+    step_frames: usize,
+    // 当前 100ms 中已经统计的帧数
+    step_position: usize,
+    // 当前 100ms 每个声道的平方和
+    step_sums: Vec<f64>,
+    // 最近 3s 每个 100ms 的加权能量
+    steps: VecDeque<f64>,
+    // 所有 400ms 块的能量，用于计算 integrated
+    blocks: Vec<f64>,
+    momentary_max: f64,
+    short_term_max: f64,
+    oversampler: Resampler,
+    oversampled: Block,
+    true_peak: f32,
+    sample_peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: usize, sample_rate: f64) -> Result<LoudnessMeter> {
+        if channels == 0 || sample_rate < 1.0 {
+            return Err(AudioError::with_msg(format!(
+                "invalid loudness meter format: {} ch, {} Hz",
+                channels, sample_rate
+            )));
+        }
+        let rate = sample_rate.round() as u32;
+        Ok(LoudnessMeter {
+            channels,
+            sample_rate,
+            filters: vec![k_weighting(sample_rate); channels],
+            weights: (0..channels)
+                .map(|channel| channel_weight(channels, channel))
+                .collect(),
+            step_frames: ((sample_rate / 10.0).round() as usize).max(1),
+            step_position: 0,
+            step_sums: vec![0.0; channels],
+            steps: VecDeque::with_capacity(SHORT_TERM_STEPS),
+            blocks: Vec::new(),
+            momentary_max: f64::NEG_INFINITY,
+            short_term_max: f64::NEG_INFINITY,
+            oversampler: Resampler::new(rate, rate * TRUE_PEAK_OVERSAMPLE, Quality::Fast)?,
+            oversampled: Block::new(channels, sample_rate),
+            true_peak: 0.0,
+            sample_peak: 0.0,
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// 添加交错排列的采样
+    pub fn add(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            for (channel, sample) in frame.iter().enumerate() {
+                self.sample_peak = self.sample_peak.max(sample.abs());
+                let [shelf, high_pass] = &mut self.filters[channel];
+                let weighted = high_pass.process(shelf.process(*sample as f64));
+                self.step_sums[channel] += weighted * weighted;
+            }
+            self.step_position += 1;
+            if self.step_position == self.step_frames {
+                self.end_step();
+            }
+        }
+        self.oversampled.channels = self.channels;
+        self.oversampled.samples.clear();
+        self.oversampled.samples.extend_from_slice(samples);
+        self.oversampler.process(&mut self.oversampled);
+        self.true_peak = self
+            .oversampled
+            .samples
+            .iter()
+            .fold(self.true_peak, |peak, sample| peak.max(sample.abs()));
+    }
+
+    // 一个 100ms 结束
+    fn end_step(&mut self) {
+        let power = self
+            .step_sums
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(sum, weight)| weight * sum / self.step_frames as f64)
+            .sum::<f64>();
+        self.step_sums.fill(0.0);
+        self.step_position = 0;
+        if self.steps.len() == SHORT_TERM_STEPS {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(power);
+        if self.steps.len() >= MOMENTARY_STEPS {
+            let block = self.mean_power(MOMENTARY_STEPS);
+            self.blocks.push(block);
+            self.momentary_max = self.momentary_max.max(to_lufs(block));
+        }
+        if self.steps.len() == SHORT_TERM_STEPS {
+            self.short_term_max = self
+                .short_term_max
+                .max(to_lufs(self.mean_power(SHORT_TERM_STEPS)));
+        }
+    }
+
+    // 最近 steps 个 100ms 的平均能量
+    fn mean_power(&self, steps: usize) -> f64 {
+        self.steps.iter().rev().take(steps).sum::<f64>() / steps as f64
+    }
+
+    /// 最近 400ms 的响度，LUFS
+    pub fn momentary(&self) -> f64 {
+        if self.steps.len() < MOMENTARY_STEPS {
+            return f64::NEG_INFINITY;
+        }
+        to_lufs(self.mean_power(MOMENTARY_STEPS))
+    }
+
+    /// 最近 3s 的响度，LUFS，不足 3s 时使用已有的数据
+    pub fn short_term(&self) -> f64 {
+        if self.steps.is_empty() {
+            return f64::NEG_INFINITY;
+        }
+        to_lufs(self.mean_power(self.steps.len()))
+    }
+
+    /// 开始后的整体响度，LUFS
+    pub fn integrated(&self) -> f64 {
+        let gated = |threshold: f64| {
+            let (sum, count) = self
+                .blocks
+                .iter()
+                .filter(|block| to_lufs(**block) > threshold)
+                .fold((0.0, 0), |(sum, count), block| (sum + block, count + 1));
+            if count == 0 {
+                None
+            } else {
+                Some(sum / count as f64)
+            }
+        };
+        let Some(absolute) = gated(ABSOLUTE_GATE) else {
+            return f64::NEG_INFINITY;
+        };
+        gated(to_lufs(absolute) + RELATIVE_GATE)
+            .map(to_lufs)
+            .unwrap_or(f64::NEG_INFINITY)
+    }
+
+    /// 过采样后的最大值，dBTP
+    pub fn true_peak(&self) -> f64 {
+        20.0 * (self.true_peak.max(self.sample_peak) as f64).log10()
+    }
+
+    /// 采样的最大值，dBFS
+    pub fn sample_peak(&self) -> f64 {
+        20.0 * (self.sample_peak as f64).log10()
+    }
+
+    pub fn summary(&self) -> LoudnessSummary {
+        LoudnessSummary {
+            integrated: self.integrated(),
+            momentary_max: self.momentary_max,
+            short_term_max: self.short_term_max,
+            true_peak: self.true_peak(),
+            sample_peak: self.sample_peak(),
+        }
+    }
+}
+
+impl Stage for LoudnessMeter {
+    fn process(&mut self, block: &mut Block) {
+        if block.channels == self.channels {
+            self.add(&block.samples);
+        }
+    }
+}
+
+/// 响度标准化需要的增益，dB
+/// 增益使响度达到 target，同时 true peak 不超过 ceiling；没有有效的响度时返回 None
+pub fn normalization_gain(summary: &LoudnessSummary, target: f64, ceiling: f64) -> Option<f64> {
+    if !summary.integrated.is_finite() {
+        return None;
+    }
+    let gain = target - summary.integrated;
+    if summary.true_peak.is_finite() {
+        Some(gain.min(ceiling - summary.true_peak))
+    } else {
+        Some(gain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 交错排列的正弦波，每个声道相同
+    fn sine(
+        channels: usize,
+        amplitude_db: f64,
+        frequency: f64,
+        rate: f64,
+        seconds: f64,
+    ) -> Vec<f32> {
+        let amplitude = 10f64.powf(amplitude_db / 20.0);
+        (0..(rate * seconds) as usize)
+            .flat_map(|n| {
+                let sample = (amplitude * (2.0 * PI * frequency * n as f64 / rate).sin()) as f32;
+                std::iter::repeat_n(sample, channels)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stereo_sine() {
+        // EBU Tech 3341: 1kHz -23 dBFS 立体声正弦波，响度 -23 LUFS
+        for rate in [44100.0, 48000.0] {
+            let mut meter = LoudnessMeter::new(2, rate).unwrap();
+            for chunk in sine(2, -23.0, 1000.0, rate, 20.0).chunks(1024) {
+                meter.add(chunk);
+            }
+            let summary = meter.summary();
+            assert!((summary.integrated + 23.0).abs() < 0.1, "{:?}", summary);
+            assert!((meter.momentary() + 23.0).abs() < 0.1);
+            assert!((meter.short_term() + 23.0).abs() < 0.1);
+            assert!((summary.short_term_max + 23.0).abs() < 0.1);
+            assert!((summary.sample_peak + 23.0).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn test_gating() {
+        // 静音被绝对门限排除，较小的声音被相对门限排除
+        let mut meter = LoudnessMeter::new(2, 48000.0).unwrap();
+        meter.add(&sine(2, -23.0, 1000.0, 48000.0, 10.0));
+        meter.add(&vec![0.0; 2 * 48000 * 10]);
+        meter.add(&sine(2, -50.0, 1000.0, 48000.0, 10.0));
+        assert!((meter.integrated() + 23.0).abs() < 0.1);
+
+        let silence = LoudnessMeter::new(1, 48000.0).unwrap();
+        assert_eq!(silence.integrated(), f64::NEG_INFINITY);
+        assert_eq!(silence.momentary(), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_true_peak() {
+        // fs/4 的正弦波，相位 45 度，采样都在 0.707，真实的峰值是 1.0
+        let samples = (0..48000)
+            .map(|n| (PI / 2.0 * n as f64 + PI / 4.0).sin() as f32 * 0.5)
+            .collect::<Vec<_>>();
+        let mut meter = LoudnessMeter::new(1, 48000.0).unwrap();
+        meter.add(&samples);
+        assert!((meter.sample_peak() + 9.03).abs() < 0.05);
+        assert!(
+            (meter.true_peak() + 6.02).abs() < 0.2,
+            "{}",
+            meter.true_peak()
+        );
+    }
+
+    #[test]
+    fn test_normalization_gain() {
+        let summary = LoudnessSummary {
+            integrated: -23.0,
+            momentary_max: -20.0,
+            short_term_max: -21.0,
+            true_peak: -5.0,
+            sample_peak: -5.5,
+        };
+        assert_eq!(normalization_gain(&summary, -16.0, -1.0), Some(4.0));
+        assert_eq!(normalization_gain(&summary, -20.0, -1.0), Some(3.0));
+        let silence = LoudnessSummary {
+            integrated: f64::NEG_INFINITY,
+            ..summary
+        };
+        assert_eq!(normalization_gain(&silence, -16.0, -1.0), None);
+    }
+}
@@ -0,0 +1,193 @@
+//! peak and rms level metering
+//! Meter 在录音线程中统计，MeterHandle 在其它线程读取，通过原子变量共享，不加锁
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use super::{Block, Stage};
+
+/// 一个声道的电平
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChannelLevels {
+    /// 上次读取后的最大绝对值
+    pub peak: f32,
+    /// 上次读取后的均方根
+    pub rms: f32,
+    /// 开始后绝对值达到 1.0 的采样数
+    pub clips: u64,
+}
+
+impl ChannelLevels {
+    pub fn peak_db(&self) -> f32 {
+        to_db(self.peak)
+    }
+
+    pub fn rms_db(&self) -> f32 {
+        to_db(self.rms)
+    }
+}
+
+/// 转换为 dBFS，静音时是负无穷
+pub fn to_db(value: f32) -> f32 {
+    20.0 * value.log10()
+}
+
+#[derive(Debug, Default)]
+struct ChannelState {
+    // f32 的位，非负浮点数的位和数值的大小顺序一致，可以直接比较
+    peak: AtomicU32,
+    // f64 的位
+    sum_squares: AtomicU64,
+    clips: AtomicU64,
+}
+
+#[derive(Debug)]
+struct MeterState {
+    channels: Vec<ChannelState>,
+    frames: AtomicU64,
+}
+
+/// 统计电平，不修改数据
+#[derive(Debug)]
+pub struct Meter {
+    state: Arc<MeterState>,
+    // 每个声道当前 block 的统计，避免每个采样都访问原子变量
+    peaks: Vec<f32>,
+    sum_squares: Vec<f64>,
+    clips: Vec<u64>,
+}
+
+/// 读取 Meter 的统计结果
+#[derive(Debug, Clone)]
+pub struct MeterHandle {
+    state: Arc<MeterState>,
+}
+
+impl Meter {
+    /// channels 是统计的声道数，超出的声道会被忽略
+    pub fn new(channels: usize) -> (Meter, MeterHandle) {
+        let state = Arc::new(MeterState {
+            channels: (0..channels).map(|_| ChannelState::default()).collect(),
+            frames: AtomicU64::new(0),
+        });
+        let meter = Meter {
+            state: Arc::clone(&state),
+            peaks: vec![0.0; channels],
+            sum_squares: vec![0.0; channels],
+            clips: vec![0; channels],
+        };
+        (meter, MeterHandle { state })
+    }
+}
+
+impl Stage for Meter {
+    fn process(&mut self, block: &mut Block) {
+        if block.channels == 0 || block.is_empty() {
+            return;
+        }
+        self.peaks.fill(0.0);
+        self.sum_squares.fill(0.0);
+        self.clips.fill(0);
+        for frame in block.samples.chunks_exact(block.channels) {
+            for (channel, sample) in frame.iter().take(self.peaks.len()).enumerate() {
+                let value = sample.abs();
+                self.peaks[channel] = self.peaks[channel].max(value);
+                self.sum_squares[channel] += (value as f64) * (value as f64);
+                if value >= 1.0 {
+                    self.clips[channel] += 1;
+                }
+            }
+        }
+        for (channel, state) in self.state.channels.iter().enumerate() {
+            state
+                .peak
+                .fetch_max(self.peaks[channel].to_bits(), Ordering::Relaxed);
+            let sum_squares = self.sum_squares[channel];
+            let _ = state
+                .sum_squares
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                    Some((f64::from_bits(bits) + sum_squares).to_bits())
+                });
+            state
+                .clips
+                .fetch_add(self.clips[channel], Ordering::Relaxed);
+        }
+        self.state
+            .frames
+            .fetch_add(block.frames() as u64, Ordering::Relaxed);
+    }
+}
+
+impl MeterHandle {
+    pub fn channels(&self) -> usize {
+        self.state.channels.len()
+    }
+
+    /// 读取上次读取后的电平，并重新开始统计 peak 和 rms
+    pub fn take(&self) -> Vec<ChannelLevels> {
+        let frames = self.state.frames.swap(0, Ordering::Relaxed);
+        self.state
+            .channels
+            .iter()
+            .map(|state| {
+                let peak = f32::from_bits(state.peak.swap(0, Ordering::Relaxed));
+                let sum_squares = f64::from_bits(state.sum_squares.swap(0, Ordering::Relaxed));
+                let rms = if frames == 0 {
+                    0.0
+                } else {
+                    (sum_squares / frames as f64).sqrt() as f32
+                };
+                ChannelLevels {
+                    peak,
+                    rms,
+                    clips: state.clips.load(Ordering::Relaxed),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let (mut meter, handle) = Meter::new(2);
+        let mut block = Block::new(2, 48000.0);
+        block.samples = vec![0.5, -1.0, -0.5, 0.25, 0.5, 0.0, -0.5, 0.0];
+        meter.process(&mut block);
+        // 数据不变
+        assert_eq!(block.samples[1], -1.0);
+        let levels = handle.take();
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels[0].peak, 0.5);
+        assert!((levels[0].rms - 0.5).abs() < 1e-6);
+        assert!((levels[0].peak_db() + 6.0206).abs() < 1e-3);
+        assert_eq!(levels[1].peak, 1.0);
+        assert_eq!(levels[1].clips, 1);
+
+        // 读取后重新统计 peak 和 rms，clips 累计
+        block.samples = vec![0.0, 0.1, 0.0, -0.1];
+        meter.process(&mut block);
+        let levels = handle.take();
+        assert_eq!(levels[0].peak, 0.0);
+        assert_eq!(levels[0].rms_db(), f32::NEG_INFINITY);
+        assert!((levels[1].peak - 0.1).abs() < 1e-6);
+        assert_eq!(levels[1].clips, 1);
+    }
+
+    #[test]
+    fn test_extra_channels_ignored() {
+        let (mut meter, handle) = Meter::new(1);
+        let mut block = Block::new(3, 48000.0);
+        block.samples = vec![0.25, 1.0, 1.0];
+        meter.process(&mut block);
+        let levels = handle.take();
+        assert_eq!(handle.channels(), 1);
+        assert_eq!(levels[0].peak, 0.25);
+        assert_eq!(levels[0].clips, 0);
+    }
+}
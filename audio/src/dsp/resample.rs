@@ -0,0 +1,376 @@
+//! sample rate conversion
+//! 多相滤波器实现的任意有理数比例重采样，滤波器是 Kaiser 窗口的 sinc 函数
+
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+use crate::aoerror::AudioError;
+
+use super::{Block, Stage};
+
+/// 重采样质量，质量越高每个输出采样的计算量越大
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quality {
+    Fast,
+    Medium,
+    #[default]
+    High,
+}
+
+impl Quality {
+    // 每个相位的滤波器长度
+    fn taps(self) -> usize {
+        match self {
+            Quality::Fast => 16,
+            Quality::Medium => 32,
+            Quality::High => 64,
+        }
+    }
+
+    // 截止频率相对奈奎斯特频率的比例
+    fn rolloff(self) -> f64 {
+        match self {
+            Quality::Fast => 0.85,
+            Quality::Medium => 0.9,
+            Quality::High => 0.945,
+        }
+    }
+
+    // Kaiser 窗口参数，决定阻带衰减
+    fn beta(self) -> f64 {
+        match self {
+            Quality::Fast => 6.0,
+            Quality::Medium => 8.6,
+            Quality::High => 10.0,
+        }
+    }
+}
+
+impl FromStr for Quality {
+    type Err = AudioError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fast" => Ok(Quality::Fast),
+            "medium" => Ok(Quality::Medium),
+            "high" => Ok(Quality::High),
+            _ => Err(AudioError::with_msg(format!(
+                "unsupported resample quality: {s}, supported: fast, medium, high"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Quality::Fast => "fast",
+            Quality::Medium => "medium",
+            Quality::High => "high",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 流式重采样
+/// 采样率比例约分为 up / down：先插值 up 倍，低通滤波，再抽取 down 倍。
+/// 滤波器延迟已经补偿，输出和输入在时间上对齐，flush 输出剩余的采样
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    from_rate: u32,
+    to_rate: u32,
+    up: usize,
+    down: usize,
+    taps: usize,
+    // 多相滤波器，第 p 个相位的系数是 filter[p * taps..(p + 1) * taps]
+    filter: Vec<f32>,
+    channels: usize,
+    // 交错排列的输入，前面是已经使用过、后续输出仍然需要的历史帧
+    history: Vec<f32>,
+    // 下一个输出需要的最新输入帧在 history 中的位置
+    index: usize,
+    // 下一个输出的相位
+    phase: usize,
+    input_frames: u64,
+    output_frames: u64,
+    scratch: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, to_rate: u32, quality: Quality) -> Result<Resampler, AudioError> {
+        if from_rate == 0 || to_rate == 0 {
+            return Err(AudioError::with_msg(format!(
+                "invalid sample rate: {} -> {}",
+                from_rate, to_rate
+            )));
+        }
+        let divisor = gcd(from_rate, to_rate);
+        let up = (to_rate / divisor) as usize;
+        let down = (from_rate / divisor) as usize;
+        let taps = quality.taps();
+        let filter = design_filter(up, down, taps, quality);
+        let mut resampler = Resampler {
+            from_rate,
+            to_rate,
+            up,
+            down,
+            taps,
+            filter,
+            channels: 0,
+            history: Vec::new(),
+            index: 0,
+            phase: 0,
+            input_frames: 0,
+            output_frames: 0,
+            scratch: Vec::new(),
+        };
+        resampler.reset(0);
+        Ok(resampler)
+    }
+
+    pub fn from_rate(&self) -> u32 {
+        self.from_rate
+    }
+
+    pub fn to_rate(&self) -> u32 {
+        self.to_rate
+    }
+
+    /// 输出相对输入的延迟，单位是输入帧，flush 前缓存在内部
+    pub fn latency(&self) -> usize {
+        self.taps / 2
+    }
+
+    /// 清空缓存的数据
+    pub fn reset(&mut self, channels: usize) {
+        self.channels = channels;
+        // 前面补 taps - 1 帧静音，作为第一个输出的历史
+        self.history.clear();
+        self.history.resize((self.taps - 1) * channels, 0.0);
+        // 插值后的滤波器延迟，从延迟之后的位置开始输出，补偿延迟
+        let delay = (self.up * self.taps - 1) / 2;
+        self.index = delay / self.up + self.taps - 1;
+        self.phase = delay % self.up;
+        self.input_frames = 0;
+        self.output_frames = 0;
+    }
+
+    fn is_passthrough(&self) -> bool {
+        self.up == 1 && self.down == 1
+    }
+
+    // 输出 history 中可以计算的所有帧，追加到 out
+    // limit 限制输出的总帧数
+    fn drain(&mut self, out: &mut Vec<f32>, limit: Option<u64>) {
+        let channels = self.channels;
+        let available = self.history.len() / channels;
+        while self.index < available && limit.is_none_or(|limit| self.output_frames < limit) {
+            let coefficients = &self.filter[self.phase * self.taps..(self.phase + 1) * self.taps];
+            // 系数已经按时间倒序存放，直接和从旧到新的帧对应
+            let start = (self.index + 1 - self.taps) * channels;
+            let frames = &self.history[start..(self.index + 1) * channels];
+            for channel in 0..channels {
+                let sum = coefficients
+                    .iter()
+                    .zip(frames.iter().skip(channel).step_by(channels))
+                    .map(|(coefficient, sample)| coefficient * sample)
+                    .sum::<f32>();
+                out.push(sum);
+            }
+            self.output_frames += 1;
+            self.phase += self.down;
+            self.index += self.phase / self.up;
+            self.phase %= self.up;
+        }
+        // 删除后续输出不再需要的帧
+        let keep_from = (self.index + 1).saturating_sub(self.taps).min(available);
+        self.history.drain(..keep_from * channels);
+        self.index -= keep_from;
+    }
+
+    // 输入 input_frames 帧时应该输出的帧数
+    fn expected_output_frames(&self) -> u64 {
+        (self.input_frames * self.up as u64).div_ceil(self.down as u64)
+    }
+}
+
+impl Stage for Resampler {
+    fn process(&mut self, block: &mut Block) {
+        block.sample_rate = self.to_rate as f64;
+        if self.is_passthrough() || block.channels == 0 {
+            return;
+        }
+        if block.channels != self.channels {
+            self.reset(block.channels);
+        }
+        self.input_frames += block.frames() as u64;
+        self.history.extend_from_slice(&block.samples);
+        let mut out = std::mem::take(&mut self.scratch);
+        out.clear();
+        self.drain(&mut out, None);
+        self.scratch = std::mem::replace(&mut block.samples, out);
+    }
+
+    fn flush(&mut self, block: &mut Block) {
+        block.sample_rate = self.to_rate as f64;
+        if self.is_passthrough() || self.channels == 0 {
+            return;
+        }
+        block.channels = self.channels;
+        // 补充静音，输出延迟中的数据
+        self.history
+            .resize(self.history.len() + self.taps * self.channels, 0.0);
+        let limit = self.expected_output_frames();
+        self.drain(&mut block.samples, Some(limit));
+        self.reset(self.channels);
+    }
+}
+
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+// 零阶修正贝塞尔函数
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..50 {
+        term *= half / k as f64;
+        sum += term * term;
+        if term * term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+// 插值后采样率下的低通滤波器，按相位重新排列
+// 每个相位内的系数按时间倒序，和 history 中按时间顺序的帧对应
+fn design_filter(up: usize, down: usize, taps: usize, quality: Quality) -> Vec<f32> {
+    let length = up * taps;
+    // 和 Resampler::reset 中补偿的延迟一致，必须是整数
+    let center = ((length - 1) / 2) as f64;
+    // 相对插值后采样率的截止频率
+    let cutoff = quality.rolloff() * 0.5 / up.max(down) as f64;
+    let beta = quality.beta();
+    let denominator = bessel_i0(beta);
+    let prototype = (0..length)
+        .map(|m| {
+            let t = m as f64 - center;
+            let x = 2.0 * cutoff * t;
+            let sinc = if x == 0.0 {
+                1.0
+            } else {
+                (PI * x).sin() / (PI * x)
+            };
+            let ratio = t / (center + 1.0);
+            let window = bessel_i0(beta * (1.0 - ratio * ratio).max(0.0).sqrt()) / denominator;
+            2.0 * cutoff * sinc * window
+        })
+        .collect::<Vec<_>>();
+    // 插值补零后能量降低为 1/up，乘 up 保持增益
+    let gain = up as f64 / prototype.iter().sum::<f64>();
+    let mut filter = vec![0.0f32; length];
+    for phase in 0..up {
+        for k in 0..taps {
+            // 输出 index - k 帧使用 prototype[phase + k * up]，倒序存放
+            filter[phase * taps + (taps - 1 - k)] = (prototype[phase + k * up] * gain) as f32;
+        }
+    }
+    filter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frequency: f64, rate: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|n| (2.0 * PI * frequency * n as f64 / rate).sin() as f32)
+            .collect()
+    }
+
+    fn resample(resampler: &mut Resampler, samples: Vec<f32>, chunk: usize) -> Vec<f32> {
+        let mut out = Vec::new();
+        for samples in samples.chunks(chunk) {
+            let mut block = Block::new(1, resampler.from_rate() as f64);
+            block.samples = samples.to_vec();
+            resampler.process(&mut block);
+            out.extend_from_slice(&block.samples);
+        }
+        let mut block = Block::new(1, resampler.from_rate() as f64);
+        resampler.flush(&mut block);
+        out.extend_from_slice(&block.samples);
+        out
+    }
+
+    // 跳过两端，和参考信号比较
+    fn max_error(out: &[f32], reference: &[f32], skip: usize) -> f32 {
+        out[skip..out.len() - skip]
+            .iter()
+            .zip(&reference[skip..])
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_downsample_sine() {
+        let mut resampler = Resampler::new(48000, 16000, Quality::High).unwrap();
+        let out = resample(&mut resampler, sine(1000.0, 48000.0, 48000), 512);
+        assert_eq!(out.len(), 16000);
+        let reference = sine(1000.0, 16000.0, 16000);
+        assert!(max_error(&out, &reference, 100) < 1e-3);
+    }
+
+    #[test]
+    fn test_rational_ratio() {
+        let mut resampler = Resampler::new(44100, 48000, Quality::High).unwrap();
+        let out = resample(&mut resampler, sine(440.0, 44100.0, 44100), 441);
+        assert_eq!(out.len(), 48000);
+        let reference = sine(440.0, 48000.0, 48000);
+        assert!(max_error(&out, &reference, 200) < 1e-3);
+    }
+
+    #[test]
+    fn test_stopband() {
+        // 12kHz 高于 16kHz 采样率的奈奎斯特频率，应该被滤除
+        let mut resampler = Resampler::new(48000, 16000, Quality::Medium).unwrap();
+        let out = resample(&mut resampler, sine(12000.0, 48000.0, 9600), 480);
+        let rms = (out[100..3100].iter().map(|s| s * s).sum::<f32>() / 3000.0).sqrt();
+        assert!(rms < 1e-2, "rms: {}", rms);
+    }
+
+    #[test]
+    fn test_chunk_independent() {
+        let input = sine(300.0, 48000.0, 4800);
+        let mut resampler = Resampler::new(48000, 22050, Quality::Fast).unwrap();
+        let whole = resample(&mut resampler, input.clone(), input.len());
+        let chunked = resample(&mut resampler, input, 37);
+        assert_eq!(whole.len(), 2205);
+        assert_eq!(whole, chunked);
+    }
+
+    #[test]
+    fn test_stereo_and_passthrough() {
+        let mut resampler = Resampler::new(48000, 24000, Quality::High).unwrap();
+        let mut block = Block::new(2, 48000.0);
+        block.samples = (0..4800).flat_map(|_| [0.5, -0.25]).collect();
+        resampler.process(&mut block);
+        assert_eq!(block.channels, 2);
+        assert_eq!(block.sample_rate, 24000.0);
+        // 直流信号增益为1
+        let frame = block.frames() / 2;
+        assert!((block.samples[frame * 2] - 0.5).abs() < 1e-3);
+        assert!((block.samples[frame * 2 + 1] + 0.25).abs() < 1e-3);
+
+        let mut resampler = Resampler::new(48000, 48000, Quality::High).unwrap();
+        let mut block = Block::new(1, 48000.0);
+        block.samples = vec![0.1, 0.2];
+        resampler.process(&mut block);
+        assert_eq!(block.samples, vec![0.1, 0.2]);
+        assert!(Resampler::new(0, 48000, Quality::High).is_err());
+    }
+}
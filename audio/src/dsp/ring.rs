@@ -0,0 +1,135 @@
+//! ring buffer
+//! 在内存中保留最近一段时间的采样，写满后覆盖最早的数据
+//! 位置是从开始写入以来的帧数，读取时用位置表示从哪里开始
+
+/// 交错排列的 f32 环形缓冲
+#[derive(Debug, Clone)]
+pub struct RingBuffer {
+    channels: usize,
+    // 容量，帧数
+    capacity: usize,
+    samples: Vec<f32>,
+    // 已经写入的帧数
+    total: u64,
+}
+
+impl RingBuffer {
+    /// capacity 是保留的帧数
+    pub fn new(channels: usize, capacity: usize) -> RingBuffer {
+        let channels = channels.max(1);
+        let capacity = capacity.max(1);
+        RingBuffer {
+            channels,
+            capacity,
+            samples: vec![0.0; capacity * channels],
+            total: 0,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// 已经写入的帧数，也是下一帧的位置
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// 当前保留的帧数
+    pub fn len(&self) -> usize {
+        self.total.min(self.capacity as u64) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// 保留的最早一帧的位置
+    pub fn oldest(&self) -> u64 {
+        self.total - self.len() as u64
+    }
+
+    /// 写入交错排列的采样，不足一帧的部分被丢弃
+    pub fn push(&mut self, samples: &[f32]) {
+        let frames = samples.len() / self.channels;
+        // 超过容量时只需要写入最后 capacity 帧
+        let skip = frames.saturating_sub(self.capacity);
+        let mut samples = &samples[skip * self.channels..frames * self.channels];
+        self.total += skip as u64;
+        while !samples.is_empty() {
+            let start = (self.total % self.capacity as u64) as usize;
+            let count = (self.capacity - start).min(samples.len() / self.channels);
+            let (head, tail) = samples.split_at(count * self.channels);
+            self.samples[start * self.channels..(start + count) * self.channels]
+                .copy_from_slice(head);
+            self.total += count as u64;
+            samples = tail;
+        }
+    }
+
+    /// 从 position 开始读取到最新的数据，追加到 out
+    /// position 已经被覆盖时从最早的一帧开始，返回实际开始的位置
+    pub fn read_from(&self, position: u64, out: &mut Vec<f32>) -> u64 {
+        let start = position.clamp(self.oldest(), self.total);
+        let mut position = start;
+        while position < self.total {
+            let index = (position % self.capacity as u64) as usize;
+            let count = (self.capacity - index).min((self.total - position) as usize);
+            out.extend_from_slice(
+                &self.samples[index * self.channels..(index + count) * self.channels],
+            );
+            position += count as u64;
+        }
+        start
+    }
+
+    /// 读取最近 frames 帧，追加到 out，返回开始的位置
+    pub fn read_last(&self, frames: usize, out: &mut Vec<f32>) -> u64 {
+        self.read_from(self.total.saturating_sub(frames as u64), out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_read() {
+        let mut ring = RingBuffer::new(2, 4);
+        assert!(ring.is_empty());
+        ring.push(&[1.0, 1.0, 2.0, 2.0, 3.0]);
+        assert_eq!(ring.total(), 2);
+        assert_eq!(ring.len(), 2);
+        let mut out = Vec::new();
+        assert_eq!(ring.read_last(10, &mut out), 0);
+        assert_eq!(out, vec![1.0, 1.0, 2.0, 2.0]);
+        // 写满后覆盖最早的数据
+        ring.push(&[3.0, 3.0, 4.0, 4.0, 5.0, 5.0]);
+        assert_eq!(ring.len(), 4);
+        assert_eq!(ring.oldest(), 1);
+        out.clear();
+        assert_eq!(ring.read_from(0, &mut out), 1);
+        assert_eq!(out, vec![2.0, 2.0, 3.0, 3.0, 4.0, 4.0, 5.0, 5.0]);
+        out.clear();
+        assert_eq!(ring.read_last(2, &mut out), 3);
+        assert_eq!(out, vec![4.0, 4.0, 5.0, 5.0]);
+        out.clear();
+        assert_eq!(ring.read_from(5, &mut out), 5);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_push_more_than_capacity() {
+        let mut ring = RingBuffer::new(1, 3);
+        ring.push(&[1.0]);
+        ring.push(&[2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(ring.total(), 6);
+        let mut out = Vec::new();
+        assert_eq!(ring.read_from(0, &mut out), 3);
+        assert_eq!(out, vec![4.0, 5.0, 6.0]);
+    }
+}
@@ -0,0 +1,307 @@
+//! silence detection
+//! 一帧中所有声道的绝对值都低于门限时，这一帧是静音
+
+use std::ops::Range;
+
+use super::Block;
+
+/// 静音判断
+#[derive(Debug, Clone, Copy)]
+pub struct SilenceDetector {
+    threshold: f32,
+    min_frames: usize,
+}
+
+impl SilenceDetector {
+    /// threshold_db 是门限，dBFS；min_frames 是认为出现间隔需要的最少连续静音帧数
+    pub fn new(threshold_db: f32, min_frames: usize) -> SilenceDetector {
+        SilenceDetector {
+            threshold: 10f32.powf(threshold_db / 20.0),
+            min_frames: min_frames.max(1),
+        }
+    }
+
+    pub fn min_frames(&self) -> usize {
+        self.min_frames
+    }
+
+    pub fn is_silent(&self, frame: &[f32]) -> bool {
+        frame.iter().all(|sample| sample.abs() < self.threshold)
+    }
+}
+
+/// 一个 block 中属于同一个 track 的帧
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackSpan {
+    /// 从 1 开始
+    pub track: usize,
+    /// block 中的帧
+    pub frames: Range<usize>,
+}
+
+/// track 在整个录音中的位置，单位是帧
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackRange {
+    pub start: u64,
+    /// 还没有结束时是 None
+    pub end: Option<u64>,
+}
+
+/// 按静音间隔拆分为多个 track
+/// 间隔中的静音不属于任何 track，间隔开始前不足 min_frames 的静音保留在前一个 track 中
+#[derive(Debug, Clone)]
+pub struct TrackSplitter {
+    detector: SilenceDetector,
+    // 当前连续静音的帧数
+    silent_run: usize,
+    in_gap: bool,
+    // 已经处理的帧数
+    position: u64,
+    tracks: Vec<TrackRange>,
+}
+
+impl TrackSplitter {
+    /// 从第 1 个 track 开始
+    pub fn new(detector: SilenceDetector) -> TrackSplitter {
+        TrackSplitter {
+            detector,
+            silent_run: 0,
+            in_gap: false,
+            position: 0,
+            tracks: vec![TrackRange {
+                start: 0,
+                end: None,
+            }],
+        }
+    }
+
+    /// 当前 track，从 1 开始
+    pub fn track(&self) -> usize {
+        self.tracks.len()
+    }
+
+    pub fn tracks(&self) -> &[TrackRange] {
+        &self.tracks
+    }
+
+    /// 处理一个 block，out 中是需要写入的帧，按顺序排列
+    pub fn split(&mut self, block: &Block, out: &mut Vec<TrackSpan>) {
+        out.clear();
+        if block.channels == 0 {
+            return;
+        }
+        for (index, frame) in block.samples.chunks_exact(block.channels).enumerate() {
+            let silent = self.detector.is_silent(frame);
+            self.silent_run = if silent { self.silent_run + 1 } else { 0 };
+            if self.in_gap {
+                if silent {
+                    self.position += 1;
+                    continue;
+                }
+                // 声音恢复，开始新的 track
+                self.in_gap = false;
+                self.tracks.push(TrackRange {
+                    start: self.position,
+                    end: None,
+                });
+            }
+            let track = self.track();
+            match out.last_mut() {
+                Some(span) if span.track == track && span.frames.end == index => {
+                    span.frames.end += 1
+                }
+                _ => out.push(TrackSpan {
+                    track,
+                    frames: index..index + 1,
+                }),
+            }
+            self.position += 1;
+            if self.silent_run == self.detector.min_frames() {
+                // 静音足够长，当前 track 结束
+                self.in_gap = true;
+                if let Some(range) = self.tracks.last_mut() {
+                    range.end = Some(self.position);
+                }
+            }
+        }
+    }
+
+    /// 录音结束，结束最后一个 track
+    pub fn finish(&mut self) {
+        if let Some(range) = self.tracks.last_mut()
+            && range.end.is_none()
+        {
+            range.end = Some(self.position);
+        }
+    }
+}
+
+/// 去掉开始和结束的静音
+/// 开始的静音直接丢弃；结束的静音需要在写入后截断，这里只记录最后一个非静音帧的位置
+#[derive(Debug, Clone)]
+pub struct SilenceTrimmer {
+    detector: SilenceDetector,
+    started: bool,
+    // 当前文件已经写入的帧数
+    frames: u64,
+    // 当前文件中最后一个非静音帧之后的位置
+    sound_end: u64,
+}
+
+impl SilenceTrimmer {
+    pub fn new(threshold_db: f32) -> SilenceTrimmer {
+        SilenceTrimmer {
+            detector: SilenceDetector::new(threshold_db, 1),
+            started: false,
+            frames: 0,
+            sound_end: 0,
+        }
+    }
+
+    /// 是否已经出现声音
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// 返回需要写入的采样，声音出现之前的采样被丢弃
+    pub fn process<'a>(&mut self, samples: &'a [f32], channels: usize) -> &'a [f32] {
+        if channels == 0 {
+            return &samples[..0];
+        }
+        let mut frames = samples.chunks_exact(channels);
+        let skip = if self.started {
+            0
+        } else {
+            match frames.position(|frame| !self.detector.is_silent(frame)) {
+                Some(position) => {
+                    self.started = true;
+                    position
+                }
+                None => return &samples[..0],
+            }
+        };
+        let kept = &samples[skip * channels..samples.len() / channels * channels];
+        if let Some(last) = kept
+            .chunks_exact(channels)
+            .rposition(|frame| !self.detector.is_silent(frame))
+        {
+            self.sound_end = self.frames + last as u64 + 1;
+        }
+        self.frames += (kept.len() / channels) as u64;
+        kept
+    }
+
+    /// 当前文件已经写入的帧数
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// 去掉结尾的静音后，当前文件应该保留的帧数
+    pub fn sound_end(&self) -> u64 {
+        self.sound_end
+    }
+
+    /// 开始新的文件，新文件不再丢弃开始的静音
+    pub fn next_file(&mut self) {
+        self.frames = 0;
+        self.sound_end = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(samples: Vec<f32>) -> Block {
+        Block {
+            channels: 1,
+            sample_rate: 10.0,
+            samples,
+        }
+    }
+
+    #[test]
+    fn test_detector() {
+        let detector = SilenceDetector::new(-40.0, 0);
+        assert_eq!(detector.min_frames(), 1);
+        assert!(detector.is_silent(&[0.001, -0.009]));
+        assert!(!detector.is_silent(&[0.001, -0.02]));
+    }
+
+    #[test]
+    fn test_split() {
+        let mut splitter = TrackSplitter::new(SilenceDetector::new(-40.0, 3));
+        let mut out = Vec::new();
+        // 2 帧声音，5 帧静音，1 帧声音
+        splitter.split(
+            &block(vec![0.5, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.5]),
+            &mut out,
+        );
+        assert_eq!(
+            out,
+            vec![
+                TrackSpan {
+                    track: 1,
+                    frames: 0..5
+                },
+                TrackSpan {
+                    track: 2,
+                    frames: 7..8
+                },
+            ]
+        );
+        // 较短的静音不会拆分，跨 block 继续统计
+        splitter.split(&block(vec![0.0, 0.0]), &mut out);
+        assert_eq!(out[0].track, 2);
+        splitter.split(&block(vec![0.0, 0.0, 0.5]), &mut out);
+        assert_eq!(
+            out,
+            vec![
+                TrackSpan {
+                    track: 2,
+                    frames: 0..1
+                },
+                TrackSpan {
+                    track: 3,
+                    frames: 2..3
+                }
+            ]
+        );
+        splitter.finish();
+        assert_eq!(
+            splitter.tracks(),
+            &[
+                TrackRange {
+                    start: 0,
+                    end: Some(5)
+                },
+                TrackRange {
+                    start: 7,
+                    end: Some(11)
+                },
+                TrackRange {
+                    start: 12,
+                    end: Some(13)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trimmer() {
+        let mut trimmer = SilenceTrimmer::new(-40.0);
+        // 开始的静音被丢弃
+        assert!(trimmer.process(&[0.0, 0.0, 0.0, 0.0], 2).is_empty());
+        assert!(!trimmer.is_started());
+        let kept = trimmer.process(&[0.0, 0.0, 0.0, 0.5, 0.0, 0.0], 2);
+        assert_eq!(kept, &[0.0, 0.5, 0.0, 0.0]);
+        assert!(trimmer.is_started());
+        // 开始后静音也会写入，只记录声音结束的位置
+        assert_eq!(trimmer.process(&[0.0, 0.0, 0.2, 0.0, 0.0, 0.0], 2).len(), 6);
+        assert_eq!(trimmer.frames(), 5);
+        assert_eq!(trimmer.sound_end(), 4);
+        trimmer.next_file();
+        assert_eq!(trimmer.process(&[0.0, 0.0], 2).len(), 2);
+        assert_eq!(trimmer.sound_end(), 0);
+    }
+}
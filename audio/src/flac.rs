@@ -0,0 +1,493 @@
+//! FLAC metadata blocks
+//! 直接读写 flac 文件开头的 metadata block，用于修改已经关闭的文件
+//! 文件头: "fLaC"，之后是 metadata block: 标志和类型(1字节) + 大小(u24，大端序) + 数据，最后一个 block 的标志位是 1
+//! VORBIS_COMMENT 中的长度是小端序
+//! 音频帧: 帧头以同步码 0xFFF8 或 0xFFF9 开始，以 CRC-8 结束，整个帧以 CRC-16 结束
+
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::aoerror::{AudioError, Result};
+use crate::caf::Recovery;
+
+const MAGIC: &[u8; 4] = b"fLaC";
+const BLOCK_HEADER_SIZE: u64 = 4;
+const BLOCK_LAST: u8 = 0x80;
+// 重新写入 metadata 时预留的 padding，之后修改标签不需要移动音频数据
+const DEFAULT_PADDING: usize = 4096;
+// STREAMINFO 的大小
+const STREAMINFO_SIZE: usize = 34;
+// recover 从文件末尾查找完整的帧的范围，不小于 4 个最大的帧
+const RECOVER_WINDOW: u64 = 1 << 20;
+
+pub const BLOCK_STREAMINFO: u8 = 0;
+pub const BLOCK_PADDING: u8 = 1;
+pub const BLOCK_VORBIS_COMMENT: u8 = 4;
+
+/// 一个 metadata block 的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub kind: u8,
+    /// block 头的位置
+    pub offset: u64,
+    pub size: u32,
+}
+
+impl Block {
+    /// 数据的位置
+    pub fn data_offset(&self) -> u64 {
+        self.offset + BLOCK_HEADER_SIZE
+    }
+
+    /// 下一个 block 或音频数据的位置
+    pub fn end(&self) -> u64 {
+        self.data_offset() + self.size as u64
+    }
+}
+
+/// 读取所有 metadata block
+pub fn read_blocks<R: Read + Seek>(reader: &mut R) -> Result<Vec<Block>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(AudioError::with_msg("not a flac file"));
+    }
+    let mut blocks = Vec::new();
+    let mut offset = MAGIC.len() as u64;
+    loop {
+        let mut header = [0u8; BLOCK_HEADER_SIZE as usize];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut header)?;
+        let block = Block {
+            kind: header[0] & !BLOCK_LAST,
+            offset,
+            size: u32::from_be_bytes([0, header[1], header[2], header[3]]),
+        };
+        blocks.push(block);
+        offset = block.end();
+        if header[0] & BLOCK_LAST != 0 {
+            return Ok(blocks);
+        }
+    }
+}
+
+/// 读取 VORBIS_COMMENT 中的条目，key 和 value 以第一个 = 分开，没有时返回空
+pub fn read_vorbis_comment<P: AsRef<Path>>(path: P) -> Result<Vec<(String, String)>> {
+    let mut file = fs::File::open(path)?;
+    let blocks = read_blocks(&mut file)?;
+    let Some(block) = blocks
+        .iter()
+        .find(|block| block.kind == BLOCK_VORBIS_COMMENT)
+    else {
+        return Ok(Vec::new());
+    };
+    let mut data = vec![0u8; block.size as usize];
+    file.seek(SeekFrom::Start(block.data_offset()))?;
+    file.read_exact(&mut data)?;
+    let broken = || AudioError::with_msg("vorbis comment is broken");
+    let mut reader = data.as_slice();
+    // vendor
+    let vendor_len = read_u32_le(&mut reader).ok_or_else(broken)? as usize;
+    reader = reader.get(vendor_len..).ok_or_else(broken)?;
+    let count = read_u32_le(&mut reader).ok_or_else(broken)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let len = read_u32_le(&mut reader).ok_or_else(broken)? as usize;
+        let bytes = reader.get(..len).ok_or_else(broken)?;
+        reader = &reader[len..];
+        let text = String::from_utf8_lossy(bytes);
+        match text.split_once('=') {
+            Some((key, value)) => entries.push((key.to_string(), value.to_string())),
+            None => entries.push((text.into_owned(), String::new())),
+        }
+    }
+    Ok(entries)
+}
+
+fn read_u32_le(reader: &mut &[u8]) -> Option<u32> {
+    let bytes = reader.get(..4)?;
+    let value = u32::from_le_bytes(bytes.try_into().ok()?);
+    *reader = &reader[4..];
+    Some(value)
+}
+
+/// 写入 VORBIS_COMMENT，替换原来的 VORBIS_COMMENT
+/// key 只能包含 0x20 到 0x7d 之间除 = 以外的字符，其它字符替换为 _
+/// padding 足够时直接修改，否则重新写入整个文件
+pub fn write_vorbis_comment<P: AsRef<Path>>(
+    path: P,
+    vendor: &str,
+    entries: &[(String, String)],
+) -> Result<()> {
+    let path = path.as_ref();
+    let mut comment = Vec::new();
+    comment.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    comment.extend_from_slice(vendor.as_bytes());
+    comment.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    for (key, value) in entries {
+        let key = key
+            .chars()
+            .map(|c| match c {
+                '\u{20}'..='\u{7d}' if c != '=' => c,
+                _ => '_',
+            })
+            .collect::<String>();
+        let text = format!("{}={}", key, value);
+        comment.extend_from_slice(&(text.len() as u32).to_le_bytes());
+        comment.extend_from_slice(text.as_bytes());
+    }
+
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let blocks = read_blocks(&mut file)?;
+    let audio_start = blocks.last().map(|block| block.end()).unwrap_or(0);
+    // 保留 VORBIS_COMMENT 和 PADDING 以外的 block
+    let mut metadata = Vec::new();
+    for block in blocks
+        .iter()
+        .filter(|block| block.kind != BLOCK_VORBIS_COMMENT && block.kind != BLOCK_PADDING)
+    {
+        let mut data = vec![0u8; block.size as usize];
+        file.seek(SeekFrom::Start(block.data_offset()))?;
+        file.read_exact(&mut data)?;
+        push_block(&mut metadata, block.kind, &data, false)?;
+    }
+    push_block(&mut metadata, BLOCK_VORBIS_COMMENT, &comment, false)?;
+    let available = audio_start - MAGIC.len() as u64;
+    let used = metadata.len() as u64;
+    if used == available {
+        set_last(&mut metadata);
+    } else if used + BLOCK_HEADER_SIZE <= available {
+        let padding = (available - used - BLOCK_HEADER_SIZE) as usize;
+        push_block(&mut metadata, BLOCK_PADDING, &vec![0u8; padding], true)?;
+    } else {
+        // 空间不够，写入新的文件后替换
+        push_block(&mut metadata, BLOCK_PADDING, &[0u8; DEFAULT_PADDING], true)?;
+        let tmp_path = path.with_extension("tags.flac");
+        let result = (|| -> Result<()> {
+            let mut tmp = fs::File::create(&tmp_path)?;
+            tmp.write_all(MAGIC)?;
+            tmp.write_all(&metadata)?;
+            file.seek(SeekFrom::Start(audio_start))?;
+            io::copy(&mut file, &mut tmp)?;
+            tmp.sync_all()?;
+            Ok(())
+        })();
+        if let Err(error) = result {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(error);
+        }
+        drop(file);
+        fs::rename(&tmp_path, path)?;
+        return Ok(());
+    }
+    file.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+    file.write_all(&metadata)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// 修复没有正常关闭的文件
+/// 从文件末尾查找最后一个完整的帧，删除之后不完整的数据，按这个帧的位置写入 STREAMINFO 中的总帧数
+/// 修改总帧数时 MD5 无法计算，改为 0，表示未知
+pub fn recover<P: AsRef<Path>>(path: P) -> Result<Recovery> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let length = file.metadata()?.len();
+    let blocks = read_blocks(&mut file)?;
+    let Some(streaminfo) = blocks
+        .iter()
+        .find(|block| block.kind == BLOCK_STREAMINFO && block.size as usize == STREAMINFO_SIZE)
+    else {
+        return Err(AudioError::with_msg("no streaminfo block"));
+    };
+    let mut info = [0u8; STREAMINFO_SIZE];
+    file.seek(SeekFrom::Start(streaminfo.data_offset()))?;
+    file.read_exact(&mut info)?;
+    let max_block_size = u16::from_be_bytes([info[2], info[3]]) as u64;
+    let max_frame_size = u32::from_be_bytes([0, info[7], info[8], info[9]]) as u64;
+    let total_samples = ((info[13] & 0x0F) as u64) << 32
+        | u32::from_be_bytes([info[14], info[15], info[16], info[17]]) as u64;
+
+    let audio_start = blocks.last().map(|block| block.end()).unwrap_or(length);
+    let window_start =
+        audio_start.max(length.saturating_sub(RECOVER_WINDOW.max(max_frame_size * 4)));
+    let mut window = vec![0u8; (length.saturating_sub(window_start)) as usize];
+    file.seek(SeekFrom::Start(window_start))?;
+    file.read_exact(&mut window)?;
+    let Some((end, header)) = last_complete_frame(&window) else {
+        if audio_start >= length {
+            return Ok(Recovery {
+                frames: 0,
+                removed: 0,
+                repaired: false,
+            });
+        }
+        return Err(AudioError::with_msg("no complete flac frame"));
+    };
+    // 固定块大小时帧头中是帧的编号，除最后一帧外大小都是 max_block_size
+    let frames = match (header.variable, max_block_size) {
+        (true, _) | (false, 0) => header.number + header.block_size,
+        (false, max_block_size) => header.number * max_block_size + header.block_size,
+    };
+    let end = window_start + end as u64;
+    let repaired = end != length || frames != total_samples;
+    if repaired {
+        file.set_len(end)?;
+        if frames != total_samples {
+            info[13] = (info[13] & 0xF0) | (frames >> 32) as u8 & 0x0F;
+            info[14..18].copy_from_slice(&(frames as u32).to_be_bytes());
+            info[18..].fill(0);
+            file.seek(SeekFrom::Start(streaminfo.data_offset()))?;
+            file.write_all(&info)?;
+        }
+        file.sync_all()?;
+    }
+    Ok(Recovery {
+        frames,
+        removed: length - end,
+        repaired,
+    })
+}
+
+// 帧头中的信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FrameHeader {
+    // 可变块大小时 number 是第一个采样的编号，否则是帧的编号
+    variable: bool,
+    number: u64,
+    block_size: u64,
+}
+
+// 最后一个完整的帧的结束位置和帧头
+// 帧头的 CRC-8 正确的位置是可能的帧，从可能的帧开始到下一个可能的帧或末尾的 CRC-16 正确时是完整的帧
+fn last_complete_frame(bytes: &[u8]) -> Option<(usize, FrameHeader)> {
+    let starts = (0..bytes.len())
+        .filter(|start| parse_frame_header(&bytes[*start..]).is_some())
+        .collect::<Vec<_>>();
+    starts.iter().rev().find_map(|start| {
+        let header = parse_frame_header(&bytes[*start..])?;
+        let mut crc = 0u16;
+        for (end, byte) in bytes.iter().enumerate().skip(*start) {
+            crc = crc16_update(crc, *byte);
+            // 包括 CRC-16 在内的 CRC 是 0
+            if crc == 0 && (end + 1 == bytes.len() || starts.binary_search(&(end + 1)).is_ok()) {
+                return Some((end + 1, header));
+            }
+        }
+        None
+    })
+}
+
+// 解析帧头，不是帧头时返回 None
+fn parse_frame_header(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < 5 || bytes[0] != 0xFF || bytes[1] & 0xFE != 0xF8 {
+        return None;
+    }
+    let variable = bytes[1] & 1 == 1;
+    let block_code = bytes[2] >> 4;
+    let rate_code = bytes[2] & 0x0F;
+    let channel_code = bytes[3] >> 4;
+    let size_code = (bytes[3] >> 1) & 0x07;
+    if block_code == 0
+        || rate_code == 0x0F
+        || channel_code >= 11
+        || size_code == 3
+        || bytes[3] & 1 != 0
+    {
+        return None;
+    }
+    // 类似 UTF-8 的编码，最多 7 字节
+    let first = bytes[4];
+    let len = first.leading_ones() as usize;
+    let (mut number, len) = match len {
+        0 => (first as u64, 1),
+        2..=7 => ((first & (0x7F >> len)) as u64, len),
+        _ => return None,
+    };
+    let mut position = 5;
+    for _ in 1..len {
+        let byte = *bytes.get(position)?;
+        if byte & 0xC0 != 0x80 {
+            return None;
+        }
+        number = number << 6 | (byte & 0x3F) as u64;
+        position += 1;
+    }
+    let block_size = match block_code {
+        1 => 192,
+        2..=5 => 576 << (block_code - 2),
+        6 => {
+            position += 1;
+            *bytes.get(position - 1)? as u64 + 1
+        }
+        7 => {
+            position += 2;
+            u16::from_be_bytes([*bytes.get(position - 2)?, *bytes.get(position - 1)?]) as u64 + 1
+        }
+        _ => 256 << (block_code - 8),
+    };
+    position += match rate_code {
+        12 => 1,
+        13 | 14 => 2,
+        _ => 0,
+    };
+    if crc8(bytes.get(..position)?) != *bytes.get(position)? {
+        return None;
+    }
+    Some(FrameHeader {
+        variable,
+        number,
+        block_size,
+    })
+}
+
+// 多项式 x^8 + x^2 + x + 1，初始值 0
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| match crc & 0x80 {
+            0 => crc << 1,
+            _ => crc << 1 ^ 0x07,
+        })
+    })
+}
+
+// 多项式 x^16 + x^15 + x^2 + 1，初始值 0
+fn crc16_update(crc: u16, byte: u8) -> u16 {
+    (0..8).fold(crc ^ (byte as u16) << 8, |crc, _| match crc & 0x8000 {
+        0 => crc << 1,
+        _ => crc << 1 ^ 0x8005,
+    })
+}
+
+fn push_block(bytes: &mut Vec<u8>, kind: u8, data: &[u8], last: bool) -> Result<()> {
+    if data.len() >= 1 << 24 {
+        return Err(AudioError::with_msg("flac metadata block is too large"));
+    }
+    let size = (data.len() as u32).to_be_bytes();
+    bytes.push(if last { kind | BLOCK_LAST } else { kind });
+    bytes.extend_from_slice(&size[1..]);
+    bytes.extend_from_slice(data);
+    Ok(())
+}
+
+// 把最后一个 block 标记为 last
+fn set_last(metadata: &mut [u8]) {
+    let mut offset = 0;
+    while offset < metadata.len() {
+        let size = u32::from_be_bytes([
+            0,
+            metadata[offset + 1],
+            metadata[offset + 2],
+            metadata[offset + 3],
+        ]) as usize;
+        let next = offset + BLOCK_HEADER_SIZE as usize + size;
+        if next >= metadata.len() {
+            metadata[offset] |= BLOCK_LAST;
+        }
+        offset = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flac_bytes(audio: &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        push_block(&mut bytes, BLOCK_STREAMINFO, &[7u8; 34], true).unwrap();
+        bytes.extend_from_slice(audio);
+        bytes
+    }
+
+    // 固定块大小 4096 的帧，帧头之后是 payload
+    fn frame_bytes(number: u8, payload: &[u8]) -> Vec<u8> {
+        // 4096 帧，44100 Hz，立体声，16 位
+        let mut bytes = vec![0xFF, 0xF8, 0xC9, 0x18, number];
+        bytes.push(crc8(&bytes));
+        bytes.extend_from_slice(payload);
+        let crc = bytes.iter().fold(0, |crc, byte| crc16_update(crc, *byte));
+        bytes.extend_from_slice(&crc.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_recover() {
+        let path =
+            std::env::temp_dir().join(format!("resound-flac-recover-{}.flac", std::process::id()));
+        // 块大小 4096，总帧数未知
+        let mut info = [0u8; STREAMINFO_SIZE];
+        info[0..2].copy_from_slice(&4096u16.to_be_bytes());
+        info[2..4].copy_from_slice(&4096u16.to_be_bytes());
+        info[18..].fill(0xAA);
+        let mut bytes = MAGIC.to_vec();
+        push_block(&mut bytes, BLOCK_STREAMINFO, &info, true).unwrap();
+        for number in 0..3 {
+            // payload 中包含同步码
+            bytes.extend_from_slice(&frame_bytes(
+                number,
+                &[number, 0xFF, 0xF8, 0xC9, 0x18, 0, 7],
+            ));
+        }
+        let complete = bytes.len();
+        // 最后一帧不完整
+        bytes.extend_from_slice(&frame_bytes(3, &[1, 2, 3])[..7]);
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(
+            recover(&path).unwrap(),
+            Recovery {
+                frames: 3 * 4096,
+                removed: 7,
+                repaired: true,
+            }
+        );
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), complete);
+        let info = &bytes[8..8 + STREAMINFO_SIZE];
+        assert_eq!(info[13] & 0x0F, 0);
+        assert_eq!(&info[14..18], &(3u32 * 4096).to_be_bytes());
+        // MD5 未知
+        assert!(info[18..].iter().all(|byte| *byte == 0));
+        // 已经完整的文件不修改
+        assert!(!recover(&path).unwrap().repaired);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_vorbis_comment() {
+        let path = std::env::temp_dir().join(format!("resound-flac-{}.flac", std::process::id()));
+        let audio = b"frames";
+        fs::write(&path, flac_bytes(audio)).unwrap();
+        assert!(read_vorbis_comment(&path).unwrap().is_empty());
+
+        // 没有 padding，需要重新写入文件
+        let entries = vec![
+            ("ENCODER".to_string(), "resound".to_string()),
+            ("start time".to_string(), "a=b".to_string()),
+        ];
+        write_vorbis_comment(&path, "resound", &entries).unwrap();
+        let expected = vec![
+            ("ENCODER".to_string(), "resound".to_string()),
+            ("start time".to_string(), "a=b".to_string()),
+        ];
+        assert_eq!(read_vorbis_comment(&path).unwrap(), expected);
+        let size = fs::metadata(&path).unwrap().len();
+
+        // padding 足够，直接修改
+        let entries = vec![("TITLE".to_string(), "take".to_string())];
+        write_vorbis_comment(&path, "resound", &entries).unwrap();
+        assert_eq!(read_vorbis_comment(&path).unwrap(), entries);
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+
+        let bytes = fs::read(&path).unwrap();
+        let blocks = read_blocks(&mut fs::File::open(&path).unwrap()).unwrap();
+        let kinds: Vec<_> = blocks.iter().map(|block| block.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![BLOCK_STREAMINFO, BLOCK_VORBIS_COMMENT, BLOCK_PADDING]
+        );
+        assert_eq!(&bytes[blocks[0].data_offset() as usize..][..34], &[7u8; 34]);
+        assert_eq!(&bytes[blocks[2].end() as usize..], audio);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,15 +1,25 @@
 //! provide macos audio
 
 pub mod aoerror;
+pub mod caf;
 mod core_audio;
+pub mod cue;
+pub mod dsp;
+pub mod flac;
 mod foundation;
+pub mod segment;
+pub mod spsc;
+pub mod timeline;
+pub mod wav;
 
 use aoerror::{AudioError, Result};
 use std::cell;
 
 pub use core_audio::aggregate_device;
+pub use core_audio::buffer;
 pub use core_audio::device;
 pub use core_audio::ext_audio_file;
+pub use core_audio::format;
 pub use core_audio::process;
 pub use core_audio::stream;
 pub use core_audio::tap;
@@ -32,6 +42,7 @@ pub use core_audio::K_AUDIO_HARDWARE_UNSUPPORTED_OPERATION_ERROR;
 pub type AudioObjectId = coreaudio_sys::AudioObjectID;
 pub type AudioStreamBasicDescription = coreaudio_sys::AudioStreamBasicDescription;
 pub type AudioTimeStamp = coreaudio_sys::AudioTimeStamp;
+pub type AudioBuffer = coreaudio_sys::AudioBuffer;
 pub type AudioBufferList = coreaudio_sys::AudioBufferList;
 pub type OSStatus = coreaudio_sys::OSStatus;
 
@@ -0,0 +1,174 @@
+//! rolling segments
+//! 长时间录音时定期开始新的文件，并按时间和总大小删除旧的文件
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 什么时候开始新的分段，都没有指定时不分段
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// 每段的时长
+    pub duration: Option<Duration>,
+    /// 每段的最大字节数
+    pub bytes: Option<u64>,
+}
+
+impl Rotation {
+    /// 当前分段已经写入 elapsed 时长、bytes 字节时，是否需要开始新的分段
+    pub fn should_rotate(&self, elapsed: Duration, bytes: u64) -> bool {
+        self.duration.is_some_and(|duration| elapsed >= duration)
+            || self.bytes.is_some_and(|limit| bytes >= limit)
+    }
+}
+
+/// 已经结束的分段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub path: PathBuf,
+    /// 结束的时间
+    pub end: SystemTime,
+    /// 文件大小
+    pub size: u64,
+}
+
+/// 保留策略，都没有指定时不删除
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Retention {
+    /// 结束超过这个时间的分段被删除
+    pub max_age: Option<Duration>,
+    /// 所有分段的总大小上限，超过时从最早的开始删除
+    pub max_total: Option<u64>,
+}
+
+impl Retention {
+    /// closed 中需要删除的分段数，closed 按时间排列，需要删除的总是最早的几个
+    /// 正在写入的分段不会删除，但计入总大小
+    pub fn expired(&self, closed: &[Segment], current_size: u64, now: SystemTime) -> usize {
+        let mut total = current_size + closed.iter().map(|segment| segment.size).sum::<u64>();
+        let mut count = 0;
+        for segment in closed {
+            let too_old = self.max_age.is_some_and(|max_age| {
+                now.duration_since(segment.end)
+                    .is_ok_and(|age| age > max_age)
+            });
+            let too_large = self.max_total.is_some_and(|max_total| total > max_total);
+            if !too_old && !too_large {
+                break;
+            }
+            total -= segment.size;
+            count += 1;
+        }
+        count
+    }
+}
+
+/// 用于文件名的 UTC 时间，2026-10-18T14-00-00
+/// 文件名中不能使用冒号
+pub fn timestamp(time: SystemTime) -> String {
+    format!("{}T{}", utc_date(time), utc_time(time))
+}
+
+/// 用于元数据的 ISO 8601 UTC 时间，2026-10-18T14:00:00Z
+pub fn utc_iso8601(time: SystemTime) -> String {
+    format!("{}T{}Z", utc_date(time), utc_time(time).replace('-', ":"))
+}
+
+/// UTC 日期，2026-10-18
+pub fn utc_date(time: SystemTime) -> String {
+    let (year, month, day) = civil_from_days((unix_secs(time) / 86400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// UTC 时间，14-00-00
+pub fn utc_time(time: SystemTime) -> String {
+    let secs_of_day = unix_secs(time) % 86400;
+    format!(
+        "{:02}-{:02}-{:02}",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+// 1970-01-01 之后的天数转换为公历日期
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00-00-00");
+        // 闰年
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400 + 3_725);
+        assert_eq!(timestamp(time), "2000-02-29T01-02-05");
+        assert_eq!(utc_date(time), "2000-02-29");
+        assert_eq!(utc_time(time), "01-02-05");
+        assert_eq!(utc_iso8601(time), "2000-02-29T01:02:05Z");
+        let time = UNIX_EPOCH + Duration::from_secs(1_792_332_000);
+        assert_eq!(timestamp(time), "2026-10-18T14-00-00");
+    }
+
+    #[test]
+    fn test_rotation() {
+        let rotation = Rotation {
+            duration: Some(Duration::from_secs(600)),
+            bytes: Some(1000),
+        };
+        assert!(!rotation.should_rotate(Duration::from_secs(599), 999));
+        assert!(rotation.should_rotate(Duration::from_secs(600), 0));
+        assert!(rotation.should_rotate(Duration::ZERO, 1000));
+        assert!(!Rotation::default().should_rotate(Duration::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn test_retention() {
+        let now = UNIX_EPOCH + Duration::from_secs(10_000);
+        let closed = (0..4)
+            .map(|index| Segment {
+                path: PathBuf::from(format!("{}.caf", index)),
+                end: UNIX_EPOCH + Duration::from_secs(6_000 + index * 1_000),
+                size: 100,
+            })
+            .collect::<Vec<_>>();
+        // 结束超过 2500 秒的有 0、1
+        let by_age = Retention {
+            max_age: Some(Duration::from_secs(2_500)),
+            max_total: None,
+        };
+        assert_eq!(by_age.expired(&closed, 100, now), 2);
+        // 总大小 500，上限 250 时删除 3 个
+        let by_size = Retention {
+            max_age: None,
+            max_total: Some(250),
+        };
+        assert_eq!(by_size.expired(&closed, 100, now), 3);
+        // 正在写入的分段不会删除
+        assert_eq!(by_size.expired(&closed, 1_000, now), 4);
+        assert_eq!(Retention::default().expired(&closed, 100, now), 0);
+    }
+}
@@ -0,0 +1,187 @@
+//! single producer single consumer queue
+//! io 线程和录音线程之间传递数据，两端都不加锁，创建后不再分配内存
+//! 写满时 push 失败，由写入方决定丢弃哪些数据
+
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Shared<T> {
+    slots: Box<[UnsafeCell<T>]>,
+    // 已经读取的数量，只由 Consumer 修改
+    head: AtomicUsize,
+    // 已经写入的数量，只由 Producer 修改
+    tail: AtomicUsize,
+}
+
+// head 到 tail 之间的 slot 只由 Consumer 读取，其余只由 Producer 写入
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, position: usize) -> *mut T {
+        self.slots[position % self.slots.len()].get()
+    }
+}
+
+/// 写入端，只能在一个线程中使用
+pub struct Producer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// 读取端，只能在一个线程中使用
+pub struct Consumer<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// 创建最多保存 capacity 个元素的队列
+pub fn channel<T: Copy + Default>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let slots = (0..capacity.max(1))
+        .map(|_| UnsafeCell::new(T::default()))
+        .collect();
+    let shared = Arc::new(Shared {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            shared: shared.clone(),
+        },
+        Consumer { shared },
+    )
+}
+
+impl<T: Copy> Producer<T> {
+    pub fn capacity(&self) -> usize {
+        self.shared.capacity()
+    }
+
+    /// 还可以写入的数量
+    pub fn vacant(&self) -> usize {
+        let head = self.shared.head.load(Ordering::Acquire);
+        let tail = self.shared.tail.load(Ordering::Relaxed);
+        self.shared.capacity() - tail.wrapping_sub(head)
+    }
+
+    /// 写入一个元素，队列已满时返回 false
+    pub fn push(&mut self, item: T) -> bool {
+        self.push_slice(&[item])
+    }
+
+    /// 写入全部元素，空间不足时一个也不写入，返回 false
+    pub fn push_slice(&mut self, items: &[T]) -> bool {
+        if items.len() > self.vacant() {
+            return false;
+        }
+        let tail = self.shared.tail.load(Ordering::Relaxed);
+        for (offset, item) in items.iter().enumerate() {
+            // Consumer 读取到 tail 之前不会访问这些 slot
+            unsafe { *self.shared.slot(tail.wrapping_add(offset)) = *item };
+        }
+        self.shared
+            .tail
+            .store(tail.wrapping_add(items.len()), Ordering::Release);
+        true
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// 可以读取的数量
+    pub fn len(&self) -> usize {
+        let head = self.shared.head.load(Ordering::Relaxed);
+        let tail = self.shared.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 读取一个元素
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let head = self.shared.head.load(Ordering::Relaxed);
+        let item = unsafe { *self.shared.slot(head) };
+        self.shared
+            .head
+            .store(head.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    /// 读取最多 count 个元素，追加到 out，返回读取的数量
+    pub fn pop_into(&mut self, count: usize, out: &mut Vec<T>) -> usize {
+        let count = count.min(self.len());
+        let head = self.shared.head.load(Ordering::Relaxed);
+        out.reserve(count);
+        for offset in 0..count {
+            out.push(unsafe { *self.shared.slot(head.wrapping_add(offset)) });
+        }
+        self.shared
+            .head
+            .store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_push_pop() {
+        let (mut producer, mut consumer) = channel::<u32>(4);
+        assert_eq!(consumer.pop(), None);
+        assert!(producer.push_slice(&[1, 2, 3]));
+        // 空间不足时一个也不写入
+        assert!(!producer.push_slice(&[4, 5]));
+        assert_eq!(producer.vacant(), 1);
+        assert!(producer.push(4));
+        assert!(!producer.push(5));
+        assert_eq!(consumer.pop(), Some(1));
+        let mut out = Vec::new();
+        assert_eq!(consumer.pop_into(2, &mut out), 2);
+        assert_eq!(out, vec![2, 3]);
+        // 跨过末尾
+        assert!(producer.push_slice(&[5, 6, 7]));
+        assert_eq!(consumer.len(), 4);
+        out.clear();
+        assert_eq!(consumer.pop_into(10, &mut out), 4);
+        assert_eq!(out, vec![4, 5, 6, 7]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_threads() {
+        let (mut producer, mut consumer) = channel::<u64>(64);
+        let count = 100_000u64;
+        let thread = thread::spawn(move || {
+            let mut next = 0;
+            while next < count {
+                let items = [next, next + 1];
+                if producer.push_slice(&items) {
+                    next += 2;
+                } else {
+                    thread::yield_now();
+                }
+            }
+        });
+        let mut expected = 0;
+        let mut out = Vec::new();
+        while expected < count {
+            out.clear();
+            consumer.pop_into(16, &mut out);
+            for item in out.iter() {
+                assert_eq!(*item, expected);
+                expected += 1;
+            }
+        }
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+//! io proc timeline
+//! 用 AudioTimeStamp 中的 mSampleTime 检查相邻两次回调的数据是否连续
+//! 设备过载、唤醒等情况下会丢失数据，下一次回调的 mSampleTime 会跳过一段
+//! mHostTime 是第一帧的 host time，用于确定其它线程中某个时刻对应的帧
+
+use crate::AudioTimeStamp;
+
+// mSampleTime 可能有小数，小于一帧的误差认为是连续的
+const TOLERANCE_FRAMES: f64 = 1.0;
+
+/// 和上一次回调相比的不连续
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discontinuity {
+    /// 丢失的帧数
+    Gap(u64),
+    /// 和上一次回调重复的帧数
+    Overlap(u64),
+}
+
+/// 不连续的次数和帧数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimelineStats {
+    pub gaps: u64,
+    pub gap_frames: u64,
+    pub overlaps: u64,
+    pub overlap_frames: u64,
+}
+
+/// 记录下一次回调期望的 mSampleTime
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    expected: Option<f64>,
+    stats: TimelineStats,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline::default()
+    }
+
+    pub fn stats(&self) -> TimelineStats {
+        self.stats
+    }
+
+    /// 收到从 sample_time 开始的 frames 帧，返回和上一次回调之间的不连续
+    pub fn advance(&mut self, sample_time: f64, frames: usize) -> Option<Discontinuity> {
+        let expected = self.expected.replace(sample_time + frames as f64);
+        let delta = sample_time - expected?;
+        if delta >= TOLERANCE_FRAMES {
+            let frames = delta.round() as u64;
+            self.stats.gaps += 1;
+            self.stats.gap_frames += frames;
+            Some(Discontinuity::Gap(frames))
+        } else if delta <= -TOLERANCE_FRAMES {
+            let frames = (-delta).round() as u64;
+            self.stats.overlaps += 1;
+            self.stats.overlap_frames += frames;
+            Some(Discontinuity::Overlap(frames))
+        } else {
+            None
+        }
+    }
+
+    /// 同 advance，时间戳中的 mSampleTime 无效时不检查，下一次重新开始
+    pub fn advance_timestamp(
+        &mut self,
+        time: &AudioTimeStamp,
+        frames: usize,
+    ) -> Option<Discontinuity> {
+        if time.mFlags & coreaudio_sys::kAudioTimeStampSampleTimeValid == 0 {
+            self.expected = None;
+            return None;
+        }
+        self.advance(time.mSampleTime, frames)
+    }
+}
+
+/// 当前的 host time，和 AudioTimeStamp 中的 mHostTime 使用同一个时钟
+pub fn host_time_now() -> u64 {
+    unsafe { coreaudio_sys::AudioGetCurrentHostTime() }
+}
+
+/// 时间戳中的 mHostTime，无效时是 0
+pub fn host_time(time: &AudioTimeStamp) -> u64 {
+    if time.mFlags & coreaudio_sys::kAudioTimeStampHostTimeValid == 0 {
+        return 0;
+    }
+    time.mHostTime
+}
+
+/// host_time 之后 later 的秒数，later 在之前时是负数
+pub fn host_time_elapsed(host_time: u64, later: u64) -> f64 {
+    let nanos = |time| unsafe { coreaudio_sys::AudioConvertHostTimeToNanos(time) } as f64;
+    (nanos(later) - nanos(host_time)) / 1e9
+}
+
+/// 一次回调收到的 frames 帧中，第一帧之后 elapsed 秒的帧的位置
+/// 在第一帧之前时是 0，在这些帧之后时返回 None
+pub fn frame_at(elapsed: f64, sample_rate: f64, frames: usize) -> Option<usize> {
+    let frame = (elapsed * sample_rate).floor().max(0.0) as usize;
+    (frame < frames).then_some(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeline() {
+        let mut timeline = Timeline::new();
+        assert_eq!(timeline.advance(1000.0, 512), None);
+        assert_eq!(timeline.advance(1512.0, 512), None);
+        // 小于一帧的误差
+        assert_eq!(timeline.advance(2024.5, 512), None);
+        assert_eq!(timeline.advance(3048.5, 512), Some(Discontinuity::Gap(512)));
+        assert_eq!(
+            timeline.advance(3360.5, 512),
+            Some(Discontinuity::Overlap(200))
+        );
+        assert_eq!(
+            timeline.stats(),
+            TimelineStats {
+                gaps: 1,
+                gap_frames: 512,
+                overlaps: 1,
+                overlap_frames: 200,
+            }
+        );
+    }
+
+    #[test]
+    fn test_frame_at() {
+        assert_eq!(frame_at(0.0, 48000.0, 512), Some(0));
+        assert_eq!(frame_at(0.005, 48000.0, 512), Some(240));
+        // 在这次回调之前
+        assert_eq!(frame_at(-0.1, 48000.0, 512), Some(0));
+        // 在这次回调之后
+        assert_eq!(frame_at(512.0 / 48000.0, 48000.0, 512), None);
+    }
+}
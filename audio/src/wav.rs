@@ -0,0 +1,625 @@
+//! RIFF WAVE chunks
+//! 直接读写 wav 文件的 chunk，用于修改已经关闭的文件
+//! 文件头: "RIFF" + 大小(u32) + "WAVE"，之后是 chunk: 类型(4字节) + 大小(u32) + 数据，都是小端序
+//! 数据长度是奇数时后面补一个字节，不计入大小
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::aoerror::{AudioError, Result};
+use crate::caf::{Marker, Recovery};
+
+const FILE_HEADER_SIZE: u64 = 12;
+const CHUNK_HEADER_SIZE: u64 = 8;
+// bext 中 CodingHistory 之前的固定大小
+const BEXT_FIXED_SIZE: usize = 602;
+// cue chunk 中每个 cue point 的大小
+const CUE_POINT_SIZE: usize = 24;
+
+pub const CHUNK_FMT: [u8; 4] = *b"fmt ";
+pub const CHUNK_DATA: [u8; 4] = *b"data";
+pub const CHUNK_LIST: [u8; 4] = *b"LIST";
+pub const CHUNK_BEXT: [u8; 4] = *b"bext";
+pub const CHUNK_CUE: [u8; 4] = *b"cue ";
+pub const LIST_INFO: [u8; 4] = *b"INFO";
+pub const LIST_ADTL: [u8; 4] = *b"adtl";
+const ADTL_LABEL: [u8; 4] = *b"labl";
+
+/// 一个 chunk 的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunk {
+    pub kind: [u8; 4],
+    /// chunk 头的位置
+    pub offset: u64,
+    pub size: u32,
+}
+
+impl Chunk {
+    /// 数据的位置
+    pub fn data_offset(&self) -> u64 {
+        self.offset + CHUNK_HEADER_SIZE
+    }
+
+    /// 下一个 chunk 的位置，包括补齐的字节
+    pub fn end(&self) -> u64 {
+        self.data_offset() + self.size as u64 + (self.size & 1) as u64
+    }
+}
+
+/// BWF 的 bext chunk，字符串超出长度时被截断
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bext {
+    /// 最多 256 字节
+    pub description: String,
+    /// 最多 32 字节
+    pub originator: String,
+    /// 最多 32 字节
+    pub originator_reference: String,
+    /// yyyy-mm-dd
+    pub origination_date: String,
+    /// hh:mm:ss
+    pub origination_time: String,
+    /// 从当天 0 点开始的帧数
+    pub time_reference: u64,
+    pub coding_history: String,
+}
+
+/// 读取所有 chunk，最后一个 chunk 可能超出文件
+pub fn read_chunks<R: Read + Seek>(reader: &mut R) -> Result<Vec<Chunk>> {
+    let length = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; FILE_HEADER_SIZE as usize];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+        return Err(AudioError::with_msg("not a wav file"));
+    }
+    let mut chunks = Vec::new();
+    let mut offset = FILE_HEADER_SIZE;
+    while offset + CHUNK_HEADER_SIZE <= length {
+        let mut chunk_header = [0u8; CHUNK_HEADER_SIZE as usize];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut chunk_header)?;
+        let chunk = Chunk {
+            kind: [
+                chunk_header[0],
+                chunk_header[1],
+                chunk_header[2],
+                chunk_header[3],
+            ],
+            offset,
+            size: u32::from_le_bytes([
+                chunk_header[4],
+                chunk_header[5],
+                chunk_header[6],
+                chunk_header[7],
+            ]),
+        };
+        chunks.push(chunk);
+        offset = chunk.end();
+    }
+    Ok(chunks)
+}
+
+/// 只保留前 frames 帧音频数据，截断文件
+/// 只支持 data chunk 是最后一个 chunk 的文件
+pub fn truncate_frames<P: AsRef<Path>>(path: P, frames: u64, bytes_per_frame: u32) -> Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let chunks = read_chunks(&mut file)?;
+    let Some(data) = chunks.last().filter(|chunk| chunk.kind == CHUNK_DATA) else {
+        return Err(AudioError::with_msg("data chunk is not the last chunk"));
+    };
+    let size = frames * bytes_per_frame as u64;
+    if size >= data.size as u64 {
+        return Ok(());
+    }
+    write_data_size(&mut file, data, size)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// 修改 data chunk 的大小，截断之后的数据，同时修改 RIFF 的大小
+fn write_data_size(file: &mut fs::File, data: &Chunk, size: u64) -> Result<()> {
+    let end = data.data_offset() + size + (size & 1);
+    let riff_size =
+        u32::try_from(end - 8).map_err(|_| AudioError::with_msg("wav file is larger than 4GB"))?;
+    file.seek(SeekFrom::Start(data.offset + 4))?;
+    file.write_all(&(size as u32).to_le_bytes())?;
+    file.set_len(end)?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff_size.to_le_bytes())?;
+    Ok(())
+}
+
+/// 修复没有正常关闭的文件
+/// data chunk 的大小是 0 或超出文件时，文件剩余的部分都是音频数据，按完整的帧写入大小，删除不完整的帧；
+/// data chunk 之后不完整的 chunk 被删除，RIFF 的大小按实际的文件修改
+pub fn recover<P: AsRef<Path>>(path: P) -> Result<Recovery> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let length = file.metadata()?.len();
+    let chunks = read_chunks(&mut file)?;
+    let Some(fmt) = chunks.iter().find(|chunk| chunk.kind == CHUNK_FMT) else {
+        return Err(AudioError::with_msg("no fmt chunk"));
+    };
+    let Some(index) = chunks.iter().position(|chunk| chunk.kind == CHUNK_DATA) else {
+        return Err(AudioError::with_msg("no data chunk"));
+    };
+    let data = chunks[index];
+    // fmt: format + channels + 采样率 + 每秒字节数 + block align + ...
+    let mut block_align = [0u8; 2];
+    file.seek(SeekFrom::Start(fmt.data_offset() + 12))?;
+    file.read_exact(&mut block_align)?;
+    let block_align = u16::from_le_bytes(block_align) as u64;
+    if block_align == 0 {
+        return Err(AudioError::with_msg("block align is 0"));
+    }
+    let complete = |chunk: &Chunk| chunk.data_offset() + chunk.size as u64 <= length;
+    // 大小是 0 时，之后的 chunk 是正常关闭时追加的 LIST 等，否则是没有写入大小的音频数据
+    let unsized_data = data.size == 0
+        && length > data.data_offset()
+        && chunks.get(index + 1).is_none_or(|chunk| {
+            !chunk
+                .kind
+                .iter()
+                .all(|byte| byte.is_ascii_graphic() || *byte == b' ')
+        });
+    let (data_size, end) = if complete(&data) && !unsized_data {
+        // 音频数据完整，删除之后不完整的 chunk
+        let end = chunks[index..]
+            .iter()
+            .take_while(|chunk| complete(chunk))
+            .map(|chunk| chunk.end().min(length))
+            .last()
+            .unwrap_or(length);
+        (data.size as u64, end)
+    } else {
+        // 只保留完整的帧，wav 最大 4GB
+        let max_size = (u32::MAX as u64 - data.data_offset()) / block_align * block_align;
+        let size =
+            (length.saturating_sub(data.data_offset()) / block_align * block_align).min(max_size);
+        (size, data.data_offset() + size + (size & 1))
+    };
+    let mut riff_size = [0u8; 4];
+    file.seek(SeekFrom::Start(4))?;
+    file.read_exact(&mut riff_size)?;
+    let repaired = data_size != data.size as u64
+        || end != length
+        || u32::from_le_bytes(riff_size) as u64 != end - 8;
+    if repaired {
+        if data_size != data.size as u64 {
+            file.seek(SeekFrom::Start(data.offset + 4))?;
+            file.write_all(&(data_size as u32).to_le_bytes())?;
+        }
+        // 补齐的字节
+        file.set_len(end)?;
+        file.seek(SeekFrom::Start(4))?;
+        file.write_all(&((end - 8) as u32).to_le_bytes())?;
+        file.sync_all()?;
+    }
+    Ok(Recovery {
+        frames: data_size / block_align,
+        removed: length.saturating_sub(end),
+        repaired,
+    })
+}
+
+/// 在文件末尾写入 LIST/INFO，entries 是 INFO 中的 id 和文字，例如 ISFT、ICMT
+/// data chunk 的大小必须已经确定
+pub fn write_info<P: AsRef<Path>>(path: P, entries: &[([u8; 4], String)]) -> Result<()> {
+    let mut data = LIST_INFO.to_vec();
+    for (id, text) in entries {
+        // 以 0 结束的字符串
+        let mut bytes = text.bytes().filter(|byte| *byte != 0).collect::<Vec<_>>();
+        bytes.push(0);
+        push_chunk(&mut data, *id, &bytes);
+    }
+    let mut bytes = Vec::new();
+    push_chunk(&mut bytes, CHUNK_LIST, &data);
+    append_chunks(path, &bytes)
+}
+
+/// 读取 LIST/INFO 中的条目，没有时返回空
+pub fn read_info<P: AsRef<Path>>(path: P) -> Result<Vec<([u8; 4], String)>> {
+    let Some(data) = read_list(path, LIST_INFO)? else {
+        return Ok(Vec::new());
+    };
+    Ok(sub_chunks(&data)
+        .map(|(id, bytes)| {
+            let end = bytes
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(bytes.len());
+            (id, String::from_utf8_lossy(&bytes[..end]).into_owned())
+        })
+        .collect())
+}
+
+/// 在文件末尾写入 bext chunk
+pub fn write_bext<P: AsRef<Path>>(path: P, bext: &Bext) -> Result<()> {
+    let mut data = Vec::with_capacity(BEXT_FIXED_SIZE + bext.coding_history.len());
+    push_text(&mut data, &bext.description, 256);
+    push_text(&mut data, &bext.originator, 32);
+    push_text(&mut data, &bext.originator_reference, 32);
+    push_text(&mut data, &bext.origination_date, 10);
+    push_text(&mut data, &bext.origination_time, 8);
+    data.extend_from_slice(&bext.time_reference.to_le_bytes());
+    // version 1，UMID 和保留的字节都是 0
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.resize(BEXT_FIXED_SIZE, 0);
+    data.extend(bext.coding_history.bytes());
+    let mut bytes = Vec::new();
+    push_chunk(&mut bytes, CHUNK_BEXT, &data);
+    append_chunks(path, &bytes)
+}
+
+/// 读取 bext chunk
+pub fn read_bext<P: AsRef<Path>>(path: P) -> Result<Option<Bext>> {
+    let Some(data) = read_chunk(path, CHUNK_BEXT)? else {
+        return Ok(None);
+    };
+    if data.len() < BEXT_FIXED_SIZE {
+        return Err(AudioError::with_msg("bext chunk too small"));
+    }
+    let text = |start: usize, len: usize| {
+        let bytes = &data[start..start + len];
+        let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(len);
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+    let mut time_reference = [0u8; 8];
+    time_reference.copy_from_slice(&data[338..346]);
+    Ok(Some(Bext {
+        description: text(0, 256),
+        originator: text(256, 32),
+        originator_reference: text(288, 32),
+        origination_date: text(320, 10),
+        origination_time: text(330, 8),
+        time_reference: u64::from_le_bytes(time_reference),
+        coding_history: text(BEXT_FIXED_SIZE, data.len() - BEXT_FIXED_SIZE),
+    }))
+}
+
+/// 在文件末尾写入 cue chunk 和 LIST/adtl 中的名称
+/// cue point 的 id 从 1 开始，位置是帧数
+pub fn write_markers<P: AsRef<Path>>(path: P, markers: &[Marker]) -> Result<()> {
+    let mut cue = Vec::with_capacity(4 + markers.len() * CUE_POINT_SIZE);
+    cue.extend_from_slice(&(markers.len() as u32).to_le_bytes());
+    let mut adtl = LIST_ADTL.to_vec();
+    for (index, marker) in markers.iter().enumerate() {
+        let id = index as u32 + 1;
+        let frame = u32::try_from(marker.frame)
+            .map_err(|_| AudioError::with_msg("marker is out of wav range"))?;
+        cue.extend_from_slice(&id.to_le_bytes());
+        cue.extend_from_slice(&frame.to_le_bytes());
+        cue.extend_from_slice(&CHUNK_DATA);
+        // chunk start 和 block start 在没有 wavl 时都是 0
+        cue.extend_from_slice(&0u32.to_le_bytes());
+        cue.extend_from_slice(&0u32.to_le_bytes());
+        cue.extend_from_slice(&frame.to_le_bytes());
+        let mut label = id.to_le_bytes().to_vec();
+        label.extend(marker.label.bytes().filter(|byte| *byte != 0));
+        label.push(0);
+        push_chunk(&mut adtl, ADTL_LABEL, &label);
+    }
+    let mut bytes = Vec::new();
+    push_chunk(&mut bytes, CHUNK_CUE, &cue);
+    push_chunk(&mut bytes, CHUNK_LIST, &adtl);
+    append_chunks(path, &bytes)
+}
+
+/// 读取 cue chunk 中的标记，按 id 的顺序，没有名称时为空
+pub fn read_markers<P: AsRef<Path>>(path: P) -> Result<Vec<Marker>> {
+    let path = path.as_ref();
+    let Some(cue) = read_chunk(path, CHUNK_CUE)? else {
+        return Ok(Vec::new());
+    };
+    let u32_at = |bytes: &[u8], offset: usize| {
+        bytes
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let count = u32_at(&cue, 0).unwrap_or(0) as usize;
+    let mut points = (0..count)
+        .map_while(|index| {
+            let offset = 4 + index * CUE_POINT_SIZE;
+            Some((u32_at(&cue, offset)?, u32_at(&cue, offset + 20)?))
+        })
+        .collect::<Vec<_>>();
+    points.sort_by_key(|(id, _)| *id);
+    let labels = read_list(path, LIST_ADTL)?.unwrap_or_default();
+    let labels = sub_chunks(&labels)
+        .filter(|(kind, body)| *kind == ADTL_LABEL && body.len() >= 4)
+        .map(|(_, body)| {
+            let text = &body[4..];
+            let end = text
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(text.len());
+            (
+                u32_at(body, 0).unwrap_or(0),
+                String::from_utf8_lossy(&text[..end]).into_owned(),
+            )
+        })
+        .collect::<Vec<_>>();
+    Ok(points
+        .into_iter()
+        .map(|(id, frame)| Marker {
+            frame: frame as u64,
+            label: labels
+                .iter()
+                .find(|(label_id, _)| *label_id == id)
+                .map(|(_, label)| label.clone())
+                .unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// data chunk 之后的所有 chunk，原样返回
+/// 重新写入音频数据后，用 append_chunks 恢复 LIST、bext 等 chunk
+pub fn trailing_chunks<P: AsRef<Path>>(path: P) -> Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    let length = file.metadata()?.len();
+    let chunks = read_chunks(&mut file)?;
+    let Some(data) = chunks.iter().find(|chunk| chunk.kind == CHUNK_DATA) else {
+        return Err(AudioError::with_msg("no data chunk"));
+    };
+    let start = data.end().min(length);
+    let mut bytes = vec![0u8; (length - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// 在文件末尾追加完整的 chunk，同时修改 RIFF 的大小
+/// data chunk 的大小必须已经确定
+pub fn append_chunks<P: AsRef<Path>>(path: P, bytes: &[u8]) -> Result<()> {
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(path)?;
+    let length = file.metadata()?.len();
+    let chunks = read_chunks(&mut file)?;
+    let Some(data) = chunks.iter().find(|chunk| chunk.kind == CHUNK_DATA) else {
+        return Err(AudioError::with_msg("no data chunk"));
+    };
+    if data.data_offset() + data.size as u64 > length {
+        return Err(AudioError::with_msg("data chunk size is unknown"));
+    }
+    // 上一个 chunk 没有补齐时先补齐
+    let start = chunks.last().map(|chunk| chunk.end()).unwrap_or(length);
+    let end = start + bytes.len() as u64;
+    let riff_size =
+        u32::try_from(end - 8).map_err(|_| AudioError::with_msg("wav file is larger than 4GB"))?;
+    file.set_len(start)?;
+    file.seek(SeekFrom::Start(start))?;
+    file.write_all(bytes)?;
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&riff_size.to_le_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+// 追加一个 chunk，补齐到偶数长度
+pub(crate) fn push_chunk(bytes: &mut Vec<u8>, kind: [u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(&kind);
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        bytes.push(0);
+    }
+}
+
+// 固定长度的字符串，不足时补 0
+fn push_text(bytes: &mut Vec<u8>, text: &str, len: usize) {
+    let start = bytes.len();
+    bytes.extend(text.bytes().take(len));
+    bytes.resize(start + len, 0);
+}
+
+// LIST 中的子 chunk: id 和数据
+pub(crate) fn sub_chunks(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        if rest.len() < CHUNK_HEADER_SIZE as usize {
+            return None;
+        }
+        let id = [rest[0], rest[1], rest[2], rest[3]];
+        let size = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        let body = rest.get(8..8 + size)?;
+        rest = rest.get(8 + size + (size & 1)..).unwrap_or_default();
+        Some((id, body))
+    })
+}
+
+// 第一个 kind 类型的 chunk 的数据
+pub(crate) fn read_chunk<P: AsRef<Path>>(path: P, kind: [u8; 4]) -> Result<Option<Vec<u8>>> {
+    let mut file = fs::File::open(path)?;
+    let chunks = read_chunks(&mut file)?;
+    let Some(chunk) = chunks.iter().find(|chunk| chunk.kind == kind) else {
+        return Ok(None);
+    };
+    let mut data = vec![0u8; chunk.size as usize];
+    file.seek(SeekFrom::Start(chunk.data_offset()))?;
+    file.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+// 类型是 list_type 的 LIST chunk 中，list 类型之后的数据
+pub(crate) fn read_list<P: AsRef<Path>>(path: P, list_type: [u8; 4]) -> Result<Option<Vec<u8>>> {
+    let mut file = fs::File::open(path)?;
+    let chunks = read_chunks(&mut file)?;
+    for chunk in chunks.iter().filter(|chunk| chunk.kind == CHUNK_LIST) {
+        let mut data = vec![0u8; chunk.size as usize];
+        file.seek(SeekFrom::Start(chunk.data_offset()))?;
+        file.read_exact(&mut data)?;
+        if data.len() >= 4 && data[..4] == list_type {
+            return Ok(Some(data.split_off(4)));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 文件头 + fmt chunk + data chunk，data_size 可以和实际的数据不同
+    fn wav_bytes(data_size: u32, audio: &[u8]) -> Vec<u8> {
+        let mut fmt = Vec::new();
+        // PCM，2 声道，48000 Hz，16 位
+        fmt.extend_from_slice(&1u16.to_le_bytes());
+        fmt.extend_from_slice(&2u16.to_le_bytes());
+        fmt.extend_from_slice(&48000u32.to_le_bytes());
+        fmt.extend_from_slice(&(48000u32 * 4).to_le_bytes());
+        fmt.extend_from_slice(&4u16.to_le_bytes());
+        fmt.extend_from_slice(&16u16.to_le_bytes());
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(b"WAVE");
+        push_chunk(&mut bytes, CHUNK_FMT, &fmt);
+        bytes.extend_from_slice(&CHUNK_DATA);
+        bytes.extend_from_slice(&data_size.to_le_bytes());
+        bytes.extend_from_slice(audio);
+        let riff_size = (bytes.len() - 8) as u32;
+        bytes[4..8].copy_from_slice(&riff_size.to_le_bytes());
+        bytes
+    }
+
+    fn riff_size(bytes: &[u8]) -> u32 {
+        u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]])
+    }
+
+    #[test]
+    fn test_read_chunks() {
+        let bytes = wav_bytes(4, &[1, 2, 3, 4]);
+        let chunks = read_chunks(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].kind, CHUNK_FMT);
+        assert_eq!(chunks[1].kind, CHUNK_DATA);
+        assert_eq!(chunks[1].data_offset(), 44);
+        assert!(read_chunks(&mut std::io::Cursor::new(b"caff0000")).is_err());
+    }
+
+    #[test]
+    fn test_truncate_frames() {
+        let path = std::env::temp_dir().join(format!("resound-wav-{}.wav", std::process::id()));
+        let audio = (0u8..16).collect::<Vec<_>>();
+        fs::write(&path, wav_bytes(16, &audio)).unwrap();
+        truncate_frames(&path, 3, 4).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 44 + 12);
+        assert_eq!(riff_size(&bytes), 44 + 12 - 8);
+        assert_eq!(&bytes[44..], &audio[..12]);
+        let chunks = read_chunks(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(chunks[1].size, 12);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_info_and_bext() {
+        let path =
+            std::env::temp_dir().join(format!("resound-wav-info-{}.wav", std::process::id()));
+        // 奇数长度的 data chunk，追加时先补齐
+        fs::write(&path, wav_bytes(3, &[1, 2, 3])).unwrap();
+        let entries = vec![
+            (*b"ISFT", "resound 0.1.0".to_string()),
+            (*b"ICMT", "source bundle: com.example".to_string()),
+        ];
+        write_info(&path, &entries).unwrap();
+        let bext = Bext {
+            description: "capture".to_string(),
+            originator: "resound".to_string(),
+            originator_reference: String::new(),
+            origination_date: "2026-10-18".to_string(),
+            origination_time: "14:00:00".to_string(),
+            time_reference: 48000 * 3600 * 14,
+            coding_history: "A=PCM,F=48000,W=16,M=stereo,T=resound\r\n".to_string(),
+        };
+        write_bext(&path, &bext).unwrap();
+        assert_eq!(read_info(&path).unwrap(), entries);
+        assert_eq!(read_bext(&path).unwrap(), Some(bext));
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(riff_size(&bytes) as usize, bytes.len() - 8);
+        let chunks = read_chunks(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.kind).collect::<Vec<_>>(),
+            vec![CHUNK_FMT, CHUNK_DATA, CHUNK_LIST, CHUNK_BEXT]
+        );
+        // 重新写入音频数据后恢复
+        let trailing = trailing_chunks(&path).unwrap();
+        fs::write(&path, wav_bytes(4, &[1, 2, 3, 4])).unwrap();
+        append_chunks(&path, &trailing).unwrap();
+        assert_eq!(read_info(&path).unwrap(), entries);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_recover() {
+        let path =
+            std::env::temp_dir().join(format!("resound-wav-recover-{}.wav", std::process::id()));
+        // 没有写入大小，最后一帧不完整
+        fs::write(&path, wav_bytes(0, &[1u8; 10])).unwrap();
+        assert_eq!(
+            recover(&path).unwrap(),
+            Recovery {
+                frames: 2,
+                removed: 2,
+                repaired: true,
+            }
+        );
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 52);
+        let chunks = read_chunks(&mut std::io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(chunks[1].size, 8);
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 44);
+        // 已经完整的文件不修改
+        assert!(!recover(&path).unwrap().repaired);
+        // 写入 LIST 时中断
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&CHUNK_LIST).unwrap();
+        file.write_all(&100u32.to_le_bytes()).unwrap();
+        file.write_all(&[0u8; 5]).unwrap();
+        drop(file);
+        assert_eq!(recover(&path).unwrap().removed, 13);
+        assert_eq!(fs::read(&path).unwrap().len(), 52);
+        // 正常关闭的空文件
+        fs::write(&path, wav_bytes(0, &[])).unwrap();
+        write_info(&path, &[(*b"ICMT", "comment".to_string())]).unwrap();
+        assert_eq!(
+            recover(&path).unwrap(),
+            Recovery {
+                frames: 0,
+                removed: 0,
+                repaired: false,
+            }
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_markers() {
+        let path =
+            std::env::temp_dir().join(format!("resound-wav-markers-{}.wav", std::process::id()));
+        fs::write(&path, wav_bytes(16, &[0u8; 16])).unwrap();
+        assert!(read_markers(&path).unwrap().is_empty());
+        let markers = vec![
+            Marker {
+                frame: 0,
+                label: "start".to_string(),
+            },
+            Marker {
+                frame: 3,
+                label: "中文".to_string(),
+            },
+        ];
+        write_markers(&path, &markers).unwrap();
+        write_info(&path, &[(*b"ICMT", "comment".to_string())]).unwrap();
+        assert_eq!(read_markers(&path).unwrap(), markers);
+        assert_eq!(
+            read_info(&path).unwrap(),
+            vec![(*b"ICMT", "comment".to_string())]
+        );
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(
+            u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize,
+            bytes.len() - 8
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
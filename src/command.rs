@@ -1,17 +1,63 @@
 //! analyse command, run operate
 
 use std::borrow::Cow;
+use std::fs;
 
 use tokio::sync::{mpsc, oneshot};
 
-use crate::interactive::{self, PROMPT_DEFAULT_COW, PROMPT_ERR_COMMAND_COW};
+use crate::interactive::{self, LineReader, ListOptions, OutputFormat, PROMPT_DEFAULT_COW};
+use crate::rserror::{Result, RsError};
 
+mod analyze;
+pub(super) mod control;
+mod daemon;
 mod process;
 mod re;
+mod recover;
+mod registry;
+mod stream;
+
+use registry::{Arg, Args, Command, Complete, Kind, ParseError, Parsed};
 
 const TAP_NAME_DEFAULT: &str = "resoundTap";
 
+// 交互式的所有命令，quit 由 run 处理
+const COMMANDS: &[Command] = &[
+    Command::new("quit", "quit resound"),
+    Command::new(
+        "output",
+        "show or set the default output format of lists, --json and --csv override it",
+    )
+    .args(&[Arg::positional("format", Kind::Text)
+        .optional()
+        .choices(&["text", "json", "csv"])])
+    .run(output),
+    process::COMMAND,
+    re::COMMAND,
+    stream::COMMAND,
+    analyze::COMMAND,
+    recover::COMMAND,
+];
+// 只能在命令行中使用的命令
+const ARGS_COMMANDS: &[Command] = &[re::RECORD, daemon::COMMAND];
+// 输出列表的命令的选项
+const LIST_OPTIONS: &[Arg] = &[
+    Arg::option("--sort", Kind::Text, "column"),
+    Arg::flag("--reverse"),
+    Arg::option("--filter", Kind::Text, "text"),
+    Arg::flag("--json"),
+    Arg::flag("--csv"),
+];
+
 pub(super) fn wait_command(tx: mpsc::Sender<(String, oneshot::Sender<()>)>) {
+    let mut reader = match LineReader::new(complete) {
+        Ok(reader) => reader,
+        Err(error) => {
+            eprintln!("init line editor fail: {}", error);
+            let _ = tx.blocking_send(("quit".to_string(), oneshot::channel().0));
+            return;
+        }
+    };
     loop {
          unsafe {
             // 调用 termios.h 中的 tcflush 函数。
@@ -19,11 +65,9 @@ pub(super) fn wait_command(tx: mpsc::Sender<(String, oneshot::Sender<()>)>) {
             // libc::TCIFLUSH 告诉函数清空输入缓冲区。
             libc::tcflush(libc::STDIN_FILENO, libc::TCIFLUSH);
         }
-        let mut command = String::new();
-        if let Err(error) = std::io::stdin().read_line(&mut command) {
-            eprintln!("read command fail: {}", error);
-        }
-        
+        // ctrl + c、ctrl + d 时退出
+        let command = reader.read_line().unwrap_or_else(|| "quit".to_string());
+
         let (callback_tx, callback_rx) = oneshot::channel();
         // send fail, It indicates that the consumer has closed（That means the consumer is closed）
         if let Err(_) = tx.blocking_send((command, callback_tx)) {
@@ -35,49 +79,225 @@ pub(super) fn wait_command(tx: mpsc::Sender<(String, oneshot::Sender<()>)>) {
     }
 }
 
+/// 命令行模式，执行 args 中的一条命令后退出，返回进程的退出码
+/// 除 ctl、record、daemon 之外，命令和交互式相同，例如 resound analyze file.caf
+pub(super) async fn run_args(args: &[String]) -> i32 {
+    let tokens = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let code = match tokens.first() {
+        Some(&("-h" | "--help")) => {
+            print_args_help();
+            0
+        }
+        Some(&"ctl") => control::ctl(&args[1..]).await,
+        Some(&("record" | "daemon")) => match registry::parse(ARGS_COMMANDS, &tokens) {
+            Ok(Parsed::Run(command, args)) => {
+                let result = match command.name {
+                    "record" => re::record(&args).await,
+                    _ => daemon::run(&args).await,
+                };
+                match result {
+                    Ok(()) => 0,
+                    Err(error) => {
+                        eprintln!("{}", error);
+                        1
+                    }
+                }
+            }
+            Ok(Parsed::Help(commands, prefix)) => {
+                registry::print_help(commands, &prefix);
+                0
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                2
+            }
+        },
+        // 提示信息输出到 stdout，错误输出到 stderr
+        _ => match execute(&tokens) {
+            Ok(prompt) if prompt == PROMPT_DEFAULT_COW => 0,
+            Ok(prompt) => {
+                println!("{}", prompt);
+                0
+            }
+            Err(error) => match error.downcast_ref::<ParseError>() {
+                Some(error @ ParseError::Unknown(_)) => {
+                    eprintln!("{}, see resound --help", error);
+                    2
+                }
+                Some(error) => {
+                    eprintln!("{}", error);
+                    2
+                }
+                None => {
+                    eprintln!("{}", error);
+                    1
+                }
+            },
+        },
+    };
+    // 退出时不会执行 drop，停止命令开始的录音，清理 tap 等
+    re::shutdown();
+    code
+}
+
+// 解析并执行一条命令，返回提示信息
+// 命令和参数错误时返回 ParseError，执行失败时返回命令的错误
+fn execute(tokens: &[&str]) -> Result<Cow<'static, str>> {
+    match registry::parse(COMMANDS, tokens)? {
+        Parsed::Run(command, args) => match command.run {
+            Some(run) => run(&args),
+            None => Err(ParseError::Unknown(tokens.join(" ")).into()),
+        },
+        Parsed::Help(commands, prefix) => {
+            if prefix.is_empty() {
+                interactive::print_line(
+                    "if you want to view details for command, please use \"command help\"",
+                );
+            }
+            registry::print_help(commands, &prefix);
+            Ok(PROMPT_DEFAULT_COW)
+        }
+    }
+}
+
 // start
 pub(super) async fn run(mut rx: mpsc::Receiver<(String, oneshot::Sender<()>)>) {
     // let mut command;
     // let mut prompt = PROMPT_DEFAULT_COW;
     while let Some((command, collback_tx)) = rx.recv().await {
         // command = interactive::wait_command(&prompt);
-        let mut command_iter = command.split_whitespace();
-        let command_iter = &mut command_iter;
-        let token = command_iter.next();
-        let prompt = match token {
-            Some("help") => help(),
+        let prompt = match command.split_whitespace().next() {
             // 友好的退出
             // todo 监听 ctrl + c、kill等，在退出时执行相同的处理
             // todo 关闭正在执行的录音对象，清理tap等内容
             // 测试kill、ctrl + c 、painc 等场景下，结构体的drop方法是否会执行
             // painc 回执行drop方法，其它场景不会
             Some("quit") => {
+                re::shutdown();
                 break;
             }
-            Some("process") => process::run_command(command_iter),
-            // 录音相关
-            Some("re") => re::run_command(command_iter),
-            _ => PROMPT_ERR_COMMAND_COW,
+            // 开始录音、re meter 等命令会阻塞，不能占用 async 线程，否则 control socket 也无法响应
+            _ => tokio::task::spawn_blocking(move || {
+                execute(&command.split_whitespace().collect::<Vec<_>>())
+                    .unwrap_or_else(|error| Cow::from(error.to_string()))
+            })
+            .await
+            .unwrap_or_else(|error| Cow::from(error.to_string())),
         };
         interactive::print_line(&prompt);
         let _ = collback_tx.send(());
     }
 }
 
-fn help() -> Cow<'static, str> {
-    interactive::print_line(
-        "if you want to view details for command, please use \"command help\"",
-    );
-    interactive::print_list(&HELP_CONTENT);
-    PROMPT_DEFAULT_COW
+// 设置默认的输出格式，没有参数时显示当前的格式
+fn output(args: &Args) -> Result<Cow<'static, str>> {
+    match args.text("format").map(str::parse::<OutputFormat>) {
+        Some(Ok(format)) => {
+            interactive::set_output_format(format);
+            Ok(Cow::from(format!("output format: {}", format.as_str())))
+        }
+        Some(Err(error)) => Err(RsError::with_msg(error).into()),
+        None => Ok(Cow::from(format!(
+            "output format: {}",
+            interactive::output_format().as_str()
+        ))),
+    }
 }
 
-const HELP_CONTENT: [[(Cow<'_, str>, Cow<'_, str>); 1]; 4] = [
-    [(Cow::Borrowed("help"), Cow::Borrowed("show this"))],
-    [(Cow::Borrowed("quit"), Cow::Borrowed("quit resound"))],
-    [(
-        Cow::Borrowed("process"),
-        Cow::Borrowed("I think process is one with suppoer audio"),
-    )],
-    [(Cow::Borrowed("re"), Cow::Borrowed("record sound"))],
-];
+// LIST_OPTIONS 指定的输出方式，没有指定格式时使用默认的格式
+fn list_options(args: &Args) -> ListOptions<'_> {
+    let format = if args.flag("--json") {
+        OutputFormat::Json
+    } else if args.flag("--csv") {
+        OutputFormat::Csv
+    } else {
+        interactive::output_format()
+    };
+    ListOptions {
+        format,
+        sort: args.text("--sort"),
+        reverse: args.flag("--reverse"),
+        filter: args.text("--filter"),
+    }
+}
+
+// 补全交互式输入的最后一个词
+fn complete(line: &str) -> Vec<String> {
+    registry::complete(COMMANDS, line, complete_value)
+}
+
+// 参数值的补全，registry::complete 会按前缀过滤
+fn complete_value(complete: Complete, prefix: &str) -> Vec<String> {
+    match complete {
+        Complete::None => Vec::new(),
+        Complete::Process => audio::process::list()
+            .map(|list| {
+                list.iter()
+                    .map(|process| process.get_id().to_string())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        Complete::Bundle => audio::process::list()
+            .map(|list| {
+                list.iter()
+                    .filter_map(|process| process.get_bundle_id().ok().cloned())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        Complete::File => complete_file(prefix),
+    }
+}
+
+// prefix 所在目录中的文件，目录以 / 结束
+fn complete_file(prefix: &str) -> Vec<String> {
+    let dir = prefix
+        .rfind('/')
+        .map(|index| &prefix[..=index])
+        .unwrap_or_default();
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let mut path = format!("{}{}", dir, entry.file_name().to_string_lossy());
+            if entry.path().is_dir() {
+                path.push('/');
+            }
+            path
+        })
+        .collect()
+}
+
+// 命令行模式的帮助
+fn print_args_help() {
+    let mut list = vec![
+        [(
+            Cow::Borrowed("resound"),
+            Cow::Borrowed("start interactive mode"),
+        )],
+        [(
+            Cow::Borrowed("resound command"),
+            Cow::Borrowed(
+                "run one interactive command and exit, e.g. resound process listall, resound analyze file.caf",
+            ),
+        )],
+    ];
+    list.push([(
+        Cow::Borrowed("resound ctl"),
+        Cow::Borrowed(
+            "send start, stop, status, mark or list to the running resound through the control socket, e.g. resound ctl mark intro",
+        ),
+    )]);
+    for command in ARGS_COMMANDS {
+        list.push([(
+            Cow::from(format!("resound {}", command.name)),
+            Cow::from(format!(
+                "{}. usage: {}",
+                command.help,
+                command.usage(&["resound"])
+            )),
+        )]);
+    }
+    interactive::print_list(&list);
+}
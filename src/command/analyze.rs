@@ -0,0 +1,165 @@
+//! analyze audio file
+
+use std::borrow::Cow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use audio::{
+    caf,
+    dsp::loudness::{LoudnessMeter, LoudnessSummary},
+    ext_audio_file::{AudioExtAudioFile, FileType},
+    flac,
+    format::{SampleType, StreamFormat},
+    wav,
+};
+
+use crate::interactive::{PROMPT_DEFAULT_COW, print_list};
+use crate::rserror::{Result, RsError};
+
+use super::registry::{Arg, Args, Command, Complete, Kind};
+
+// 每次读取的帧数
+const READ_FRAMES: usize = 4096;
+
+pub(super) const COMMAND: Command = Command::new(
+    "analyze",
+    "measure EBU R128 loudness and true peak of audio file",
+)
+.args(&[Arg::positional("file", Kind::Text).complete(Complete::File)])
+.run(run);
+
+fn run(args: &Args) -> Result<Cow<'static, str>> {
+    let Some(path) = args.text("file") else {
+        return Err(RsError::with_msg("缺少参数: file").into());
+    };
+    print_summary(path, &measure_file(path)?);
+    Ok(PROMPT_DEFAULT_COW)
+}
+
+fn print_summary(path: &str, summary: &LoudnessSummary) {
+    let list = [
+        [(Cow::Borrowed("file"), Cow::Borrowed(path))],
+        [(
+            Cow::Borrowed("integrated"),
+            Cow::from(format!("{:.1} LUFS", summary.integrated)),
+        )],
+        [(
+            Cow::Borrowed("momentary max"),
+            Cow::from(format!("{:.1} LUFS", summary.momentary_max)),
+        )],
+        [(
+            Cow::Borrowed("short-term max"),
+            Cow::from(format!("{:.1} LUFS", summary.short_term_max)),
+        )],
+        [(
+            Cow::Borrowed("true peak"),
+            Cow::from(format!("{:.1} dBTP", summary.true_peak)),
+        )],
+        [(
+            Cow::Borrowed("sample peak"),
+            Cow::from(format!("{:.1} dBFS", summary.sample_peak)),
+        )],
+    ];
+    print_list(&list);
+}
+
+// 打开文件，读取的格式设置为交错排列的 f32
+fn open_f32<P: AsRef<Path>>(path: P) -> Result<(AudioExtAudioFile, StreamFormat)> {
+    let mut audio_ext_file = AudioExtAudioFile::open(path)?;
+    let file_format = StreamFormat::from(&audio_ext_file.file_format()?);
+    let client_format = StreamFormat::linear_pcm(
+        file_format.sample_rate,
+        SampleType::Float,
+        32,
+        file_format.channels,
+        true,
+    );
+    audio_ext_file.set_client_format(&client_format.to_basic_description())?;
+    Ok((audio_ext_file, client_format))
+}
+
+// 依次读取文件的所有数据
+fn for_each_block<F>(
+    audio_ext_file: &mut AudioExtAudioFile,
+    channels: usize,
+    mut f: F,
+) -> Result<()>
+where
+    F: FnMut(&mut [f32]) -> Result<()>,
+{
+    let mut data = vec![0u8; READ_FRAMES * channels * 4];
+    let mut samples = Vec::with_capacity(READ_FRAMES * channels);
+    loop {
+        let frames = audio_ext_file.read_interleaved(&mut data)?;
+        if frames == 0 {
+            return Ok(());
+        }
+        samples.clear();
+        samples.extend(
+            data[..frames * channels * 4]
+                .chunks_exact(4)
+                .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        );
+        f(&mut samples)?;
+    }
+}
+
+/// 测量文件的响度
+pub(super) fn measure_file<P: AsRef<Path>>(path: P) -> Result<LoudnessSummary> {
+    let (mut audio_ext_file, format) = open_f32(path)?;
+    let channels = format.channels as usize;
+    let mut meter = LoudnessMeter::new(channels, format.sample_rate)?;
+    for_each_block(&mut audio_ext_file, channels, |samples| {
+        meter.add(samples);
+        Ok(())
+    })?;
+    Ok(meter.summary())
+}
+
+/// 文件的所有采样乘以增益，dB
+/// 先写入临时文件，完成后替换原文件，文件格式和元数据不变
+/// caf、wav 保留 data chunk 之后的 info、mark、LIST、bext 等 chunk，flac 保留 Vorbis comment
+pub(super) fn apply_gain<P: AsRef<Path>>(path: P, gain_db: f64) -> Result<()> {
+    let path = path.as_ref();
+    let gain = 10f64.powf(gain_db / 20.0) as f32;
+    let (mut reader, format) = open_f32(path)?;
+    let channels = format.channels as usize;
+    let file_type = FileType::from_path(path).unwrap_or_default();
+    let (trailing, comment) = match file_type {
+        FileType::Caf => (caf::trailing_chunks(path)?, Vec::new()),
+        FileType::Wav => (wav::trailing_chunks(path)?, Vec::new()),
+        FileType::Flac => (Vec::new(), flac::read_vorbis_comment(path)?),
+    };
+    let mut tmp_path = PathBuf::from(path);
+    tmp_path.set_extension(format!("normalize.{}", file_type.extension()));
+    let result = (|| {
+        let mut writer =
+            AudioExtAudioFile::create_with_type(&tmp_path, file_type, &reader.file_format()?)?;
+        writer.set_client_format(&format.to_basic_description())?;
+        let mut data = Vec::new();
+        for_each_block(&mut reader, channels, |samples| {
+            data.clear();
+            samples.iter().for_each(|sample| {
+                data.extend_from_slice(&(sample * gain).clamp(-1.0, 1.0).to_ne_bytes())
+            });
+            writer.write_interleaved(&data)?;
+            Ok(())
+        })
+    })();
+    // 关闭文件后再替换
+    drop(reader);
+    if let Err(error) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(
+            RsError::with_msg(format!("normalize {} fail: {}", path.display(), error)).into(),
+        );
+    }
+    fs::rename(&tmp_path, path)?;
+    match file_type {
+        _ if trailing.is_empty() && comment.is_empty() => {}
+        FileType::Caf => caf::append_chunks(path, &trailing)?,
+        FileType::Wav => wav::append_chunks(path, &trailing)?,
+        FileType::Flac => flac::write_vorbis_comment(path, "resound", &comment)?,
+    }
+    Ok(())
+}
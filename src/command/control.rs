@@ -0,0 +1,490 @@
+//! control socket
+//! 其它程序（快捷键、编辑器插件等）通过 unix domain socket 控制正在运行的 resound
+//!
+//! 每行一个 json 请求，args 和对应的交互式命令相同，例如
+//! {"command": "start", "args": ["123", "--format", "s16"]}
+//! 每个请求返回一行 json
+//! {"ok": true, "message": "..."}、{"ok": true, "data": [...]} 或 {"ok": false, "error": "..."}
+
+use std::fs::{File, Permissions, TryLockError};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::interactive::{self, Record};
+use crate::rserror::{Result, RsError};
+
+use super::registry::{self, Args, Parsed};
+use super::{COMMANDS, process, re};
+
+const SOCKET_NAME: &str = "control.sock";
+
+/// 控制命令，用 command 的定义解析参数
+struct ControlCommand {
+    name: &'static str,
+    command: &'static [&'static str],
+    run: fn(&Args) -> Result<Response>,
+}
+
+const CONTROL_COMMANDS: &[ControlCommand] = &[
+    ControlCommand {
+        name: "start",
+        command: &["re", "start"],
+        run: start,
+    },
+    ControlCommand {
+        name: "stop",
+        command: &["re", "stop"],
+        run: stop,
+    },
+    ControlCommand {
+        name: "status",
+        command: &["re", "status"],
+        run: status,
+    },
+    ControlCommand {
+        name: "mark",
+        command: &["re", "mark"],
+        run: mark,
+    },
+    ControlCommand {
+        name: "list",
+        command: &["process", "listall"],
+        run: list,
+    },
+];
+
+/// 一行请求
+#[derive(Debug, Serialize, Deserialize)]
+struct Request {
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// 一行响应，message 和 data 最多有一个
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Response {
+    ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Response {
+    fn message<T: Into<String>>(message: T) -> Response {
+        Response {
+            ok: true,
+            message: Some(message.into()),
+            ..Default::default()
+        }
+    }
+
+    fn data(data: serde_json::Value) -> Response {
+        Response {
+            ok: true,
+            data: Some(data),
+            ..Default::default()
+        }
+    }
+
+    fn error<T: ToString>(error: T) -> Response {
+        Response {
+            ok: false,
+            error: Some(error.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+fn socket_path() -> Result<PathBuf> {
+    interactive::data_dir()
+        .map(|dir| dir.join(SOCKET_NAME))
+        .ok_or_else(|| RsError::with_msg("没有找到 HOME 目录").into())
+}
+
+/// 交互式运行时监听 control socket，失败时只输出错误
+pub(crate) async fn serve() {
+    let result = match bind().await {
+        Ok(socket) => socket.serve().await,
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        eprintln!("control socket fail: {}", error);
+    }
+}
+
+/// 创建 control socket，有其它 resound 正在监听时返回错误
+pub(crate) async fn bind() -> Result<ControlSocket> {
+    bind_at(socket_path()?, CONTROL_COMMANDS).await
+}
+
+// 在 path 上监听，处理 controls 中的命令
+// 监听期间持有 path 旁边的锁文件，拿到锁之后 path 上的 socket 文件只能是上次异常退出时留下的
+async fn bind_at(path: PathBuf, controls: &'static [ControlCommand]) -> Result<ControlSocket> {
+    let lock = File::create(path.with_extension("lock"))?;
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            return Err(RsError::with_msg(format!(
+                "{} is used by another resound",
+                path.display()
+            ))
+            .into());
+        }
+        Err(TryLockError::Error(error)) => return Err(error.into()),
+    }
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    // 只允许当前用户连接
+    std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+    Ok(ControlSocket {
+        listener,
+        path,
+        controls,
+        _lock: lock,
+    })
+}
+
+/// 正在监听的 control socket，drop 时删除 socket 文件，然后释放锁
+pub(crate) struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    controls: &'static [ControlCommand],
+    _lock: File,
+}
+
+impl ControlSocket {
+    /// 处理连接，直到 task 被取消
+    pub(crate) async fn serve(&self) -> Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept().await?;
+            let controls = self.controls;
+            tokio::spawn(async move {
+                if let Err(error) = handle(stream, controls).await {
+                    eprintln!("control connection fail: {}", error);
+                }
+            });
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// 一个连接可以发送多个请求，按顺序返回
+async fn handle(stream: UnixStream, controls: &'static [ControlCommand]) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            // 开始和停止录音会阻塞
+            Ok(request) => tokio::task::spawn_blocking(move || execute(controls, &request))
+                .await
+                .unwrap_or_else(Response::error),
+            Err(error) => Response::error(format!("invalid request: {}", error)),
+        };
+        let mut text = serde_json::to_string(&response)?;
+        text.push('\n');
+        writer.write_all(text.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+fn execute(controls: &[ControlCommand], request: &Request) -> Response {
+    let Some(control) = controls
+        .iter()
+        .find(|control| control.name == request.command)
+    else {
+        return Response::error(format!(
+            "unknown command: {}, supported: {}",
+            request.command,
+            command_names(controls)
+        ));
+    };
+    let tokens = control
+        .command
+        .iter()
+        .copied()
+        .chain(request.args.iter().map(String::as_str))
+        .collect::<Vec<_>>();
+    match registry::parse(COMMANDS, &tokens) {
+        Ok(Parsed::Run(_, args)) => (control.run)(&args).unwrap_or_else(Response::error),
+        // 参数和交互式命令相同，返回交互式命令的用法
+        Ok(Parsed::Help(commands, prefix)) => Response::error(
+            commands
+                .iter()
+                .map(|command| format!("usage: {}", command.usage(&prefix)))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ),
+        Err(error) => Response::error(error),
+    }
+}
+
+fn command_names(controls: &[ControlCommand]) -> String {
+    controls
+        .iter()
+        .map(|control| control.name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn start(args: &Args) -> Result<Response> {
+    re::start_record(args)?;
+    Ok(Response::message("start record sound"))
+}
+
+fn stop(_: &Args) -> Result<Response> {
+    match re::stop_record()? {
+        true => Ok(Response::message("stop record sound")),
+        false => Ok(Response::message("not recording")),
+    }
+}
+
+fn status(args: &Args) -> Result<Response> {
+    records(re::status_records(), args)
+}
+
+fn mark(args: &Args) -> Result<Response> {
+    let label = re::add_mark(args.text("label").map(str::to_string))?;
+    Ok(Response::message(format!("marked \"{}\"", label)))
+}
+
+fn list(args: &Args) -> Result<Response> {
+    records(process::process_records()?, args)
+}
+
+// --sort、--filter 和交互式命令相同，总是返回 json
+fn records(mut data: Vec<Record<'_>>, args: &Args) -> Result<Response> {
+    interactive::select_records(&mut data, &super::list_options(args))
+        .map_err(RsError::with_msg)?;
+    Ok(Response::data(interactive::records_json(&data)))
+}
+
+/// resound ctl command [args]，把一个命令发送给正在运行的 resound，返回进程的退出码
+/// message 和 data 输出到 stdout，error 输出到 stderr
+pub(crate) async fn ctl(args: &[String]) -> i32 {
+    let Some((command, args)) = args.split_first() else {
+        eprintln!(
+            "usage: resound ctl {} [args]",
+            command_names(CONTROL_COMMANDS).replace(", ", "|")
+        );
+        return 2;
+    };
+    let request = Request {
+        command: command.clone(),
+        args: args.to_vec(),
+    };
+    let result = match socket_path() {
+        Ok(path) => send(&path, &request).await,
+        Err(error) => Err(error),
+    };
+    match result {
+        Ok(Response {
+            ok: true,
+            message,
+            data,
+            ..
+        }) => {
+            if let Some(message) = message {
+                println!("{}", message);
+            }
+            if let Some(data) = data {
+                println!("{}", data);
+            }
+            0
+        }
+        Ok(Response { error, .. }) => {
+            eprintln!("{}", error.unwrap_or_default());
+            1
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    }
+}
+
+async fn send(path: &Path, request: &Request) -> Result<Response> {
+    let stream = UnixStream::connect(path).await.map_err(|error| {
+        RsError::with_msg(format!(
+            "connect {} fail: {}, please start resound first",
+            path.display(),
+            error
+        ))
+    })?;
+    let (reader, mut writer) = stream.into_split();
+    let mut text = serde_json::to_string(request)?;
+    text.push('\n');
+    writer.write_all(text.as_bytes()).await?;
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| RsError::with_msg("resound closed the connection"))?;
+    Ok(serde_json::from_str(&line)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use serde_json::json;
+
+    use super::*;
+
+    // list 使用固定的进程，结果不依赖正在运行的程序
+    const TEST_COMMANDS: &[ControlCommand] = &[
+        ControlCommand {
+            name: "status",
+            command: &["re", "status"],
+            run: status,
+        },
+        ControlCommand {
+            name: "mark",
+            command: &["re", "mark"],
+            run: mark,
+        },
+        ControlCommand {
+            name: "list",
+            command: &["process", "listall"],
+            run: test_list,
+        },
+    ];
+
+    fn test_list(args: &Args) -> Result<Response> {
+        let record = |id: &'static str, bundle: &'static str| {
+            vec![
+                (Cow::from("id"), Cow::from(id)),
+                (Cow::from("bundle id"), Cow::from(bundle)),
+            ]
+        };
+        records(
+            vec![
+                record("120", "com.apple.Music"),
+                record("35", "com.example.player"),
+                record("7", "com.apple.Safari"),
+            ],
+            args,
+        )
+    }
+
+    fn request(command: &str, args: &[&str]) -> Request {
+        Request {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_serde() {
+        let parsed: Request = serde_json::from_str(r#"{"command": "stop"}"#).unwrap();
+        assert_eq!(parsed.command, "stop");
+        assert!(parsed.args.is_empty());
+        let text = serde_json::to_string(&request("mark", &["intro"])).unwrap();
+        assert_eq!(text, r#"{"command":"mark","args":["intro"]}"#);
+        // 只输出有值的字段
+        for (response, text) in [
+            (Response::message("done"), r#"{"ok":true,"message":"done"}"#),
+            (Response::data(json!([1])), r#"{"ok":true,"data":[1]}"#),
+            (Response::error("fail"), r#"{"ok":false,"error":"fail"}"#),
+        ] {
+            assert_eq!(serde_json::to_string(&response).unwrap(), text);
+            let parsed: Response = serde_json::from_str(text).unwrap();
+            assert_eq!(parsed.ok, response.ok);
+            assert_eq!(parsed.message, response.message);
+            assert_eq!(parsed.data, response.data);
+            assert_eq!(parsed.error, response.error);
+        }
+    }
+
+    #[test]
+    fn test_execute_error() {
+        let response = execute(TEST_COMMANDS, &request("play", &[]));
+        assert!(!response.ok);
+        assert_eq!(
+            response.error.unwrap(),
+            "unknown command: play, supported: status, mark, list"
+        );
+        // 参数错误时返回命令的用法
+        let response = execute(TEST_COMMANDS, &request("list", &["--sort"]));
+        assert!(!response.ok);
+        assert!(response.error.unwrap().contains("usage: process listall"));
+        let response = execute(TEST_COMMANDS, &request("status", &["extra"]));
+        assert!(!response.ok);
+        // 命令执行失败
+        let response = execute(TEST_COMMANDS, &request("mark", &["intro"]));
+        assert_eq!(response.error.unwrap(), "not recording");
+    }
+
+    #[test]
+    fn test_execute_records() {
+        let response = execute(
+            TEST_COMMANDS,
+            &request("list", &["--filter", "apple", "--sort", "id"]),
+        );
+        assert!(response.ok);
+        assert_eq!(
+            response.data.unwrap(),
+            json!([
+                {"id": "7", "bundle_id": "com.apple.Safari"},
+                {"id": "120", "bundle_id": "com.apple.Music"},
+            ])
+        );
+        // 没有录音时 status 是空列表
+        let response = execute(
+            TEST_COMMANDS,
+            &request("status", &["--sort", "stream", "--filter", "1"]),
+        );
+        assert!(response.ok);
+        assert_eq!(response.data.unwrap(), json!([]));
+        // 没有这一列
+        let response = execute(TEST_COMMANDS, &request("list", &["--sort", "name"]));
+        assert!(!response.ok);
+    }
+
+    #[tokio::test]
+    async fn test_socket() {
+        let path =
+            std::env::temp_dir().join(format!("resound-control-{}.sock", std::process::id()));
+        // 上次异常退出时留下的文件
+        File::create(&path).unwrap();
+        let socket = bind_at(path.clone(), TEST_COMMANDS).await.unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // 已经在监听时不能再创建，也不会删除正在使用的 socket 文件
+        assert!(bind_at(path.clone(), TEST_COMMANDS).await.is_err());
+        assert!(path.exists());
+        let server = tokio::spawn(async move { socket.serve().await });
+        let response = send(&path, &request("list", &["--sort", "id", "--reverse"]))
+            .await
+            .unwrap();
+        assert!(response.ok);
+        assert_eq!(response.data.unwrap()[0]["id"], "120");
+        let response = send(&path, &request("play", &[])).await.unwrap();
+        assert!(!response.ok);
+        // 取消 task 时关闭 socket，删除文件
+        server.abort();
+        let _ = server.await;
+        assert!(!path.exists());
+        assert!(send(&path, &request("status", &[])).await.is_err());
+        // 释放锁之后可以重新创建
+        drop(bind_at(path.clone(), TEST_COMMANDS).await.unwrap());
+        let _ = std::fs::remove_file(path.with_extension("lock"));
+    }
+}
@@ -0,0 +1,246 @@
+//! daemon mode
+//! resound daemon 在后台运行，没有交互式输入，通过 control socket（resound ctl）控制
+//! 启动时重新执行 resound daemon --foreground，新的进程使用新的 session，离开终端，
+//! stdout 和 stderr 写入日志文件
+//! 子进程开始监听 control socket 后通过管道通知父进程，父进程这时才报告启动成功
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::signal::unix::{SignalKind, signal};
+
+use crate::interactive;
+use crate::rserror::{Result, RsError};
+
+use super::registry::{Arg, Args, Command, Complete, Kind};
+use super::{control, re};
+
+const PID_FILE_NAME: &str = "resound.pid";
+const LOG_FILE_NAME: &str = "resound.log";
+// 等待 daemon 开始监听的最长时间
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+// 通知管道的 fd，由父进程传给 --foreground 的子进程
+const READY_FD_ENV: &str = "RESOUND_READY_FD";
+const READY_MESSAGE: &str = "ready";
+
+/// 命令行模式的 daemon，没有处理函数，由 run 执行
+pub(super) const COMMAND: Command = Command::new(
+    "daemon",
+    "run in the background until SIGTERM, controlled by resound ctl",
+)
+.args(&[
+    Arg::option("--log", Kind::Text, "file").complete(Complete::File),
+    Arg::flag("--foreground"),
+]);
+
+/// resound daemon [--log file] [--foreground]
+/// --foreground 时在当前进程中运行，日志输出到 stdout 和 stderr
+pub(super) async fn run(args: &Args) -> Result<()> {
+    let dir = interactive::data_dir().ok_or_else(|| RsError::with_msg("没有找到 HOME 目录"))?;
+    let pid_path = dir.join(PID_FILE_NAME);
+    if args.flag("--foreground") {
+        return run_foreground(&pid_path).await;
+    }
+    // 提前检查，避免启动之后才在日志中报错，加锁由 --foreground 的子进程完成
+    PidFile::check(&pid_path)?;
+    let log_path = args
+        .text("--log")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dir.join(LOG_FILE_NAME));
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .map_err(|error| {
+            RsError::with_msg(format!("open {} fail: {}", log_path.display(), error))
+        })?;
+    // 子进程持有写入端，通知后或者退出时关闭
+    let (mut ready_reader, ready_writer) = std::io::pipe()?;
+    let ready_fd = ready_writer.as_raw_fd();
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .args(["daemon", "--foreground"])
+        .env(READY_FD_ENV, ready_fd.to_string())
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    unsafe {
+        // 在新的 session 中运行，关闭终端时不会收到 SIGHUP，ctrl + c 也不会发送到 daemon
+        // 管道默认是 close-on-exec，需要保留给子进程
+        command.pre_exec(move || {
+            if libc::setsid() == -1 || libc::fcntl(ready_fd, libc::F_SETFD, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    drop(ready_writer);
+    let ready = tokio::task::spawn_blocking(move || {
+        let mut message = String::new();
+        ready_reader
+            .read_to_string(&mut message)
+            .map(|_| message.trim() == READY_MESSAGE)
+    });
+    match tokio::time::timeout(START_TIMEOUT, ready).await {
+        Ok(Ok(Ok(true))) => {}
+        Ok(_) => {
+            // 没有通知就关闭了管道，子进程已经退出
+            let status = child.wait()?;
+            return Err(RsError::with_msg(format!(
+                "resound daemon exited with {}, see {}",
+                status,
+                log_path.display()
+            ))
+            .into());
+        }
+        Err(_) => {
+            return Err(RsError::with_msg(format!(
+                "resound daemon did not start in {}s, pid {}, see {}",
+                START_TIMEOUT.as_secs(),
+                child.id(),
+                log_path.display()
+            ))
+            .into());
+        }
+    }
+    println!(
+        "resound daemon started, pid {}, log {}",
+        child.id(),
+        log_path.display()
+    );
+    Ok(())
+}
+
+// 监听 control socket，直到收到 SIGTERM 或 SIGINT
+async fn run_foreground(pid_path: &Path) -> Result<()> {
+    let _pid_file = PidFile::lock(pid_path)?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let socket = control::bind().await?;
+    println!("resound daemon started, pid {}", std::process::id());
+    notify_ready();
+    tokio::select! {
+        result = socket.serve() => result?,
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
+    // 删除 pid 文件之前停止录音
+    re::shutdown();
+    println!("resound daemon stopped");
+    Ok(())
+}
+
+// 通知启动 daemon 的父进程已经开始监听，直接运行 --foreground 时没有父进程等待
+fn notify_ready() {
+    let Some(fd) = std::env::var(READY_FD_ENV)
+        .ok()
+        .and_then(|fd| fd.parse::<RawFd>().ok())
+    else {
+        return;
+    };
+    // 父进程传入的管道只在这里使用，写入后关闭
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    if let Err(error) = file.write_all(READY_MESSAGE.as_bytes()) {
+        eprintln!("notify ready fail: {}", error);
+    }
+}
+
+/// 加锁的 pid 文件，锁在进程退出时释放，drop 时删除文件
+struct PidFile {
+    path: PathBuf,
+    _file: File,
+}
+
+impl PidFile {
+    // 加锁失败说明有其它 daemon 正在运行
+    fn lock(path: &Path) -> Result<PidFile> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        try_lock(&mut file)?;
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+            _file: file,
+        })
+    }
+
+    /// 检查是否有其它 daemon 正在运行，不写入也不删除文件
+    /// 没有 pid 文件时直接返回，加锁成功时关闭文件释放锁
+    fn check(path: &Path) -> Result<()> {
+        match File::open(path) {
+            Ok(mut file) => try_lock(&mut file),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
+
+// 已经被其它进程加锁时，返回其中的 pid
+fn try_lock(file: &mut File) -> Result<()> {
+    match file.try_lock() {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            Err(RsError::with_msg(format!(
+                "resound daemon is already running, pid {}",
+                pid.trim()
+            ))
+            .into())
+        }
+        Err(TryLockError::Error(error)) => Err(error.into()),
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file() {
+        let path = std::env::temp_dir().join(format!("resound-pid-{}.pid", std::process::id()));
+        let pid_file = PidFile::lock(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        // 同一个进程中打开的另一个文件也不能加锁
+        let error = PidFile::lock(&path).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "resound daemon is already running, pid {}",
+                std::process::id()
+            )
+        );
+        // 检查时不修改文件
+        assert!(PidFile::check(&path).is_err());
+        assert!(path.exists());
+        drop(pid_file);
+        assert!(!path.exists());
+        PidFile::check(&path).unwrap();
+        assert!(!path.exists());
+        // 上次异常退出时留下的文件，没有加锁
+        std::fs::write(&path, "1\n").unwrap();
+        PidFile::check(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -4,50 +4,39 @@ use std::borrow::Cow;
 
 use audio::process;
 
-use crate::interactive::{print_list, PROMPT_DEFAULT_COW, PROMPT_ERR_COMMAND_COW};
+use crate::interactive::{PROMPT_DEFAULT_COW, Record, print_records};
+use crate::rserror::{Result, RsError};
 
-pub(super) fn run_command<'a, I>(command_iter: &mut I) -> Cow<'_, str>
-where
-    I: Iterator<Item = &'a str>, // Item 是 &'a str，生命周期 'a 确保字符串切片有效
-{
-    let token = command_iter.next();
-    match token {
-        Some("help") => help(),
-        Some("listall") => list_all(),
-        _ => PROMPT_ERR_COMMAND_COW,
-    }
-}
-
-// show help
-fn help() -> Cow<'static, str> {
-    print_list(&HELP_CONTENT);
-    PROMPT_DEFAULT_COW
-}
+use super::registry::{Args, Command};
 
-const HELP_CONTENT: [[(Cow<'_, str>, Cow<'_, str>); 1]; 2] = [
-    [(Cow::Borrowed("help"), Cow::Borrowed("show this"))],
-    [(Cow::Borrowed("listall"), Cow::Borrowed("show all process"))],
-];
+pub(super) const COMMAND: Command =
+    Command::new("process", "I think process is one with suppoer audio").subcommands(&[
+        Command::new("listall", "show all process")
+            .common(super::LIST_OPTIONS)
+            .run(list_all),
+    ]);
 
 // show all process
-fn list_all() -> Cow<'static, str> {
-    let process_vec = process::list().unwrap();
+fn list_all(args: &Args) -> Result<Cow<'static, str>> {
+    print_records(process_records()?, &super::list_options(args)).map_err(RsError::with_msg)?;
+    Ok(PROMPT_DEFAULT_COW)
+}
+
+/// 所有进程，每个进程一行
+pub(super) fn process_records() -> Result<Vec<Record<'static>>> {
+    let process_vec = process::list()?;
     let content_vec = process_vec
         .iter()
         .map(|process| {
             let bundle_id = process
                 .get_bundle_id()
-                .map(|bundle_id| Cow::from(bundle_id))
+                .map(|bundle_id| Cow::from(bundle_id.clone()))
                 .unwrap_or(Cow::from("query err"));
-            let vec = vec![
+            vec![
                 (Cow::from("id"), Cow::from(process.get_id().to_string())),
-                (Cow::from("budle id"), bundle_id),
-            ];
-            vec
+                (Cow::from("bundle id"), bundle_id),
+            ]
         })
-        .collect::<Vec<Vec<(Cow<'_, str>, Cow<'_, str>)>>>();
-
-    print_list(&content_vec);
-
-    PROMPT_DEFAULT_COW
+        .collect();
+    Ok(content_vec)
 }
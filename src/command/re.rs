@@ -1,186 +1,562 @@
 //! record sound command
 
 use std::borrow::Cow;
-use std::fs;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use audio::{
-    AudioBufferList, AudioObjectId, AudioTimeStamp, OSStatus, aggregate_device, device,
-    ext_audio_file, stream, tap,
+    AudioObjectId,
+    dsp::{
+        convert::SampleFormat,
+        meter::{ChannelLevels, MeterHandle},
+        resample::Quality,
+    },
+    ext_audio_file::FileType,
+    process,
+    segment::{Retention, Rotation},
 };
+use tokio::signal::unix::{SignalKind, signal};
 
-use crate::interactive::{print_list, PROMPT_ERR_COMMAND_COW};
-
-use crate::command;
+use crate::interactive::{OutputFormat, Record, print_records};
 use crate::rserror::{Result, RsError};
 
-const DEFAULT_AGGREGATE_DEVICE_NAME: &str = "resound-aggregate-device";
-const DEFAULT_AGGREGATE_DEVICE_UID: &str = "ABF64EB6-DC77-4251-80E2-1E773C25755E";
-const DEFAULT_FILE_NAME: &str = "resound";
+use super::registry::{Arg, Args, Command, Complete, Kind};
+
+mod manifest;
+mod session;
+mod template;
+mod writer;
+
+use session::{Mode, OutputSpec, Session, SessionStatus};
+use writer::{SegmentSpec, SplitSpec};
+
+// 同一时间只有一个录音，aggregate device 的 uid 是固定的
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+// 电平表显示的范围，单位 dBFS
+const METER_FLOOR_DB: f32 = -60.0;
+const METER_WIDTH: usize = 30;
+// 按静音拆分时，默认的最短静音间隔，秒
+const DEFAULT_SPLIT_GAP: f64 = 2.0;
+// 实时显示时的刷新间隔
+const METER_INTERVAL: Duration = Duration::from_millis(100);
+
+// re start、re buffer 和 record 共用的输出选项
+const OUTPUT_OPTIONS: &[Arg] = &[
+    Arg::option("--format", Kind::Text, "").choices(&["s16", "s24", "s32", "f32"]),
+    Arg::option("--channels", Kind::Integer, "N"),
+    Arg::option("--map", Kind::Text, "1,0"),
+    Arg::option("--rate", Kind::Integer, "16000"),
+    Arg::option("--quality", Kind::Text, "").choices(&["fast", "medium", "high"]),
+    Arg::option("--normalize", Kind::Number, "LUFS"),
+    Arg::option("--split-silence", Kind::Number, "dBFS"),
+    Arg::option("--split-gap", Kind::Number, "seconds"),
+    Arg::option("--trim-silence", Kind::Number, "dBFS"),
+    Arg::option("--segment", Kind::Duration, "10m"),
+    Arg::option("--segment-size", Kind::Size, "100MB"),
+    Arg::option("--keep", Kind::Duration, "24h"),
+    Arg::option("--max-total", Kind::Size, "2GB"),
+    Arg::option("--container", Kind::Text, "").choices(&["caf", "wav", "flac"]),
+    Arg::option("--out", Kind::Text, "dir|file.flac").complete(Complete::File),
+    Arg::option(
+        "--template",
+        Kind::Text,
+        "{bundle}/{date}_{time}_{stream}.{ext}",
+    ),
+    Arg::flag("--overwrite"),
+    Arg::flag("--fill-gaps"),
+];
+
+pub(super) const COMMAND: Command = Command::new("re", "record sound").subcommands(&[
+    Command::new("start", "start record sound")
+        .args(&[Arg::positional("process_id", Kind::Integer).complete(Complete::Process)])
+        .common(OUTPUT_OPTIONS)
+        .run(start),
+    // buffer 模式默认在内存中保留 60 秒
+    Command::new("buffer", "keep the last seconds of sound in memory")
+        .args(&[
+            Arg::positional("process_id", Kind::Integer).complete(Complete::Process),
+            Arg::option("--window", Kind::Duration, "60s").default("60s"),
+        ])
+        .common(OUTPUT_OPTIONS)
+        .run(buffer),
+    Command::new(
+        "save-last",
+        "save buffered sound to file, --continue keeps recording to the same file",
+    )
+    .args(&[
+        Arg::positional("duration", Kind::Duration),
+        Arg::flag("--continue"),
+    ])
+    .run(save_last),
+    Command::new("stop", "stop record sound").run(stop),
+    Command::new(
+        "mark",
+        "add a marker at the current position, saved in the files and manifest",
+    )
+    .args(&[Arg::positional("label", Kind::Rest).optional()])
+    .run(mark),
+    Command::new("status", "show the recording, one line for each stream")
+        .common(super::LIST_OPTIONS)
+        .run(status),
+    Command::new("meter", "show peak and rms level of each channel")
+        .args(&[Arg::positional("seconds", Kind::Number).optional()])
+        .run(meter),
+]);
+
+/// 命令行模式的录音，没有处理函数，由 record 执行
+pub(super) const RECORD: Command = Command::new(
+    "record",
+    "record until --duration or ctrl + c, options are the same as re start",
+)
+.args(&[
+    Arg::option("--pid", Kind::Integer, "N").complete(Complete::Process),
+    Arg::option("--bundle", Kind::Text, "id").complete(Complete::Bundle),
+    Arg::option("--duration", Kind::Duration, "10m"),
+])
+.common(OUTPUT_OPTIONS);
+
+// start recond sound
+fn start(args: &Args) -> Result<Cow<'static, str>> {
+    start_record(args)?;
+    Ok(Cow::Borrowed("start record sound..."))
+}
 
-pub(super) fn run_command<'a, I>(command_iter: &mut I) -> Cow<'_, str>
-where
-    I: Iterator<Item = &'a str>, // Item 是 &'a str，生命周期 'a 确保字符串切片有效
-{
-    let token = command_iter.next();
-    match token {
-        Some("help") => help(),
-        Some("start") => start(command_iter),
-        _ => PROMPT_ERR_COMMAND_COW,
+/// 开始录音，参数和 re start 相同
+pub(super) fn start_record(args: &Args) -> Result<()> {
+    start_args(args, Mode::Record)
+}
+
+// 在内存中保留最近一段时间的声音，由 save-last 保存
+fn buffer(args: &Args) -> Result<Cow<'static, str>> {
+    let window = args.duration("--window").unwrap_or_default();
+    if window.is_zero() {
+        return Err(RsError::with_msg("usage: --window 60s").into());
     }
+    start_args(args, Mode::Buffer { window })?;
+    Ok(Cow::from(format!(
+        "buffering the last {}s of sound, use \"re save-last\" to save...",
+        window.as_secs_f64()
+    )))
 }
 
-// start recond sound
-fn start<'a, I>(command_iter: &mut I) -> Cow<'_, str>
-where
-    I: Iterator<Item = &'a str>, // Item 是 &'a str，生命周期 'a 确保字符串切片有效
-{
-    let process_id;
-    let token = command_iter.next();
-    if let Some(id) = token {
-        // command appoint process id
-        // todo 需要检查ID是数字，需要检查ID存在，需要支持多个ID
-        process_id = id.parse::<AudioObjectId>().unwrap();
+// re start 和 re buffer 的 process_id 和输出选项
+fn start_args(args: &Args, mode: Mode) -> Result<()> {
+    // todo 需要检查ID存在，需要支持多个ID
+    let Some(process_id) = args.integer::<AudioObjectId>("process_id")? else {
+        return Err(RsError::with_msg("缺少参数: process_id").into());
+    };
+    start_session(process_id, parse_output_spec(args)?, mode)
+}
+
+/// 命令行模式的录音，录到 --duration 或收到 ctrl + c、kill 后停止
+/// resound record (--pid N | --bundle id) [--duration 10m] [options]，其它参数和 re start 相同
+pub(super) async fn record(args: &Args) -> Result<()> {
+    let process_id = match (
+        args.integer::<AudioObjectId>("--pid")?,
+        args.text("--bundle"),
+    ) {
+        (Some(process_id), None) => process_id,
+        (None, Some(bundle)) => find_process(bundle)?,
+        _ => return Err(RsError::with_msg("需要 --pid 或 --bundle 中的一个").into()),
+    };
+    let duration = args.duration("--duration");
+    let output_spec = parse_output_spec(args)?;
+    // 先注册信号，避免开始录音后收到的信号结束进程
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    start_session(process_id, output_spec, Mode::Record)?;
+    println!(
+        "start record process {}, press ctrl + c to stop",
+        process_id
+    );
+    let timeout = async {
+        match duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = timeout => {}
+        _ = interrupt.recv() => {}
+        _ = terminate.recv() => {}
+    }
+    let session = SESSION
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .take();
+    match session {
+        Some(session) => session.stop(),
+        None => Ok(()),
+    }
+}
+
+// bundle id 对应的进程，有多个时使用第一个
+fn find_process(bundle: &str) -> Result<AudioObjectId> {
+    process::list()?
+        .iter()
+        .find(|process| {
+            process
+                .get_bundle_id()
+                .is_ok_and(|bundle_id| bundle_id == bundle)
+        })
+        .map(|process| process.get_id())
+        .ok_or_else(|| RsError::with_msg(format!("没有找到 {} 的进程", bundle)).into())
+}
+
+// 同一时间只能有一个 session
+fn start_session(process_id: AudioObjectId, output_spec: OutputSpec, mode: Mode) -> Result<()> {
+    let mut session = SESSION.lock().unwrap_or_else(|error| error.into_inner());
+    if let Some(session) = session.as_ref() {
+        return Err(RsError::with_msg(format!(
+            "already recording process {}, please use \"re stop\" first",
+            session.process_id()
+        ))
+        .into());
+    }
+    *session = Some(Session::start(process_id, output_spec, mode)?);
+    Ok(())
+}
+
+// save buffered sound
+// re save-last 30s [--continue]，--continue 时保存后继续录音到同一个文件
+fn save_last(args: &Args) -> Result<Cow<'static, str>> {
+    let Some(duration) = args.duration("duration") else {
+        return Err(RsError::with_msg("usage: re save-last 30s [--continue]").into());
+    };
+    let keep_recording = args.flag("--continue");
+    let mut session = SESSION.lock().unwrap_or_else(|error| error.into_inner());
+    let Some(session) = session.as_mut() else {
+        return Err(RsError::with_msg("not recording").into());
+    };
+    let paths = session
+        .save_last(duration, keep_recording)?
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if keep_recording {
+        Ok(Cow::from(format!(
+            "saved to {}, continue recording...",
+            paths
+        )))
     } else {
-        // todo
-        todo!()
+        Ok(Cow::from(format!("saved to {}", paths)))
     }
+}
 
-    match recond_sound(process_id) {
-        Ok(cow) => cow,
-        Err(error) => Cow::from(error.to_string()),
+// stop recond sound
+fn stop(_: &Args) -> Result<Cow<'static, str>> {
+    match stop_record()? {
+        true => Ok(Cow::Borrowed("stop record sound")),
+        false => Ok(Cow::Borrowed("not recording")),
     }
 }
 
-// show help
-fn help() -> Cow<'static, str> {
-    print_list(&HELP_CONTENT);
-    crate::interactive::PROMPT_DEFAULT_COW
+/// 停止录音，没有录音时返回 false
+pub(super) fn stop_record() -> Result<bool> {
+    let session = SESSION
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .take();
+    match session {
+        Some(session) => session.stop().map(|()| true),
+        None => Ok(false),
+    }
 }
 
-const HELP_CONTENT: [[(Cow<'_, str>, Cow<'_, str>); 1]; 2] = [
-    [(Cow::Borrowed("help"), Cow::Borrowed("show this"))],
-    [(
-        Cow::Borrowed("start"),
-        Cow::Borrowed("start record sound. usage: re start process_id"),
-    )],
-];
+/// 退出时停止正在执行的录音
+pub(super) fn shutdown() {
+    if let Some(Err(error)) = SESSION
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .take()
+        .map(Session::stop)
+    {
+        eprintln!("stop record fail: {}", error);
+    }
+}
 
-// recond sound
-fn recond_sound(process_id: AudioObjectId) -> Result<Cow<'static, str>> {
-    // create tap
-    let tap_description_builder = tap::AudioTapDescriptionBuilder {
-        name: command::TAP_NAME_DEFAULT.to_string(),
-        uid: None,
-        processes: vec![process_id],
-        mono: false,
-        exclusive: false,
-        mixdown: true,
-        private: false,
-        device_uid: None,
-        stream: None,
-    };
-    let tap_description = tap_description_builder.build()?;
-    let tap = tap::AudioTap::create(&tap_description)?;
-    let tap_uid = tap::query_uid(&tap)?;
-    println!("tap_uid: {}", tap_uid);
-    // create aggregate device
-    let aggregate_device = aggregate_device::AudioAggregateDevice::builder(
-        DEFAULT_AGGREGATE_DEVICE_NAME,
-        DEFAULT_AGGREGATE_DEVICE_UID,
-    )
-    .private(false)
-    .tap_list(vec![tap_uid])
-    .build()?;
-    // 查询 stream
-    // 读取stream 格式
-    let streams = stream::list_by_id(&aggregate_device)?;
-    if streams.is_empty() {
-        return Err(RsError::with_msg("创建的临时aggregate device没有stream"))?;
-    }
-    // 一个stream,创建一个文件,暂时不考虑多个stream合并的问题
-    // create audio file
-    let mut audio_ext_file_vec: Vec<ext_audio_file::AudioExtAudioFile> =
-        Vec::with_capacity(streams.len());
-    for (i, stream) in streams.iter().enumerate() {
-        match stream.get_basic_description() {
-            Ok(basic_description) => {
-                let audio_ext_file = ext_audio_file::AudioExtAudioFile::create(
-                    format!("{}-{}.caf", DEFAULT_FILE_NAME, i),
-                    basic_description,
-                )?;
-                audio_ext_file_vec.push(audio_ext_file);
-            }
-            Err(error) => {
-                // clean audio file
-                audio_ext_file_vec.iter().for_each(|path| {
-                    let _ = fs::remove_file(path);
-                });
-                audio_ext_file_vec.iter().for_each(|path| {
-                    let _ = fs::remove_file(path);
-                });
-                // println! error
-                return Err(error)?;
-            }
-        }
+// add marker
+// re mark [label]，没有名称时使用 mark N
+fn mark(args: &Args) -> Result<Cow<'static, str>> {
+    let label = add_mark(args.text("label").map(str::to_string))?;
+    Ok(Cow::from(format!("marked \"{}\"", label)))
+}
+
+/// 在当前位置添加标记，返回标记的名称
+pub(super) fn add_mark(label: Option<String>) -> Result<String> {
+    match SESSION
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_mut()
+    {
+        Some(session) => session.mark(label),
+        None => Err(RsError::with_msg("not recording").into()),
     }
-    // create io proc id
-    let re_io_proc = ReIoProc { audio_ext_file_vec };
-    let mut audio_io_proc_handler = device::AudioIoProcHandler::new(&aggregate_device, re_io_proc);
-    // start
-    audio_io_proc_handler.start()?;
-    // 临时方案：
-    // 调用audio模块的一个tap创建方法
-    // 方法内完整实现整个流程，包含停应用
-    // 逐步抽取代码到其他方法
+}
 
-    let ten_millis = std::time::Duration::from_millis(5000);
-    std::thread::sleep(ten_millis);
+// show recording status
+fn status(args: &Args) -> Result<Cow<'static, str>> {
+    let options = super::list_options(args);
+    let list = status_records();
+    if list.is_empty() && options.format == OutputFormat::Text {
+        return Ok(Cow::Borrowed("not recording"));
+    }
+    print_records(list, &options).map_err(RsError::with_msg)?;
+    Ok(crate::interactive::PROMPT_DEFAULT_COW)
+}
 
-    audio_io_proc_handler.stop()?;
+/// 正在录音的每个 stream 一行，没有录音时为空
+pub(super) fn status_records() -> Vec<Record<'static>> {
+    SESSION
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_ref()
+        .map(|session| status_list(&session.status()))
+        .unwrap_or_default()
+}
 
-    Ok(Cow::Borrowed("start record sound..."))
+fn status_list(status: &SessionStatus) -> Vec<Record<'static>> {
+    let mode = match status.mode {
+        Mode::Record => Cow::Borrowed("record"),
+        Mode::Buffer { window } => Cow::from(format!("buffer {}s", window.as_secs_f64())),
+    };
+    let elapsed = status.started.elapsed().unwrap_or_default();
+    status
+        .formats
+        .iter()
+        .enumerate()
+        .map(|(index, format)| {
+            vec![
+                (Cow::from("stream"), Cow::from(index.to_string())),
+                (
+                    Cow::from("process id"),
+                    Cow::from(status.process_id.to_string()),
+                ),
+                (Cow::from("mode"), mode.clone()),
+                (
+                    Cow::from("recording"),
+                    Cow::from(status.recording.to_string()),
+                ),
+                (
+                    Cow::from("elapsed"),
+                    Cow::from(format!("{:.1}s", elapsed.as_secs_f64())),
+                ),
+                (Cow::from("markers"), Cow::from(status.markers.to_string())),
+                (Cow::from("format"), Cow::from(format.to_string())),
+            ]
+        })
+        .collect()
 }
 
-struct ReIoProc {
-    audio_ext_file_vec: Vec<ext_audio_file::AudioExtAudioFile>,
-}
-
-impl device::AudioIoProc for ReIoProc {
-    fn proc(
-        &mut self,
-        _in_device: AudioObjectId,
-        _in_now: &AudioTimeStamp,
-        in_input_data: &AudioBufferList,
-        _in_input_time: &AudioTimeStamp,
-        _out_output_data: &mut AudioBufferList,
-        _in_output_time: &AudioTimeStamp,
-    ) -> OSStatus {
-        let m_number_buffers = in_input_data.mNumberBuffers;
-
-        let mut all_success = true;
-
-        for i in 0..m_number_buffers as usize {
-            let buffer = in_input_data.mBuffers[i];
-            let buffers = [buffer];
-            // 目前，没有对stream的情况做任何处理
-            // 一个stream对应一个音频文件
-            // 需要重新组装AudioBufferList，满足格式要求
-            let io_data = AudioBufferList {
-                mNumberBuffers: 1,
-                mBuffers: buffers,
-            };
-            if let Some(ext_audio_file) = self.audio_ext_file_vec.get_mut(i) {
-                if let Err(error) = ext_audio_file.write_audio_buffer_list_async(&io_data) {
-                    all_success = false;
-                    eprintln!("{}", error);
-                }
-            }
+// show level meter
+// 没有参数时显示一次，参数是秒数时，在这段时间内实时刷新
+// 会阻塞一段时间，command::run 在 spawn_blocking 中执行
+fn meter(args: &Args) -> Result<Cow<'static, str>> {
+    let seconds = match args.number("seconds") {
+        None => 0.0,
+        Some(seconds) if seconds >= 0.0 => seconds,
+        Some(_) => return Err(RsError::with_msg("usage: re meter [seconds]").into()),
+    };
+    let meters = match SESSION
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+        .as_ref()
+    {
+        Some(session) => session.meters(),
+        None => return Err(RsError::with_msg("not recording").into()),
+    };
+    let times = (seconds / METER_INTERVAL.as_secs_f64()).ceil().max(1.0) as usize;
+    let mut lines = 0;
+    let mut stdout = io::stdout();
+    for time in 0..times {
+        // 第一次先等待一个间隔，积累数据
+        std::thread::sleep(METER_INTERVAL);
+        let text = render_meters(&meters);
+        if time > 0 {
+            // 光标移动到上次输出的开始位置，覆盖输出
+            let _ = write!(stdout, "\x1b[{}A", lines);
+        }
+        lines = text.lines().count();
+        let _ = write!(stdout, "{}", text);
+        let _ = stdout.flush();
+    }
+    Ok(crate::interactive::PROMPT_DEFAULT_COW)
+}
+
+// 每个 stream 的每个声道一行
+fn render_meters(meters: &[MeterHandle]) -> String {
+    let mut text = String::new();
+    for (stream_index, meter) in meters.iter().enumerate() {
+        for (channel, levels) in meter.take().iter().enumerate() {
+            text.push_str(&format!(
+                "stream {} ch {} {}\x1b[K\n",
+                stream_index,
+                channel,
+                render_levels(levels)
+            ));
         }
+    }
+    text
+}
 
-        if all_success {
-            audio::K_AUDIO_HARDWARE_NO_ERROR
+// [=====|----    ] peak  -6.0 dB rms -12.0 dB clips 0
+// = 是 rms，| 是 peak
+fn render_levels(levels: &ChannelLevels) -> String {
+    let position = |db: f32| -> usize {
+        if db.is_nan() || db <= METER_FLOOR_DB {
+            return 0;
+        }
+        let ratio = (db.min(0.0) - METER_FLOOR_DB) / -METER_FLOOR_DB;
+        (ratio * METER_WIDTH as f32).round() as usize
+    };
+    let rms = position(levels.rms_db());
+    let peak = position(levels.peak_db());
+    let bar = (0..METER_WIDTH)
+        .map(|index| {
+            if index + 1 == peak {
+                '|'
+            } else if index < rms {
+                '='
+            } else if index < peak {
+                '-'
+            } else {
+                ' '
+            }
+        })
+        .collect::<String>();
+    let db = |db: f32| {
+        if db <= METER_FLOOR_DB {
+            "  -inf".to_string()
         } else {
-            audio::K_AUDIO_HARDWARE_ILLEGAL_OPERATION_ERROR
+            format!("{:6.1}", db)
         }
+    };
+    format!(
+        "[{}] peak {} dB rms {} dB clips {}",
+        bar,
+        db(levels.peak_db()),
+        db(levels.rms_db()),
+        levels.clips
+    )
+}
+
+// 由 OUTPUT_OPTIONS 中的选项生成输出格式，检查取值范围和选项之间的关系
+fn parse_output_spec(args: &Args) -> Result<OutputSpec> {
+    let mut output_spec = OutputSpec::default();
+    if let Some(format) = args.text("--format") {
+        output_spec.sample_format = Some(format.parse::<SampleFormat>()?);
+    }
+    match args.integer::<usize>("--channels") {
+        Ok(Some(channels)) if channels > 0 => output_spec.channels = Some(channels),
+        Ok(None) => {}
+        _ => return Err(RsError::with_msg("声道数错误").into()),
+    }
+    if let Some(value) = args.text("--map") {
+        let channel_map = value
+            .split(',')
+            .map(|index| index.trim().parse::<usize>())
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| RsError::with_msg(format!("声道映射错误: {}", value)))?;
+        output_spec.channel_map = Some(channel_map);
+    }
+    match args.integer::<u32>("--rate") {
+        Ok(Some(sample_rate)) if sample_rate > 0 => output_spec.sample_rate = Some(sample_rate),
+        Ok(None) => {}
+        _ => return Err(RsError::with_msg("采样率错误").into()),
+    }
+    if let Some(quality) = args.text("--quality") {
+        output_spec.quality = quality.parse::<Quality>()?;
+    }
+    match args.number("--normalize") {
+        Some(target) if target < 0.0 => output_spec.normalize = Some(target),
+        Some(target) => return Err(RsError::with_msg(format!("目标响度错误: {}", target)).into()),
+        None => {}
+    }
+    match args.number("--split-silence") {
+        Some(threshold_db) if threshold_db < 0.0 => {
+            output_spec.split = Some(SplitSpec {
+                threshold_db: threshold_db as f32,
+                min_gap: DEFAULT_SPLIT_GAP,
+            })
+        }
+        Some(threshold_db) => {
+            return Err(RsError::with_msg(format!("静音门限错误: {}", threshold_db)).into());
+        }
+        None => {}
+    }
+    match args.number("--trim-silence") {
+        Some(threshold_db) if threshold_db < 0.0 => {
+            output_spec.trim_silence = Some(threshold_db as f32)
+        }
+        Some(threshold_db) => {
+            return Err(RsError::with_msg(format!("静音门限错误: {}", threshold_db)).into());
+        }
+        None => {}
+    }
+    match (output_spec.split.as_mut(), args.number("--split-gap")) {
+        (Some(split), Some(min_gap)) if min_gap > 0.0 => split.min_gap = min_gap,
+        (Some(_), Some(min_gap)) => {
+            return Err(RsError::with_msg(format!("静音间隔错误: {}", min_gap)).into());
+        }
+        (None, Some(_)) => {
+            return Err(RsError::with_msg("--split-gap 需要和 --split-silence 一起使用").into());
+        }
+        _ => {}
+    }
+    let mut segment = SegmentSpec::default();
+    match args.duration("--segment") {
+        Some(duration) if duration.as_secs() > 0 => segment.rotation.duration = Some(duration),
+        Some(_) => return Err(RsError::with_msg("分段时长至少 1 秒").into()),
+        None => {}
+    }
+    segment.rotation.bytes = args.size("--segment-size");
+    segment.retention.max_age = args.duration("--keep");
+    segment.retention.max_total = args.size("--max-total");
+    if let Some(out) = args.text("--out") {
+        output_spec.path.set_out(out)?;
+    }
+    if let Some(template) = args.text("--template") {
+        if output_spec.path.is_out_file() {
+            return Err(RsError::with_msg("--out 是文件时不能使用 --template").into());
+        }
+        output_spec.path.set_template(template)?;
+    }
+    if let Some(container) = args.text("--container") {
+        let file_type = FileType::from_extension(container)
+            .ok_or_else(|| RsError::with_msg(format!("不支持的文件格式: {}", container)))?;
+        output_spec.path.set_container(file_type)?;
+    }
+    // flac 只支持整数采样，不能截断
+    if output_spec.path.file_type() == FileType::Flac {
+        if output_spec.sample_format == Some(SampleFormat::F32) {
+            return Err(
+                RsError::with_msg("flac 不支持 f32，请使用 --format s16、s24 或 s32").into(),
+            );
+        }
+        if output_spec.trim_silence.is_some() {
+            return Err(RsError::with_msg("flac 不能和 --trim-silence 一起使用").into());
+        }
+    }
+    output_spec.path.set_overwrite(args.flag("--overwrite"));
+    output_spec.fill_gaps = args.flag("--fill-gaps");
+    if segment.rotation != Rotation::default() {
+        if output_spec.split.is_some() {
+            return Err(RsError::with_msg("分段录音不能和 --split-silence 一起使用").into());
+        }
+        output_spec.segment = Some(segment);
+    } else if segment.retention != Retention::default() {
+        return Err(RsError::with_msg(
+            "--keep 和 --max-total 需要和 --segment 或 --segment-size 一起使用",
+        )
+        .into());
+    }
+    if let (Some(channels), Some(channel_map)) = (output_spec.channels, &output_spec.channel_map)
+        && channels != channel_map.len()
+    {
+        return Err(RsError::with_msg("--channels 和 --map 的声道数不一致"))?;
     }
+    Ok(output_spec)
 }
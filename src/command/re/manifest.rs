@@ -0,0 +1,198 @@
+//! session manifest
+//! 录音结束后在音频文件旁边写入 json，描述这次录音的来源、格式、文件和错误
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::rserror::Result;
+
+/// 一次录音
+#[derive(Debug, Default, Serialize)]
+pub(super) struct Manifest {
+    /// resound 的版本
+    pub(super) version: String,
+    /// record 或 buffer
+    pub(super) mode: String,
+    pub(super) start_time: String,
+    pub(super) end_time: String,
+    pub(super) taps: Vec<TapInfo>,
+    pub(super) processes: Vec<ProcessInfo>,
+    pub(super) aggregate_device: DeviceInfo,
+    pub(super) streams: Vec<StreamInfo>,
+    pub(super) io: IoInfo,
+    pub(super) errors: Vec<String>,
+    pub(super) markers: Vec<MarkerInfo>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(super) struct TapInfo {
+    pub(super) uid: String,
+    pub(super) processes: Vec<u32>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(super) struct ProcessInfo {
+    pub(super) id: u32,
+    pub(super) bundle: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(super) struct DeviceInfo {
+    pub(super) name: String,
+    pub(super) uid: String,
+}
+
+/// 一个 stream 的输入、输出格式和生成的文件
+#[derive(Debug, Default, Serialize)]
+pub(super) struct StreamInfo {
+    pub(super) index: usize,
+    pub(super) input_format: String,
+    pub(super) output_format: String,
+    pub(super) files: Vec<PathBuf>,
+}
+
+/// io proc 的运行统计
+#[derive(Debug, Default, Serialize)]
+pub(super) struct IoInfo {
+    pub(super) calls: u64,
+    /// 处理失败、被丢弃的 buffer 数
+    pub(super) dropped_buffers: u64,
+    pub(super) panics: u64,
+    /// mSampleTime 不连续，丢失数据的次数和帧数
+    pub(super) gaps: u64,
+    pub(super) gap_frames: u64,
+    /// mSampleTime 和上一次回调重复的次数和帧数，重复的帧没有写入文件
+    pub(super) overlaps: u64,
+    pub(super) overlap_frames: u64,
+    /// 录音线程来不及处理，队列已满丢弃的回调次数和帧数
+    pub(super) overflows: u64,
+    pub(super) overflow_frames: u64,
+    /// 是否用静音填充了丢失的数据
+    pub(super) gaps_filled: bool,
+}
+
+/// re mark 添加的标记
+#[derive(Debug, Default, Serialize)]
+pub(super) struct MarkerInfo {
+    pub(super) label: String,
+    /// 标记所在的帧的 mSampleTime，由发出命令时的 host time 确定
+    pub(super) sample_time: f64,
+    /// 从开始录音算起的秒数
+    pub(super) offset: f64,
+    pub(super) time: String,
+    /// 每个文件中的帧位置
+    pub(super) files: Vec<MarkerFile>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(super) struct MarkerFile {
+    pub(super) path: PathBuf,
+    pub(super) frame: u64,
+}
+
+impl Manifest {
+    pub(super) fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let text = serde_json::to_string_pretty(self)?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // 脚本按字段名读取 manifest，修改字段名需要同时修改这里
+    #[test]
+    fn test_field_names() {
+        let manifest = Manifest {
+            version: "0.1.0".to_string(),
+            mode: "record".to_string(),
+            start_time: "2026-10-18T14:00:00Z".to_string(),
+            end_time: "2026-10-18T14:10:00Z".to_string(),
+            taps: vec![TapInfo {
+                uid: "tap".to_string(),
+                processes: vec![42],
+            }],
+            processes: vec![ProcessInfo {
+                id: 42,
+                bundle: "com.example".to_string(),
+            }],
+            aggregate_device: DeviceInfo {
+                name: "resound".to_string(),
+                uid: "aggregate".to_string(),
+            },
+            streams: vec![StreamInfo {
+                index: 0,
+                input_format: "in".to_string(),
+                output_format: "out".to_string(),
+                files: vec![PathBuf::from("a.caf")],
+            }],
+            io: IoInfo {
+                calls: 1,
+                dropped_buffers: 2,
+                panics: 3,
+                gaps: 4,
+                gap_frames: 5,
+                overlaps: 6,
+                overlap_frames: 7,
+                overflows: 8,
+                overflow_frames: 9,
+                gaps_filled: true,
+            },
+            errors: vec!["error".to_string()],
+            markers: vec![MarkerInfo {
+                label: "intro".to_string(),
+                sample_time: 512.0,
+                offset: 1.5,
+                time: "2026-10-18T14:00:01Z".to_string(),
+                files: vec![MarkerFile {
+                    path: PathBuf::from("a.caf"),
+                    frame: 72_000,
+                }],
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(&manifest).unwrap(),
+            json!({
+                "version": "0.1.0",
+                "mode": "record",
+                "start_time": "2026-10-18T14:00:00Z",
+                "end_time": "2026-10-18T14:10:00Z",
+                "taps": [{"uid": "tap", "processes": [42]}],
+                "processes": [{"id": 42, "bundle": "com.example"}],
+                "aggregate_device": {"name": "resound", "uid": "aggregate"},
+                "streams": [{
+                    "index": 0,
+                    "input_format": "in",
+                    "output_format": "out",
+                    "files": ["a.caf"]
+                }],
+                "io": {
+                    "calls": 1,
+                    "dropped_buffers": 2,
+                    "panics": 3,
+                    "gaps": 4,
+                    "gap_frames": 5,
+                    "overlaps": 6,
+                    "overlap_frames": 7,
+                    "overflows": 8,
+                    "overflow_frames": 9,
+                    "gaps_filled": true
+                },
+                "errors": ["error"],
+                "markers": [{
+                    "label": "intro",
+                    "sample_time": 512.0,
+                    "offset": 1.5,
+                    "time": "2026-10-18T14:00:01Z",
+                    "files": [{"path": "a.caf", "frame": 72000}]
+                }]
+            })
+        );
+    }
+}
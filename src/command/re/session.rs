@@ -0,0 +1,1220 @@
+//! recording session
+//! 录音在单独的线程中执行，tap、aggregate device、io proc 都在这个线程中创建和销毁
+//! io proc 只把收到的数据放入无锁队列，处理、写入文件都在录音线程中
+
+use std::mem;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use audio::{
+    AudioBufferList, AudioObjectId, AudioStreamBasicDescription, AudioTimeStamp, OSStatus,
+    aggregate_device,
+    buffer::AudioBufferListRef,
+    device,
+    dsp::{
+        Block, Pipeline, Stage,
+        convert::{ChannelConvert, SampleFormat},
+        loudness::{self, LoudnessMeter},
+        meter::{Meter, MeterHandle},
+        resample::{Quality, Resampler},
+        ring::RingBuffer,
+    },
+    ext_audio_file::FileType,
+    format::StreamFormat,
+    process, segment, spsc, stream, tap,
+    timeline::{self, Discontinuity, Timeline},
+};
+
+use crate::command::{self, analyze};
+use crate::rserror::{Result, RsError};
+
+use super::manifest::{
+    DeviceInfo, IoInfo, Manifest, MarkerFile, MarkerInfo, ProcessInfo, StreamInfo, TapInfo,
+};
+use super::template::{OutputPath, PathTemplate};
+use super::writer::{SegmentSpec, SplitSpec, TrackWriter};
+
+const DEFAULT_AGGREGATE_DEVICE_NAME: &str = "resound-aggregate-device";
+const DEFAULT_AGGREGATE_DEVICE_UID: &str = "ABF64EB6-DC77-4251-80E2-1E773C25755E";
+// 标准化后 true peak 的上限，dBTP
+const NORMALIZE_TRUE_PEAK_CEILING: f64 = -1.0;
+// 录音线程中最多记录的错误数
+const MAX_IO_ERRORS: usize = 100;
+// 一次最多用静音填充的时长，更长的间隔通常是时间戳错误
+const MAX_GAP_FILL: Duration = Duration::from_secs(60);
+// 填充静音时每个 block 的帧数
+const GAP_FILL_BLOCK_FRAMES: u64 = 4096;
+// 把正在写入的文件同步到磁盘的间隔
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+// 录音线程处理 io proc 放入队列的数据的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// io proc 和录音线程之间的队列能保存的时长
+const QUEUE_DURATION: Duration = Duration::from_secs(2);
+// 队列中最多保存的回调次数
+const MAX_QUEUED_CALLBACKS: usize = 4096;
+
+/// 输出文件的格式，没有指定的使用 stream 的格式
+#[derive(Debug, Clone, Default)]
+pub(super) struct OutputSpec {
+    pub(super) sample_format: Option<SampleFormat>,
+    pub(super) channels: Option<usize>,
+    // 输出第 i 个声道来自输入第 channel_map[i] 个声道
+    pub(super) channel_map: Option<Vec<usize>>,
+    pub(super) sample_rate: Option<u32>,
+    pub(super) quality: Quality,
+    // 停止后标准化到的响度，LUFS
+    pub(super) normalize: Option<f64>,
+    // 按静音拆分为多个文件
+    pub(super) split: Option<SplitSpec>,
+    // 去掉开始和结束的静音，dBFS
+    pub(super) trim_silence: Option<f32>,
+    // 定期开始新的文件，删除旧的文件
+    pub(super) segment: Option<SegmentSpec>,
+    // 输出目录和文件名
+    pub(super) path: PathTemplate,
+    // 用静音填充丢失的数据，使文件的时间和实际时间一致
+    pub(super) fill_gaps: bool,
+}
+
+/// 录音方式
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Mode {
+    /// 开始后直接写入文件
+    Record,
+    /// 只在内存中保留最近 window 的数据，由 save_last 写入文件
+    Buffer { window: Duration },
+}
+
+/// 录音的状态
+#[derive(Debug, Clone)]
+pub(super) struct SessionStatus {
+    pub(super) process_id: AudioObjectId,
+    pub(super) mode: Mode,
+    // buffer 模式下，save_last 之后是否继续写入文件
+    pub(super) recording: bool,
+    pub(super) started: SystemTime,
+    pub(super) markers: usize,
+    // 每个 stream 输出文件的格式
+    pub(super) formats: Vec<StreamFormat>,
+}
+
+/// 正在执行的录音
+/// drop 时停止录音，并等待录音线程结束
+pub(super) struct Session {
+    process_id: AudioObjectId,
+    mode: Mode,
+    output_spec: OutputSpec,
+    stop_tx: mpsc::Sender<()>,
+    command_tx: mpsc::Sender<ReCommand>,
+    thread: Option<thread::JoinHandle<Result<()>>>,
+    // 每个 stream 一个，和录音线程中的 ReOutput 一一对应
+    streams: Vec<StreamOutput>,
+    // buffer 模式下，save_last 之后是否继续写入文件
+    recording: bool,
+    // 已经添加的标记数
+    markers: usize,
+    started: SystemTime,
+}
+
+impl Session {
+    /// 开始录音，录音开始后才返回
+    pub(super) fn start(
+        process_id: AudioObjectId,
+        output_spec: OutputSpec,
+        mode: Mode,
+    ) -> Result<Session> {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let thread_output_spec = output_spec.clone();
+        let thread = thread::Builder::new()
+            .name("resound-record".to_string())
+            .spawn(move || {
+                recond_sound(
+                    process_id,
+                    &thread_output_spec,
+                    mode,
+                    |streams, command_tx| {
+                        let _ = ready_tx.send((streams, command_tx));
+                    },
+                    stop_rx,
+                )
+            })?;
+        match ready_rx.recv() {
+            Ok((streams, command_tx)) => Ok(Session {
+                process_id,
+                mode,
+                output_spec,
+                stop_tx,
+                command_tx,
+                thread: Some(thread),
+                streams,
+                recording: matches!(mode, Mode::Record),
+                markers: 0,
+                started: SystemTime::now(),
+            }),
+            // 没有开始，线程已经结束
+            Err(_) => match thread.join() {
+                Ok(Err(error)) => Err(error),
+                Ok(Ok(())) => Err(RsError::with_msg("录音线程提前结束"))?,
+                Err(_) => Err(RsError::with_msg("录音线程 panic"))?,
+            },
+        }
+    }
+
+    pub(super) fn process_id(&self) -> AudioObjectId {
+        self.process_id
+    }
+
+    pub(super) fn status(&self) -> SessionStatus {
+        SessionStatus {
+            process_id: self.process_id,
+            mode: self.mode,
+            recording: self.recording,
+            started: self.started,
+            markers: self.markers,
+            formats: self
+                .streams
+                .iter()
+                .map(|stream| stream.file_format)
+                .collect(),
+        }
+    }
+
+    pub(super) fn meters(&self) -> Vec<MeterHandle> {
+        self.streams
+            .iter()
+            .map(|stream| stream.meter.clone())
+            .collect()
+    }
+
+    /// buffer 模式下，把最近 duration 的数据写入文件，返回创建的文件
+    /// keep_recording 时文件不关闭，之后的数据继续写入，直到停止
+    pub(super) fn save_last(
+        &mut self,
+        duration: Duration,
+        keep_recording: bool,
+    ) -> Result<Vec<PathBuf>> {
+        let Mode::Buffer { window } = self.mode else {
+            return Err(RsError::with_msg("not buffering, please use \"re buffer\" first").into());
+        };
+        if duration > window {
+            return Err(RsError::with_msg(format!(
+                "only the last {}s is buffered",
+                window.as_secs_f64()
+            ))
+            .into());
+        }
+        if keep_recording && self.recording {
+            return Err(RsError::with_msg("already recording").into());
+        }
+        // 同一个 session 可以保存多次，文件名中的时间是保存的时间
+        let now = SystemTime::now();
+        let mut paths = Vec::new();
+        let mut commands = Vec::with_capacity(self.streams.len());
+        for (index, stream) in self.streams.iter().enumerate() {
+            let (mut writer, loudness, position) =
+                stream.save_last(stream.output_path.at(now), duration, &self.output_spec)?;
+            paths.extend_from_slice(writer.paths());
+            if keep_recording {
+                commands.push(ReCommand::Attach {
+                    stream: index,
+                    writer: Box::new(writer),
+                    loudness: Box::new(loudness),
+                    position,
+                });
+                continue;
+            }
+            writer.finish()?;
+            if let (Some(target), Some(loudness)) = (self.output_spec.normalize, loudness) {
+                normalize(writer.paths(), &loudness.summary(), target)?;
+            }
+            // 记录到 manifest
+            commands.push(ReCommand::Saved {
+                stream: index,
+                paths: writer.paths().to_vec(),
+            });
+        }
+        for command in commands {
+            self.command_tx
+                .send(command)
+                .map_err(|_| RsError::with_msg("录音线程已经结束"))?;
+        }
+        self.recording |= keep_recording;
+        Ok(paths)
+    }
+
+    /// 在发出命令时设备正在录制的帧添加标记，返回标记的名称
+    pub(super) fn mark(&mut self, label: Option<String>) -> Result<String> {
+        self.markers += 1;
+        let label = label.unwrap_or_else(|| format!("mark {}", self.markers));
+        self.command_tx
+            .send(ReCommand::Mark {
+                label: label.clone(),
+                time: SystemTime::now(),
+                host_time: timeline::host_time_now(),
+            })
+            .map_err(|_| RsError::with_msg("录音线程已经结束"))?;
+        Ok(label)
+    }
+
+    /// 停止录音，返回录音线程中的错误
+    pub(super) fn stop(mut self) -> Result<()> {
+        self.join()
+    }
+
+    fn join(&mut self) -> Result<()> {
+        let Some(thread) = self.thread.take() else {
+            return Ok(());
+        };
+        let _ = self.stop_tx.send(());
+        match thread.join() {
+            Ok(result) => result,
+            Err(_) => Err(RsError::with_msg("录音线程 panic"))?,
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Err(error) = self.join() {
+            eprintln!("stop record fail: {}", error);
+        }
+    }
+}
+
+// recond sound
+// 在录音线程中执行，开始后通过 ready 返回每个 stream 的输出和命令发送端，
+// 收到停止消息或 stop_rx 关闭后停止
+fn recond_sound<F>(
+    process_id: AudioObjectId,
+    output_spec: &OutputSpec,
+    mode: Mode,
+    ready: F,
+    stop_rx: mpsc::Receiver<()>,
+) -> Result<()>
+where
+    F: FnOnce(Vec<StreamOutput>, mpsc::Sender<ReCommand>),
+{
+    // create tap
+    let tap_description_builder = tap::AudioTapDescriptionBuilder {
+        name: command::TAP_NAME_DEFAULT.to_string(),
+        uid: None,
+        processes: vec![process_id],
+        mono: false,
+        exclusive: false,
+        mixdown: true,
+        private: false,
+        device_uid: None,
+        stream: None,
+    };
+    let tap_description = tap_description_builder.build()?;
+    let tap = tap::AudioTap::create(&tap_description)?;
+    let tap_uid = tap::query_uid(&tap)?;
+    let start_time = SystemTime::now();
+    println!("tap_uid: {}", tap_uid);
+    // create aggregate device
+    // tap 交给 aggregate device 管理，保证先删除device，再删除tap
+    let aggregate_device = aggregate_device::AudioAggregateDevice::builder(
+        DEFAULT_AGGREGATE_DEVICE_NAME,
+        DEFAULT_AGGREGATE_DEVICE_UID,
+    )
+    .private(false)
+    .taps(vec![tap])
+    .build()?;
+    // 查询 stream
+    // 读取stream 格式
+    let streams = stream::list_by_id(&aggregate_device)?;
+    if streams.is_empty() {
+        return Err(RsError::with_msg("创建的临时aggregate device没有stream"))?;
+    }
+    // 一个stream,创建一个文件,暂时不考虑多个stream合并的问题
+    // create audio file
+    // 文件名模板中的变量
+    let bundle = process::AudioProcess::from(process_id)
+        .get_bundle_id()
+        .cloned()
+        .unwrap_or_else(|_| "unknown".to_string());
+    // 每个文件都写入的元数据
+    let tags = vec![
+        (
+            "encoding application".to_string(),
+            format!("resound {}", env!("CARGO_PKG_VERSION")),
+        ),
+        ("source bundle".to_string(), bundle.clone()),
+        ("source process".to_string(), process_id.to_string()),
+        (
+            "device".to_string(),
+            DEFAULT_AGGREGATE_DEVICE_NAME.to_string(),
+        ),
+    ];
+    let mut output_vec: Vec<ReOutput> = Vec::with_capacity(streams.len());
+    let mut stream_output_vec: Vec<StreamOutput> = Vec::with_capacity(streams.len());
+    let mut io_stream_vec: Vec<IoStream> = Vec::with_capacity(streams.len());
+    let mut stream_desc_vec: Vec<AudioStreamBasicDescription> = Vec::with_capacity(streams.len());
+    for (i, stream) in streams.iter().enumerate() {
+        match stream.get_basic_description() {
+            Ok(basic_description) => {
+                let (output, stream_output, io_stream) = ReOutput::create(
+                    output_spec
+                        .path
+                        .bind(&bundle, process_id, &i.to_string(), start_time),
+                    StreamFormat::from(basic_description),
+                    output_spec,
+                    mode,
+                    tags.clone(),
+                )?;
+                output_vec.push(output);
+                stream_output_vec.push(stream_output);
+                io_stream_vec.push(io_stream);
+                stream_desc_vec.push(*basic_description);
+            }
+            Err(error) => {
+                // clean audio file
+                output_vec
+                    .iter_mut()
+                    .filter_map(|output| output.writer.as_mut())
+                    .for_each(|writer| writer.remove_files());
+                // println! error
+                return Err(error)?;
+            }
+        }
+    }
+    let (event_producer, event_consumer) = spsc::channel(MAX_QUEUED_CALLBACKS);
+    let mut recorder = Recorder {
+        output_vec,
+        events: event_consumer,
+        errors: Vec::new(),
+        pending_marks: Vec::new(),
+        markers: Vec::new(),
+        first_sample_time: None,
+        next_sample_time: 0.0,
+        overlap: 0,
+        sample_rate: stream_desc_vec
+            .first()
+            .map(|desc| desc.mSampleRate)
+            .unwrap_or(1.0),
+        fill_gaps: output_spec.fill_gaps,
+    };
+    // create io proc id
+    let re_io_proc = ReIoProc {
+        stream_vec: io_stream_vec,
+        stream_desc_vec,
+        events: event_producer,
+        timeline: Timeline::new(),
+        overflows: 0,
+        overflow_frames: 0,
+        dropped_frames: 0,
+    };
+    let mut audio_io_proc_handler = device::AudioIoProcHandler::new(&aggregate_device, re_io_proc);
+    // start
+    audio_io_proc_handler.start()?;
+    let (command_tx, command_rx) = mpsc::channel();
+    ready(stream_output_vec, command_tx);
+    // 等待停止，期间处理 io proc 放入队列的数据，并定期同步正在写入的文件
+    // 关闭前 caf 的 data chunk 大小是 -1，wav 和 flac 的大小、总帧数没有写入，异常退出后可以用 recover 修复
+    let mut last_sync = Instant::now();
+    while let Err(mpsc::RecvTimeoutError::Timeout) = stop_rx.recv_timeout(POLL_INTERVAL) {
+        command_rx
+            .try_iter()
+            .for_each(|command| recorder.command(command));
+        recorder.process();
+        if last_sync.elapsed() >= SYNC_INTERVAL {
+            recorder.flush();
+            last_sync = Instant::now();
+        }
+    }
+
+    // 停止后的错误不中断处理，都记录到 manifest，最后返回第一个
+    let mut errors = Vec::new();
+    if let Err(error) = audio_io_proc_handler.stop() {
+        errors.push(error.to_string());
+    }
+    let stats = audio_io_proc_handler.stats();
+    let (timeline_stats, overflows, overflow_frames) = audio_io_proc_handler
+        .audio_io_proc_mut()
+        .map(|re_io_proc| {
+            (
+                re_io_proc.timeline.stats(),
+                re_io_proc.overflows,
+                re_io_proc.overflow_frames,
+            )
+        })
+        .unwrap_or_default();
+    drop(audio_io_proc_handler);
+    // 处理停止前队列中剩余的数据
+    command_rx
+        .try_iter()
+        .for_each(|command| recorder.command(command));
+    recorder.process();
+    recorder.finish_marks();
+    let mut stream_infos = Vec::new();
+    // 需要标准化的文件和响度
+    let mut loudness_vec = Vec::new();
+    for (index, output) in recorder.output_vec.iter_mut().enumerate() {
+        if let Err(error) = output.finish() {
+            errors.push(error.to_string());
+        }
+        if let (Some(writer), Some(loudness)) = (output.writer.as_ref(), output.loudness.as_ref()) {
+            loudness_vec.push((writer.paths().to_vec(), loudness.summary()));
+        }
+        stream_infos.push(output.info(index));
+    }
+    let io_errors = mem::take(&mut recorder.errors);
+    let markers = mem::take(&mut recorder.markers);
+    // 关闭文件
+    drop(recorder);
+
+    // 第二遍：按录音时测量的响度调整增益
+    if let Some(target) = output_spec.normalize {
+        for (paths, summary) in loudness_vec {
+            if let Err(error) = normalize(&paths, &summary, target) {
+                errors.push(error.to_string());
+            }
+        }
+    }
+
+    let manifest = Manifest {
+        version: format!("resound {}", env!("CARGO_PKG_VERSION")),
+        mode: match mode {
+            Mode::Record => "record".to_string(),
+            Mode::Buffer { .. } => "buffer".to_string(),
+        },
+        start_time: segment::utc_iso8601(start_time),
+        end_time: segment::utc_iso8601(SystemTime::now()),
+        taps: vec![TapInfo {
+            uid: tap_uid,
+            processes: vec![process_id],
+        }],
+        processes: vec![ProcessInfo {
+            id: process_id,
+            bundle: bundle.clone(),
+        }],
+        aggregate_device: DeviceInfo {
+            name: DEFAULT_AGGREGATE_DEVICE_NAME.to_string(),
+            uid: DEFAULT_AGGREGATE_DEVICE_UID.to_string(),
+        },
+        streams: stream_infos,
+        io: IoInfo {
+            calls: stats.calls(),
+            dropped_buffers: stats.errors() + stats.panics(),
+            panics: stats.panics(),
+            gaps: timeline_stats.gaps,
+            gap_frames: timeline_stats.gap_frames,
+            overlaps: timeline_stats.overlaps,
+            overlap_frames: timeline_stats.overlap_frames,
+            overflows,
+            overflow_frames,
+            gaps_filled: output_spec.fill_gaps,
+        },
+        errors: io_errors
+            .into_iter()
+            .chain(errors.iter().cloned())
+            .collect(),
+        markers,
+    };
+    let manifest_path = output_spec
+        .path
+        .bind(&bundle, process_id, "session", start_time);
+    manifest.write(manifest_path.resolve(manifest_path.manifest(), &[])?)?;
+
+    match errors.into_iter().next() {
+        Some(error) => Err(RsError::with_msg(error).into()),
+        None => Ok(()),
+    }
+}
+
+// 按录音时测量的响度调整增益，拆分后的多个文件使用相同的增益
+fn normalize(paths: &[PathBuf], summary: &loudness::LoudnessSummary, target: f64) -> Result<()> {
+    let Some(gain) = loudness::normalization_gain(summary, target, NORMALIZE_TRUE_PEAK_CEILING)
+    else {
+        println!("normalize: no loudness, skip");
+        return Ok(());
+    };
+    for path in paths {
+        println!(
+            "normalize {}: {:.1} LUFS, gain {:+.1} dB",
+            path.display(),
+            summary.integrated,
+            gain
+        );
+        analyze::apply_gain(path, gain)?;
+    }
+    Ok(())
+}
+
+// 控制线程中一个 stream 的输出，和录音线程中的 ReOutput 一一对应
+struct StreamOutput {
+    output_path: OutputPath,
+    // 输出文件的格式
+    file_format: StreamFormat,
+    sample_format: SampleFormat,
+    // 输入的电平
+    meter: MeterHandle,
+    // buffer 模式下最近的输出数据
+    ring: Option<Arc<Mutex<RingBuffer>>>,
+    // 写入文件的元数据
+    tags: Vec<(String, String)>,
+}
+
+impl StreamOutput {
+    // 创建文件，写入 ring 中最近 duration 的数据
+    // 返回 writer、这些数据的响度，以及 ring 中下一帧的位置
+    fn save_last(
+        &self,
+        output_path: OutputPath,
+        duration: Duration,
+        output_spec: &OutputSpec,
+    ) -> Result<(TrackWriter, Option<LoudnessMeter>, u64)> {
+        let Some(ring) = self.ring.as_ref() else {
+            return Err(RsError::with_msg("not buffering").into());
+        };
+        let channels = self.file_format.channels as usize;
+        let frames = (duration.as_secs_f64() * self.file_format.sample_rate).round() as usize;
+        let mut block = Block::new(channels, self.file_format.sample_rate);
+        // 只在复制时持有锁
+        let position = {
+            let ring = ring.lock().unwrap_or_else(|error| error.into_inner());
+            ring.read_last(frames, &mut block.samples);
+            ring.total()
+        };
+        let mut loudness = match output_spec.normalize {
+            Some(_) => Some(LoudnessMeter::new(channels, self.file_format.sample_rate)?),
+            None => None,
+        };
+        if let Some(loudness) = loudness.as_mut() {
+            loudness.add(&block.samples);
+        }
+        let mut writer = TrackWriter::create(
+            output_path,
+            &self.file_format,
+            self.sample_format,
+            output_spec.split,
+            output_spec.trim_silence,
+            output_spec.segment,
+            self.tags.clone(),
+        )?;
+        if let Err(error) = writer.write(&block) {
+            writer.remove_files();
+            return Err(error);
+        }
+        Ok((writer, loudness, position))
+    }
+}
+
+// 控制线程发送给录音线程的命令
+enum ReCommand {
+    // 保存 pre-roll 后继续录音：position 之前的数据已经写入 writer，录音线程从 position 开始继续写入
+    Attach {
+        stream: usize,
+        writer: Box<TrackWriter>,
+        loudness: Box<Option<LoudnessMeter>>,
+        position: u64,
+    },
+    // 保存 pre-roll 生成的文件，记录到 manifest
+    Saved {
+        stream: usize,
+        paths: Vec<PathBuf>,
+    },
+    // 添加标记，time 是收到命令的时间，host_time 用于确定标记在哪一帧
+    Mark {
+        label: String,
+        time: SystemTime,
+        host_time: u64,
+    },
+}
+
+// 一个 stream 的输出：转换为指定的声道和采样格式后写入文件
+struct ReOutput {
+    stream_format: StreamFormat,
+    file_format: StreamFormat,
+    block: Block,
+    pipeline: Pipeline,
+    // 输出的响度，需要标准化时才测量
+    loudness: Option<LoudnessMeter>,
+    // buffer 模式下保存之前没有文件
+    writer: Option<TrackWriter>,
+    // buffer 模式下最近的输出数据，和控制线程共享
+    ring: Option<Arc<Mutex<RingBuffer>>>,
+    // io proc 收到的数据
+    queue: spsc::Consumer<f32>,
+    // writer 需要从 ring 的这个位置开始补写
+    catch_up: Option<u64>,
+    // 控制线程保存 pre-roll 生成的文件
+    saved: Vec<PathBuf>,
+}
+
+impl ReOutput {
+    fn create(
+        output_path: OutputPath,
+        stream_format: StreamFormat,
+        output_spec: &OutputSpec,
+        mode: Mode,
+        mut tags: Vec<(String, String)>,
+    ) -> Result<(ReOutput, StreamOutput, IoStream)> {
+        let input_channels = stream_format.channels as usize;
+        if let Some(index) = output_spec
+            .channel_map
+            .iter()
+            .flatten()
+            .find(|index| **index >= input_channels)
+        {
+            return Err(RsError::with_msg(format!(
+                "声道映射错误: {} 超出输入声道数 {}",
+                index, input_channels
+            ))
+            .into());
+        }
+        let channel_convert = match (&output_spec.channel_map, output_spec.channels) {
+            (Some(channel_map), _) => ChannelConvert::with_map(channel_map.clone()),
+            (None, Some(channels)) => ChannelConvert::new(channels),
+            (None, None) => ChannelConvert::new(input_channels),
+        };
+        // 没有指定采样格式时，尽量和 stream 保持一致
+        let sample_format = output_spec
+            .sample_format
+            .or_else(|| SampleFormat::from_stream_format(&stream_format))
+            .unwrap_or(SampleFormat::F32);
+        // flac 只支持整数采样，stream 是浮点时使用 24 位
+        let sample_format = match (output_path.file_type(), sample_format) {
+            (FileType::Flac, SampleFormat::F32) => SampleFormat::S24,
+            (_, sample_format) => sample_format,
+        };
+        let sample_rate = output_spec
+            .sample_rate
+            .map(f64::from)
+            .unwrap_or(stream_format.sample_rate);
+        let channel_convert_channels = channel_convert.channels();
+        let file_format = sample_format.stream_format(sample_rate, channel_convert_channels as u32);
+        tags.push((
+            "sample format".to_string(),
+            format!(
+                "{} {} Hz {} ch",
+                sample_format, sample_rate, channel_convert_channels
+            ),
+        ));
+        let (writer, ring) = match mode {
+            Mode::Record => {
+                let writer = TrackWriter::create(
+                    output_path.clone(),
+                    &file_format,
+                    sample_format,
+                    output_spec.split,
+                    output_spec.trim_silence,
+                    output_spec.segment,
+                    tags.clone(),
+                )?;
+                (Some(writer), None)
+            }
+            Mode::Buffer { window } => {
+                let frames = (window.as_secs_f64() * sample_rate).ceil() as usize;
+                let ring = RingBuffer::new(channel_convert_channels, frames);
+                (None, Some(Arc::new(Mutex::new(ring))))
+            }
+        };
+        let mut pipeline = Pipeline::new();
+        let (meter, meter_handle) = Meter::new(input_channels);
+        pipeline.push(meter);
+        // 先转换声道，减少重采样的计算量
+        pipeline.push(channel_convert);
+        if let Some(to_rate) = output_spec.sample_rate {
+            pipeline.push(Resampler::new(
+                stream_format.sample_rate.round() as u32,
+                to_rate,
+                output_spec.quality,
+            )?);
+        }
+        // buffer 模式下保存时才开始测量
+        let loudness = match (output_spec.normalize, mode) {
+            (Some(_), Mode::Record) => {
+                Some(LoudnessMeter::new(channel_convert_channels, sample_rate)?)
+            }
+            _ => None,
+        };
+        let stream_output = StreamOutput {
+            output_path,
+            file_format,
+            sample_format,
+            meter: meter_handle,
+            ring: ring.clone(),
+            tags,
+        };
+        let queue_samples = (QUEUE_DURATION.as_secs_f64() * stream_format.sample_rate).ceil()
+            as usize
+            * input_channels;
+        let (producer, queue) = spsc::channel(queue_samples);
+        let io_stream = IoStream {
+            format: stream_format,
+            block: Block::new(input_channels, stream_format.sample_rate),
+            queue: producer,
+        };
+        let output = ReOutput {
+            block: Block::new(input_channels, stream_format.sample_rate),
+            stream_format,
+            file_format,
+            pipeline,
+            loudness,
+            writer,
+            ring,
+            queue,
+            catch_up: None,
+            saved: Vec::new(),
+        };
+        Ok((output, stream_output, io_stream))
+    }
+
+    // 从队列中读取 io proc 收到的 frames 帧，去掉开始的和已经写入的数据重复的 overlap 帧
+    fn write(&mut self, frames: usize, overlap: usize) -> Result<()> {
+        self.block.channels = self.stream_format.channels as usize;
+        self.block.sample_rate = self.stream_format.sample_rate;
+        self.block.samples.clear();
+        let samples = frames * self.block.channels;
+        if self.queue.pop_into(samples, &mut self.block.samples) != samples {
+            return Err(RsError::with_msg("io queue is out of sync").into());
+        }
+        if overlap > 0 {
+            self.block
+                .samples
+                .drain(..overlap.min(frames) * self.block.channels);
+        }
+        if self.block.samples.is_empty() {
+            return Ok(());
+        }
+        self.process_block()
+    }
+
+    // 用静音填充丢失的 frames 帧，frames 是 stream 的采样率下的帧数
+    fn fill_gap(&mut self, frames: u64) -> Result<()> {
+        let max_frames = (MAX_GAP_FILL.as_secs_f64() * self.stream_format.sample_rate) as u64;
+        let mut rest = frames.min(max_frames);
+        while rest > 0 {
+            let frames = rest.min(GAP_FILL_BLOCK_FRAMES);
+            self.block.channels = self.stream_format.channels as usize;
+            self.block.sample_rate = self.stream_format.sample_rate;
+            self.block.samples.clear();
+            self.block
+                .samples
+                .resize(frames as usize * self.block.channels, 0.0);
+            self.process_block()?;
+            rest -= frames;
+        }
+        Ok(())
+    }
+
+    // 处理 block 中读取的数据，写入 ring 和文件
+    fn process_block(&mut self) -> Result<()> {
+        self.pipeline.process(&mut self.block);
+        if let Some(ring) = self.ring.as_ref() {
+            let mut ring = ring.lock().unwrap_or_else(|error| error.into_inner());
+            ring.push(&self.block.samples);
+            if self.writer.is_some()
+                && let Some(position) = self.catch_up.take()
+            {
+                // 写入控制线程读取之后的所有数据，包括当前 block
+                self.block.samples.clear();
+                if ring.read_from(position, &mut self.block.samples) != position {
+                    eprintln!("pre-roll buffer overrun, some audio is lost");
+                }
+            }
+        }
+        self.write_block()
+    }
+
+    // 在下一次写入的数据的第 frame 帧添加标记，frame 是 stream 的采样率下的帧数
+    fn mark(&mut self, label: &str, frame: usize) -> Option<(PathBuf, u64)> {
+        let ratio = self.file_format.sample_rate / self.stream_format.sample_rate;
+        let frame = (frame as f64 * ratio).round() as u64;
+        self.writer.as_mut()?.mark(label, frame)
+    }
+
+    // 保存 pre-roll 后继续写入文件
+    fn attach(&mut self, writer: TrackWriter, loudness: Option<LoudnessMeter>, position: u64) {
+        self.writer = Some(writer);
+        self.loudness = loudness;
+        self.catch_up = Some(position);
+    }
+
+    // manifest 中的 stream
+    fn info(&self, index: usize) -> StreamInfo {
+        let mut files = self.saved.clone();
+        if let Some(writer) = self.writer.as_ref() {
+            files.extend_from_slice(writer.paths());
+        }
+        StreamInfo {
+            index,
+            input_format: self.stream_format.to_string(),
+            output_format: self.file_format.to_string(),
+            files,
+        }
+    }
+
+    // 停止后写入 pipeline 中缓存的数据，关闭文件
+    fn finish(&mut self) -> Result<()> {
+        if self.writer.is_none() {
+            return Ok(());
+        }
+        if let Some(position) = self.catch_up.take()
+            && let Some(ring) = self.ring.as_ref()
+        {
+            // 保存后还没有来得及补写
+            let ring = ring.lock().unwrap_or_else(|error| error.into_inner());
+            self.block.channels = ring.channels();
+            self.block.samples.clear();
+            ring.read_from(position, &mut self.block.samples);
+            drop(ring);
+            self.write_block()?;
+        }
+        self.block.clear();
+        self.pipeline.flush(&mut self.block);
+        if !self.block.is_empty() {
+            self.write_block()?;
+        }
+        match self.writer.as_mut() {
+            Some(writer) => writer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // 编码 block 中处理后的数据，写入文件
+    fn write_block(&mut self) -> Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        if let Some(loudness) = self.loudness.as_mut() {
+            loudness.add(&self.block.samples);
+        }
+        writer.write(&self.block)
+    }
+}
+
+// 录音线程中处理 io proc 放入队列的数据
+struct Recorder {
+    output_vec: Vec<ReOutput>,
+    // 每次回调的信息，和每个 stream 队列中的采样一一对应
+    events: spsc::Consumer<IoEvent>,
+    // 处理数据时的错误，写入 manifest
+    errors: Vec<String>,
+    // 还没有处理到 host time 对应的数据的标记
+    pending_marks: Vec<PendingMark>,
+    markers: Vec<MarkerInfo>,
+    // 第一次回调时的 mSampleTime
+    first_sample_time: Option<f64>,
+    // 已经处理的数据之后的 mSampleTime
+    next_sample_time: f64,
+    // 和已经写入的数据重复、还没有去掉的帧数
+    overlap: u64,
+    // 第一个 stream 的采样率，用于计算标记的时间
+    sample_rate: f64,
+    fill_gaps: bool,
+}
+
+impl Recorder {
+    fn command(&mut self, command: ReCommand) {
+        match command {
+            ReCommand::Attach {
+                stream,
+                writer,
+                loudness,
+                position,
+            } => {
+                if let Some(output) = self.output_vec.get_mut(stream) {
+                    output.attach(*writer, *loudness, position);
+                }
+            }
+            ReCommand::Saved { stream, paths } => {
+                if let Some(output) = self.output_vec.get_mut(stream) {
+                    output.saved.extend(paths);
+                }
+            }
+            ReCommand::Mark {
+                label,
+                time,
+                host_time,
+            } => self.pending_marks.push(PendingMark {
+                label,
+                time,
+                host_time,
+            }),
+        }
+    }
+
+    // 处理队列中所有的数据
+    fn process(&mut self) {
+        while let Some(event) = self.events.pop() {
+            // io proc 只记录不连续，在这里输出和填充
+            let mut gap = event.dropped_frames;
+            if event.dropped_frames > 0 {
+                eprintln!("io queue overflow, {} frames dropped", event.dropped_frames);
+            }
+            match event.discontinuity {
+                Some(Discontinuity::Gap(frames)) => gap += frames,
+                Some(Discontinuity::Overlap(frames)) => self.overlap += frames,
+                None => {}
+            }
+            if let Some(discontinuity) = event.discontinuity {
+                eprintln!("input discontinuity: {:?}", discontinuity);
+            }
+            if self.fill_gaps && gap > 0 {
+                for output in self.output_vec.iter_mut() {
+                    if let Err(error) = output.fill_gap(gap) {
+                        push_error(&mut self.errors, error.to_string());
+                    }
+                }
+            }
+            // 重复的数据不再写入，可能跨过多次回调
+            let overlap = self.overlap.min(event.frames as u64) as usize;
+            self.overlap -= overlap as u64;
+            self.add_pending_marks(&event, overlap);
+            self.next_sample_time = event.sample_time + event.frames as f64;
+            for output in self.output_vec.iter_mut() {
+                if let Err(error) = output.write(event.frames, overlap) {
+                    push_error(&mut self.errors, error.to_string());
+                }
+            }
+        }
+    }
+
+    // 把正在写入的文件同步到磁盘
+    fn flush(&mut self) {
+        for output in self.output_vec.iter_mut() {
+            if let Some(writer) = output.writer.as_mut()
+                && let Err(error) = writer.flush()
+            {
+                push_error(&mut self.errors, error.to_string());
+            }
+        }
+    }
+
+    // 添加 host time 在这次回调的数据中的标记，在写入这些数据之前调用
+    // 没有 host time 时标记在这次回调的第一帧，开始的 overlap 帧不写入文件
+    fn add_pending_marks(&mut self, event: &IoEvent, overlap: usize) {
+        self.first_sample_time.get_or_insert(event.sample_time);
+        let mut index = 0;
+        while index < self.pending_marks.len() {
+            let host_time = self.pending_marks[index].host_time;
+            let frame = match event.host_time {
+                0 => Some(0),
+                event_host_time => timeline::frame_at(
+                    timeline::host_time_elapsed(event_host_time, host_time),
+                    self.sample_rate,
+                    event.frames,
+                ),
+            };
+            match frame {
+                Some(frame) => {
+                    let mark = self.pending_marks.remove(index);
+                    let sample_time = event.sample_time + frame as f64;
+                    self.add_mark(mark, sample_time, frame.saturating_sub(overlap));
+                }
+                None => index += 1,
+            }
+        }
+    }
+
+    // 停止后还没有处理到的标记放在录音的末尾
+    fn finish_marks(&mut self) {
+        for mark in mem::take(&mut self.pending_marks) {
+            self.add_mark(mark, self.next_sample_time, 0);
+        }
+    }
+
+    // 在 sample_time 添加标记，是下一次写入的数据的第 frame 帧
+    fn add_mark(&mut self, mark: PendingMark, sample_time: f64, frame: usize) {
+        let first_sample_time = *self.first_sample_time.get_or_insert(sample_time);
+        let files = self
+            .output_vec
+            .iter_mut()
+            .filter_map(|output| output.mark(&mark.label, frame))
+            .map(|(path, frame)| MarkerFile { path, frame })
+            .collect();
+        self.markers.push(MarkerInfo {
+            label: mark.label,
+            sample_time,
+            offset: (sample_time - first_sample_time) / self.sample_rate,
+            time: segment::utc_iso8601(mark.time),
+            files,
+        });
+    }
+}
+
+// 等待确定位置的标记
+struct PendingMark {
+    label: String,
+    time: SystemTime,
+    host_time: u64,
+}
+
+// 输出并记录到 manifest，最多 MAX_IO_ERRORS 个
+fn push_error(errors: &mut Vec<String>, error: String) {
+    eprintln!("{}", error);
+    if errors.len() < MAX_IO_ERRORS {
+        errors.push(error);
+    }
+}
+
+// io proc 每次回调放入队列的信息，采样放在每个 stream 的队列中
+#[derive(Debug, Clone, Copy, Default)]
+struct IoEvent {
+    sample_time: f64,
+    // 第一帧的 host time，无效时是 0
+    host_time: u64,
+    // 每个 stream 的帧数
+    frames: usize,
+    // 和上一次回调之间的不连续
+    discontinuity: Option<Discontinuity>,
+    // 上一次放入队列之后，因为队列已满丢弃的帧数，包括其间的间隔
+    dropped_frames: u64,
+}
+
+// io 线程中一个 stream 的输入
+struct IoStream {
+    format: StreamFormat,
+    // 转换为 f32 的数据，预先分配的内存重复使用
+    block: Block,
+    queue: spsc::Producer<f32>,
+}
+
+// io 线程中只把收到的数据转换为 f32 放入队列，不访问文件、不加锁
+// 处理和写入文件都在录音线程的 Recorder 中
+struct ReIoProc {
+    stream_vec: Vec<IoStream>,
+    // 每个stream的格式，和 stream_vec 一一对应
+    stream_desc_vec: Vec<AudioStreamBasicDescription>,
+    events: spsc::Producer<IoEvent>,
+    // 检查每次收到的数据是否和上一次连续
+    timeline: Timeline,
+    // 队列已满丢弃的回调次数和帧数，停止后写入 manifest
+    overflows: u64,
+    overflow_frames: u64,
+    // 还没有通知录音线程的丢弃的帧数
+    dropped_frames: u64,
+}
+
+impl device::AudioIoProc for ReIoProc {
+    type Command = ();
+
+    fn proc(
+        &mut self,
+        _in_device: AudioObjectId,
+        _in_now: &AudioTimeStamp,
+        in_input_data: AudioBufferListRef<'_>,
+        in_input_time: &AudioTimeStamp,
+        _out_output_data: &mut AudioBufferList,
+        _in_output_time: &AudioTimeStamp,
+    ) -> OSStatus {
+        // 一个stream对应一个音频文件，所有 stream 来自同一个设备，帧数相同
+        let mut frames = None;
+        for (stream_buffers, stream) in in_input_data
+            .split_by_stream(&self.stream_desc_vec)
+            .zip(self.stream_vec.iter_mut())
+        {
+            let Some(stream_buffers) = stream_buffers else {
+                // buffer 数量和 stream 格式不一致
+                return audio::K_AUDIO_HARDWARE_ILLEGAL_OPERATION_ERROR;
+            };
+            if stream
+                .block
+                .read_stream(stream_buffers, &stream.format)
+                .is_err()
+                || stream.block.channels != stream.format.channels as usize
+                || *frames.get_or_insert(stream.block.frames()) != stream.block.frames()
+            {
+                return audio::K_AUDIO_HARDWARE_ILLEGAL_OPERATION_ERROR;
+            }
+        }
+        let frames = frames.unwrap_or(0);
+        let discontinuity = self.timeline.advance_timestamp(in_input_time, frames);
+        // 录音线程来不及处理时丢弃这次的数据，所有队列都有空间才写入，保证队列之间对齐
+        let full = self.events.vacant() == 0
+            || self
+                .stream_vec
+                .iter()
+                .any(|stream| stream.queue.vacant() < stream.block.samples.len());
+        if full {
+            self.overflows += 1;
+            self.overflow_frames += frames as u64;
+            // 录音线程按间隔处理，丢弃的数据可以用静音填充
+            if let Some(Discontinuity::Gap(gap)) = discontinuity {
+                self.dropped_frames += gap;
+            }
+            self.dropped_frames += frames as u64;
+            return audio::K_AUDIO_HARDWARE_ILLEGAL_OPERATION_ERROR;
+        }
+        for stream in self.stream_vec.iter_mut() {
+            stream.queue.push_slice(&stream.block.samples);
+        }
+        self.events.push(IoEvent {
+            sample_time: in_input_time.mSampleTime,
+            host_time: timeline::host_time(in_input_time),
+            frames,
+            discontinuity,
+            dropped_frames: mem::take(&mut self.dropped_frames),
+        });
+        audio::K_AUDIO_HARDWARE_NO_ERROR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audio::format::SampleType;
+
+    #[test]
+    fn test_channel_map() {
+        let stream_format = StreamFormat::linear_pcm(48000.0, SampleType::Float, 32, 2, true);
+        let output_spec = OutputSpec {
+            channel_map: Some(vec![1, 5]),
+            ..OutputSpec::default()
+        };
+        let result = ReOutput::create(
+            PathTemplate::default().bind("com.example", 42, "0", SystemTime::now()),
+            stream_format,
+            &output_spec,
+            Mode::Buffer {
+                window: Duration::from_secs(1),
+            },
+            Vec::new(),
+        );
+        assert_eq!(
+            result.err().unwrap().to_string(),
+            "声道映射错误: 5 超出输入声道数 2"
+        );
+    }
+
+    #[test]
+    fn test_write_overlap() {
+        let stream_format = StreamFormat::linear_pcm(48000.0, SampleType::Float, 32, 2, true);
+        let (mut output, stream_output, mut io_stream) = ReOutput::create(
+            PathTemplate::default().bind("com.example", 42, "0", SystemTime::now()),
+            stream_format,
+            &OutputSpec::default(),
+            Mode::Buffer {
+                window: Duration::from_secs(1),
+            },
+            Vec::new(),
+        )
+        .unwrap();
+        // 每次回调 3 帧，第二次的前 2 帧和第一次重复，第三次的第 1 帧也重复
+        for frames in [[1.0, 2.0, 3.0], [2.0, 3.0, 4.0], [3.0, 5.0, 6.0]] {
+            let samples = frames.iter().flat_map(|sample| [*sample, -sample]);
+            assert!(io_stream.queue.push_slice(&samples.collect::<Vec<_>>()));
+        }
+        output.write(3, 0).unwrap();
+        output.write(3, 2).unwrap();
+        output.write(3, 1).unwrap();
+        let mut samples = Vec::new();
+        let ring = stream_output.ring.unwrap();
+        ring.lock().unwrap().read_from(0, &mut samples);
+        assert_eq!(
+            samples,
+            vec![
+                1.0, -1.0, 2.0, -2.0, 3.0, -3.0, 4.0, -4.0, 5.0, -5.0, 6.0, -6.0
+            ]
+        );
+        // 重复的帧数超过一次回调
+        assert!(io_stream.queue.push_slice(&[7.0, -7.0]));
+        output.write(1, 1).unwrap();
+        assert_eq!(ring.lock().unwrap().total(), 6);
+    }
+}
@@ -0,0 +1,615 @@
+//! output path template
+//! 文件名模板中可以使用的变量: {bundle} {pid} {stream} {date} {time} {track} {ext}
+//! 日期和时间是 UTC，格式是 2026-10-18 和 14-00-00
+//! 文件格式由模板中的扩展名或 --container 决定，支持 caf、wav、flac，默认 caf
+//! opus 需要 ogg 容器，Core Audio 不能写入，暂不支持
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use audio::{AudioObjectId, ext_audio_file::FileType, segment};
+
+use crate::rserror::{Result, RsError};
+
+const DEFAULT_TEMPLATE: &str = "resound-{stream}.{ext}";
+const EXT_CUE: &str = "cue";
+const EXT_MANIFEST: &str = "json";
+// {track} 为空时去掉的分隔符
+const TRACK_SEPARATORS: &[char] = &['-', '_', ' '];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    Bundle,
+    Pid,
+    Stream,
+    Date,
+    Time,
+    Track,
+    Ext,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Var(Var),
+}
+
+/// 输出目录、文件名模板，以及文件已经存在时的处理方式
+#[derive(Debug, Clone)]
+pub(super) struct PathTemplate {
+    dir: Option<PathBuf>,
+    parts: Vec<Part>,
+    // --container 指定的格式
+    container: Option<FileType>,
+    // --out 指定了文件名，不能再使用模板
+    out_file: bool,
+    // 覆盖已经存在的文件，否则在文件名后追加编号
+    overwrite: bool,
+}
+
+impl Default for PathTemplate {
+    fn default() -> PathTemplate {
+        PathTemplate {
+            dir: None,
+            parts: parse(DEFAULT_TEMPLATE).unwrap_or_default(),
+            container: None,
+            out_file: false,
+            overwrite: false,
+        }
+    }
+}
+
+impl PathTemplate {
+    /// --out 是已经存在的目录或者没有扩展名的路径时作为输出目录，不存在时创建文件时创建
+    /// 否则是输出文件，例如 --out file.flac，文件名原样作为模板，扩展名决定文件格式
+    pub(super) fn set_out(&mut self, out: &str) -> Result<()> {
+        let path = PathBuf::from(out);
+        let is_dir = match path.try_exists()? {
+            true => path.is_dir(),
+            false => path.extension().is_none(),
+        };
+        if is_dir {
+            self.dir = Some(path);
+            return Ok(());
+        }
+        let Some(name) = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+        else {
+            return Err(RsError::with_msg(format!("--out 错误: {}", out)).into());
+        };
+        let parts = vec![Part::Text(name)];
+        if literal_file_type(&parts)?.is_none() {
+            return Err(RsError::with_msg(format!(
+                "--out 的文件需要扩展名 caf、wav 或 flac: {}",
+                out
+            ))
+            .into());
+        }
+        check_container(self.container, &parts)?;
+        self.dir = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map(PathBuf::from);
+        self.parts = parts;
+        self.out_file = true;
+        Ok(())
+    }
+
+    /// --out 是文件
+    pub(super) fn is_out_file(&self) -> bool {
+        self.out_file
+    }
+
+    /// 模板的扩展名是 {ext} 或者 caf、wav、flac
+    pub(super) fn set_template(&mut self, template: &str) -> Result<()> {
+        let parts = parse(template)?;
+        check_container(self.container, &parts)?;
+        self.parts = parts;
+        Ok(())
+    }
+
+    /// --container 指定 {ext} 的格式，和模板中的扩展名必须一致
+    pub(super) fn set_container(&mut self, file_type: FileType) -> Result<()> {
+        check_container(Some(file_type), &self.parts)?;
+        self.container = Some(file_type);
+        Ok(())
+    }
+
+    /// 输出文件的格式
+    pub(super) fn file_type(&self) -> FileType {
+        literal_file_type(&self.parts)
+            .ok()
+            .flatten()
+            .or(self.container)
+            .unwrap_or_default()
+    }
+
+    pub(super) fn set_overwrite(&mut self, overwrite: bool) {
+        self.overwrite = overwrite;
+    }
+
+    /// 一个 stream 的输出路径，time 是开始录音的时间
+    pub(super) fn bind(
+        &self,
+        bundle: &str,
+        pid: AudioObjectId,
+        stream: &str,
+        time: SystemTime,
+    ) -> OutputPath {
+        OutputPath {
+            template: self.clone(),
+            // 路径中不能出现目录分隔符
+            bundle: bundle.replace(['/', '\\'], "_"),
+            pid,
+            stream: stream.to_string(),
+            time,
+        }
+    }
+
+    fn has(&self, var: Var) -> bool {
+        self.parts.contains(&Part::Var(var))
+    }
+}
+
+/// 确定了变量的模板，生成一个 stream 的所有文件的路径
+#[derive(Debug, Clone)]
+pub(super) struct OutputPath {
+    template: PathTemplate,
+    bundle: String,
+    pid: AudioObjectId,
+    // stream 的编号，manifest 是 session
+    stream: String,
+    time: SystemTime,
+}
+
+impl OutputPath {
+    pub(super) fn file_type(&self) -> FileType {
+        self.template.file_type()
+    }
+
+    /// 使用新的开始时间
+    pub(super) fn at(&self, time: SystemTime) -> OutputPath {
+        OutputPath {
+            time,
+            ..self.clone()
+        }
+    }
+
+    /// 不拆分时的文件
+    pub(super) fn file(&self) -> PathBuf {
+        self.render(self.time, None, self.file_type().extension())
+    }
+
+    /// 按静音拆分时 track 的文件，模板中没有 {track} 时追加编号
+    pub(super) fn track(&self, track: usize) -> PathBuf {
+        let path = self.render(self.time, Some(track), self.file_type().extension());
+        if self.template.has(Var::Track) {
+            path
+        } else {
+            append_to_stem(&path, &format!("-{:02}", track))
+        }
+    }
+
+    /// time 开始的分段的文件，模板中没有 {time} 时追加日期和时间
+    pub(super) fn segment(&self, time: SystemTime) -> PathBuf {
+        let path = self.render(time, None, self.file_type().extension());
+        if self.template.has(Var::Time) {
+            path
+        } else {
+            append_to_stem(&path, &format!("-{}", segment::timestamp(time)))
+        }
+    }
+
+    /// 和 time 开始的分段在同一个目录、由同一个模板生成的分段，例如之前的录音留下的分段，按文件名排列
+    /// 文件名中除了日期和时间都要和模板一致，所以模板中有 {pid} 时只能找到同一个进程的分段
+    pub(super) fn existing_segments(&self, time: SystemTime) -> Result<Vec<PathBuf>> {
+        let dir = match self.segment(time).parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        // 2000-01-01T00-00-00 和 2111-12-12T11-11-11 的每一位数字都不同，文件名中不同的位置是日期和时间
+        let name = |secs| {
+            let path = self.segment(UNIX_EPOCH + Duration::from_secs(secs));
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let (first, second) = (name(946_684_800), name(4_479_361_871));
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let matched = name.len() == first.len()
+                && name.bytes().zip(first.bytes().zip(second.bytes())).all(
+                    |(byte, (a, b))| match a == b {
+                        true => byte == a,
+                        false => byte.is_ascii_digit(),
+                    },
+                );
+            if matched && entry.file_type()?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// 拆分时的 cue 文件
+    pub(super) fn cue(&self) -> PathBuf {
+        self.render_other(EXT_CUE)
+    }
+
+    /// 整个录音的 manifest
+    pub(super) fn manifest(&self) -> PathBuf {
+        self.render_other(EXT_MANIFEST)
+    }
+
+    // 音频以外的文件，模板中没有 {ext} 时替换扩展名，避免和音频文件同名
+    fn render_other(&self, ext: &str) -> PathBuf {
+        let path = self.render(self.time, None, ext);
+        if self.template.has(Var::Ext) {
+            path
+        } else {
+            path.with_extension(ext)
+        }
+    }
+
+    /// 按 overwrite 处理已经存在的文件，返回可以创建的路径
+    /// written 是这次录音已经写入的文件，不会被覆盖
+    pub(super) fn resolve(&self, path: PathBuf, written: &[PathBuf]) -> Result<PathBuf> {
+        if !path.try_exists()? {
+            return Ok(path);
+        }
+        if self.template.overwrite && !written.contains(&path) {
+            fs::remove_file(&path)?;
+            return Ok(path);
+        }
+        (1..)
+            .map(|index| append_to_stem(&path, &format!("-{}", index)))
+            .find_map(|path| match path.try_exists() {
+                Ok(false) => Some(Ok(path)),
+                Ok(true) => None,
+                Err(error) => Some(Err(error.into())),
+            })
+            .unwrap_or_else(|| Err(RsError::with_msg("no available file name").into()))
+    }
+
+    // 没有 track 时 {track} 为空，同时去掉它和相邻变量之间的一个分隔符
+    fn render(&self, time: SystemTime, track: Option<usize>, ext: &str) -> PathBuf {
+        let mut name = String::new();
+        let mut skip_separator = false;
+        for part in self.template.parts.iter() {
+            match part {
+                Part::Text(text) => {
+                    let text = match skip_separator {
+                        true => text.strip_prefix(TRACK_SEPARATORS).unwrap_or(text),
+                        false => text,
+                    };
+                    skip_separator = false;
+                    name.push_str(text);
+                }
+                Part::Var(Var::Bundle) => name.push_str(&self.bundle),
+                Part::Var(Var::Pid) => name.push_str(&self.pid.to_string()),
+                Part::Var(Var::Stream) => name.push_str(&self.stream),
+                Part::Var(Var::Date) => name.push_str(&segment::utc_date(time)),
+                Part::Var(Var::Time) => name.push_str(&segment::utc_time(time)),
+                Part::Var(Var::Track) => match track {
+                    Some(track) => name.push_str(&format!("{:02}", track)),
+                    None if name.ends_with(TRACK_SEPARATORS) => {
+                        name.pop();
+                    }
+                    None => skip_separator = true,
+                },
+                Part::Var(Var::Ext) => name.push_str(ext),
+            }
+        }
+        match &self.template.dir {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        }
+    }
+}
+
+// 解析模板，检查变量名
+fn parse(template: &str) -> Result<Vec<Part>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(Part::Text(rest[..start].to_string()));
+        }
+        let Some(end) = rest[start..].find('}') else {
+            return Err(RsError::with_msg(format!("模板缺少 }}: {}", template)).into());
+        };
+        let var = match &rest[start + 1..start + end] {
+            "bundle" => Var::Bundle,
+            "pid" => Var::Pid,
+            "stream" => Var::Stream,
+            "date" => Var::Date,
+            "time" => Var::Time,
+            "track" => Var::Track,
+            "ext" => Var::Ext,
+            name => return Err(RsError::with_msg(format!("未知的模板变量: {{{}}}", name)).into()),
+        };
+        parts.push(Part::Var(var));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_string()));
+    }
+    if parts.is_empty() {
+        return Err(RsError::with_msg("模板为空").into());
+    }
+    Ok(parts)
+}
+
+// 模板以扩展名结束时的格式，以 {ext} 结束时是 None
+fn literal_file_type(parts: &[Part]) -> Result<Option<FileType>> {
+    let Some(Part::Text(text)) = parts.last() else {
+        return Ok(None);
+    };
+    let Some((_, ext)) = text.rsplit_once('.') else {
+        return Ok(None);
+    };
+    match FileType::from_extension(ext) {
+        Some(file_type) => Ok(Some(file_type)),
+        None => Err(RsError::with_msg(format!(
+            "不支持的文件格式: .{}，支持 caf、wav、flac，或者使用 {{ext}}",
+            ext
+        ))
+        .into()),
+    }
+}
+
+// 检查模板的扩展名，和 --container 必须一致
+fn check_container(container: Option<FileType>, parts: &[Part]) -> Result<()> {
+    if let (Some(container), Some(file_type)) = (container, literal_file_type(parts)?)
+        && container != file_type
+    {
+        return Err(RsError::with_msg(format!(
+            "--container {} 和模板的扩展名 .{} 不一致",
+            container.extension(),
+            file_type.extension()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+// 在扩展名之前追加
+fn append_to_stem(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}{}", stem, suffix),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_ext() {
+        let mut template = PathTemplate::default();
+        template.set_template("{stream}.{ext}").unwrap();
+        assert_eq!(template.file_type(), FileType::Caf);
+        template.set_template("{stream}.WAV").unwrap();
+        assert_eq!(template.file_type(), FileType::Wav);
+        template.set_template("{date}/{stream}.flac").unwrap();
+        assert_eq!(template.file_type(), FileType::Flac);
+        assert_eq!(
+            template
+                .set_template("{stream}.opus")
+                .err()
+                .unwrap()
+                .to_string(),
+            "不支持的文件格式: .opus，支持 caf、wav、flac，或者使用 {ext}"
+        );
+        // 失败时保留原来的模板
+        assert!(template.has(Var::Date));
+        // --container 决定 {ext}，和扩展名不一致时报错
+        assert!(template.set_container(FileType::Wav).is_err());
+        template.set_template("{stream}.{ext}").unwrap();
+        template.set_container(FileType::Wav).unwrap();
+        assert!(template.set_template("{stream}.caf").is_err());
+        assert_eq!(template.file_type(), FileType::Wav);
+        let output_path = template.bind("com.example", 42, "1", SystemTime::UNIX_EPOCH);
+        assert_eq!(output_path.file(), PathBuf::from("1.wav"));
+        assert_eq!(output_path.cue(), PathBuf::from("1.cue"));
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("{bundle}/{date}_{stream}.{ext}").unwrap(),
+            vec![
+                Part::Var(Var::Bundle),
+                Part::Text("/".to_string()),
+                Part::Var(Var::Date),
+                Part::Text("_".to_string()),
+                Part::Var(Var::Stream),
+                Part::Text(".".to_string()),
+                Part::Var(Var::Ext),
+            ]
+        );
+        assert_eq!(
+            parse("{name}.caf").err().unwrap().to_string(),
+            "未知的模板变量: {name}"
+        );
+        assert_eq!(
+            parse("{stream.caf").err().unwrap().to_string(),
+            "模板缺少 }: {stream.caf"
+        );
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let mut template = PathTemplate::default();
+        template.set_out("out").unwrap();
+        template
+            .set_template("{bundle}_{pid}_{stream}_{date}_{time}.{ext}")
+            .unwrap();
+        // 2026-10-18T14:00:00Z
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_792_332_000);
+        let output_path = template.bind("com.example/app", 42, "1", time);
+        assert_eq!(
+            output_path.file(),
+            PathBuf::from("out/com.example_app_42_1_2026-10-18_14-00-00.caf")
+        );
+        assert_eq!(
+            output_path.cue(),
+            PathBuf::from("out/com.example_app_42_1_2026-10-18_14-00-00.cue")
+        );
+        // 模板中没有 {track} 时在扩展名之前追加编号
+        assert_eq!(
+            output_path.track(3),
+            PathBuf::from("out/com.example_app_42_1_2026-10-18_14-00-00-03.caf")
+        );
+        template.set_template("{stream}-{track}.{ext}").unwrap();
+        let output_path = template.bind("com.example", 42, "1", time);
+        assert_eq!(output_path.track(3), PathBuf::from("out/1-03.caf"));
+        // 不拆分时 {track} 为空，不留下分隔符
+        assert_eq!(output_path.file(), PathBuf::from("out/1.caf"));
+        template.set_template("{track}_{stream}.{ext}").unwrap();
+        let output_path = template.bind("com.example", 42, "1", time);
+        assert_eq!(output_path.file(), PathBuf::from("out/1.caf"));
+    }
+
+    #[test]
+    fn test_render_without_ext() {
+        let mut template = PathTemplate::default();
+        template.set_out("out").unwrap();
+        template.set_template("{date}/{stream}.CAF").unwrap();
+        // 2026-10-18T14:00:00Z
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_792_332_000);
+        let output_path = template.bind("com.example", 42, "1", time);
+        assert_eq!(output_path.file(), PathBuf::from("out/2026-10-18/1.CAF"));
+        // cue 和 manifest 不能和音频文件同名
+        assert_eq!(output_path.cue(), PathBuf::from("out/2026-10-18/1.cue"));
+        assert_eq!(
+            output_path.manifest(),
+            PathBuf::from("out/2026-10-18/1.json")
+        );
+    }
+
+    #[test]
+    fn test_out_file() {
+        let mut template = PathTemplate::default();
+        template.set_out("out/take.FLAC").unwrap();
+        assert!(template.is_out_file());
+        assert_eq!(template.file_type(), FileType::Flac);
+        let output_path = template.bind("com.example", 42, "1", SystemTime::UNIX_EPOCH);
+        assert_eq!(output_path.file(), PathBuf::from("out/take.FLAC"));
+        assert_eq!(output_path.track(2), PathBuf::from("out/take-02.FLAC"));
+        assert_eq!(output_path.cue(), PathBuf::from("out/take.cue"));
+        // 模板中的变量不展开
+        let mut template = PathTemplate::default();
+        template.set_out("{stream}.wav").unwrap();
+        let output_path = template.bind("com.example", 42, "1", SystemTime::UNIX_EPOCH);
+        assert_eq!(output_path.file(), PathBuf::from("{stream}.wav"));
+        // 没有扩展名时是目录
+        let mut template = PathTemplate::default();
+        template.set_out("out").unwrap();
+        assert!(!template.is_out_file());
+        assert_eq!(
+            template.set_out("out/take.mp3").err().unwrap().to_string(),
+            "不支持的文件格式: .mp3，支持 caf、wav、flac，或者使用 {ext}"
+        );
+        template.set_container(FileType::Wav).unwrap();
+        assert!(template.set_out("out/take.flac").is_err());
+    }
+
+    #[test]
+    fn test_existing_segments() {
+        let dir = std::env::temp_dir().join(format!("resound-segments-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut template = PathTemplate::default();
+        template.set_out(dir.to_str().unwrap()).unwrap();
+        let output_path = template.bind("com.example", 42, "1", SystemTime::now());
+        assert!(
+            output_path
+                .existing_segments(SystemTime::now())
+                .unwrap()
+                .is_empty()
+        );
+        for name in [
+            "resound-1-2026-10-18T14-10-00.caf",
+            "resound-1-2026-10-18T14-00-00.caf",
+            // 其它 stream、格式和 resolve 追加编号的文件
+            "resound-2-2026-10-18T14-00-00.caf",
+            "resound-1-2026-10-18T14-00-00.wav",
+            "resound-1-2026-10-18T14-00-00-1.caf",
+            "resound-1-2026-10-18T14-00-00.cue",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(
+            output_path.existing_segments(SystemTime::now()).unwrap(),
+            vec![
+                dir.join("resound-1-2026-10-18T14-00-00.caf"),
+                dir.join("resound-1-2026-10-18T14-10-00.caf"),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_append_to_stem() {
+        assert_eq!(
+            append_to_stem(Path::new("dir/a.caf"), "-1"),
+            PathBuf::from("dir/a-1.caf")
+        );
+        assert_eq!(
+            append_to_stem(Path::new("dir/a.b.caf"), "-1"),
+            PathBuf::from("dir/a.b-1.caf")
+        );
+        assert_eq!(append_to_stem(Path::new("a"), "-1"), PathBuf::from("a-1"));
+    }
+
+    #[test]
+    fn test_resolve() {
+        let dir = std::env::temp_dir().join(format!("resound-template-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut template = PathTemplate::default();
+        template.set_out(dir.to_str().unwrap()).unwrap();
+        let output_path = template.bind("com.example", 42, "1", SystemTime::now());
+        let path = output_path.file();
+        assert_eq!(path, dir.join("resound-1.caf"));
+        // 不存在时直接使用
+        assert_eq!(output_path.resolve(path.clone(), &[]).unwrap(), path);
+        // 已经存在时追加编号，跳过已经存在的编号
+        fs::write(&path, "a").unwrap();
+        fs::write(dir.join("resound-1-1.caf"), "b").unwrap();
+        assert_eq!(
+            output_path.resolve(path.clone(), &[]).unwrap(),
+            dir.join("resound-1-2.caf")
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a");
+        // --overwrite 时删除已经存在的文件
+        template.set_overwrite(true);
+        let output_path = template.bind("com.example", 42, "1", SystemTime::now());
+        assert_eq!(output_path.resolve(path.clone(), &[]).unwrap(), path);
+        assert!(!path.exists());
+        // 这次录音已经写入的文件不会被覆盖
+        fs::write(&path, "c").unwrap();
+        assert_eq!(
+            output_path
+                .resolve(path.clone(), std::slice::from_ref(&path))
+                .unwrap(),
+            dir.join("resound-1-2.caf")
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "c");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
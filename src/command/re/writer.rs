@@ -0,0 +1,453 @@
+//! output file writer
+//! 一个 stream 的输出文件，按静音拆分时每个 track 一个文件
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use audio::{
+    AudioStreamBasicDescription, caf,
+    cue::{CueSheet, CueTrack},
+    dsp::{
+        Block,
+        convert::SampleFormat,
+        silence::{SilenceDetector, SilenceTrimmer, TrackSpan, TrackSplitter},
+    },
+    ext_audio_file::{AudioExtAudioFile, FileType},
+    flac,
+    format::StreamFormat,
+    segment::{self, Retention, Rotation, Segment},
+    wav,
+};
+
+use crate::rserror::Result;
+
+use super::template::OutputPath;
+
+// 元数据中的软件名称
+const VENDOR: &str = "resound";
+const TAG_ENCODER: &str = "encoding application";
+
+/// 按静音拆分的参数
+#[derive(Debug, Clone, Copy)]
+pub(super) struct SplitSpec {
+    /// dBFS
+    pub(super) threshold_db: f32,
+    /// 秒
+    pub(super) min_gap: f64,
+}
+
+/// 分段录音的参数，不能和按静音拆分同时使用
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct SegmentSpec {
+    pub(super) rotation: Rotation,
+    pub(super) retention: Retention,
+}
+
+// 分段录音的状态
+struct Segments {
+    spec: SegmentSpec,
+    // 当前分段已经写入的帧数和字节数
+    frames: u64,
+    bytes: u64,
+    // 已经结束、还没有删除的分段，按时间排列
+    closed: Vec<Segment>,
+}
+
+pub(super) struct TrackWriter {
+    // 生成每个文件的路径
+    output_path: OutputPath,
+    file_type: FileType,
+    // 写入的数据的格式，flac 文件中是压缩后的数据
+    file_format: AudioStreamBasicDescription,
+    sample_format: SampleFormat,
+    sample_rate: f64,
+    splitter: Option<TrackSplitter>,
+    spans: Vec<TrackSpan>,
+    // 去掉开始和结束的静音
+    trimmer: Option<SilenceTrimmer>,
+    segments: Option<Segments>,
+    // 关闭文件时写入元数据，另外加上文件开始和结束的时间
+    // caf 写入 info chunk，wav 写入 LIST/INFO 和 bext，flac 写入 Vorbis comment
+    tags: Vec<(String, String)>,
+    audio_ext_file: Option<AudioExtAudioFile>,
+    // audio_ext_file 开始写入的时间
+    file_start: SystemTime,
+    // audio_ext_file 已经写入的帧数
+    file_frames: u64,
+    // audio_ext_file 中的标记，关闭时写入
+    markers: Vec<caf::Marker>,
+    // audio_ext_file 对应的 track
+    track: usize,
+    paths: Vec<PathBuf>,
+    // 编码后的数据，重复使用，避免频繁分配内存
+    data: Vec<u8>,
+}
+
+impl TrackWriter {
+    /// 创建第一个文件
+    pub(super) fn create(
+        output_path: OutputPath,
+        file_format: &StreamFormat,
+        sample_format: SampleFormat,
+        split: Option<SplitSpec>,
+        trim_db: Option<f32>,
+        segment: Option<SegmentSpec>,
+        tags: Vec<(String, String)>,
+    ) -> Result<TrackWriter> {
+        let splitter = split.map(|split| {
+            let min_frames = (split.min_gap * file_format.sample_rate).round() as usize;
+            TrackSplitter::new(SilenceDetector::new(split.threshold_db, min_frames))
+        });
+        let mut writer = TrackWriter {
+            file_type: output_path.file_type(),
+            output_path,
+            file_format: file_format.to_basic_description(),
+            sample_format,
+            sample_rate: file_format.sample_rate,
+            splitter,
+            spans: Vec::new(),
+            trimmer: trim_db.map(SilenceTrimmer::new),
+            segments: segment.map(|spec| Segments {
+                spec,
+                frames: 0,
+                bytes: 0,
+                closed: Vec::new(),
+            }),
+            tags,
+            audio_ext_file: None,
+            file_start: SystemTime::now(),
+            file_frames: 0,
+            markers: Vec::new(),
+            track: 0,
+            paths: Vec::new(),
+            data: Vec::new(),
+        };
+        writer.seed_segments()?;
+        writer.open_track(1)?;
+        writer.remove_expired();
+        Ok(writer)
+    }
+
+    /// 所有已经创建的文件
+    pub(super) fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    // 关闭当前文件，创建 track 的文件
+    fn open_track(&mut self, track: usize) -> Result<()> {
+        self.close_file()?;
+        // 分段的文件名中是分段开始的时间
+        let path = match (&self.splitter, &self.segments) {
+            (Some(_), _) => self.output_path.track(track),
+            (None, Some(_)) => self.output_path.segment(SystemTime::now()),
+            (None, None) => self.output_path.file(),
+        };
+        let path = self.output_path.resolve(path, &self.paths)?;
+        let file_desc = self.file_type.file_format(&self.file_format)?;
+        let mut audio_ext_file =
+            AudioExtAudioFile::create_with_type(&path, self.file_type, &file_desc)?;
+        if self.file_type == FileType::Flac {
+            audio_ext_file.set_client_format(&self.file_format)?;
+        }
+        self.audio_ext_file = Some(audio_ext_file);
+        self.file_start = SystemTime::now();
+        self.file_frames = 0;
+        self.paths.push(path);
+        self.track = track;
+        Ok(())
+    }
+
+    /// 写入处理后的 block
+    pub(super) fn write(&mut self, block: &Block) -> Result<()> {
+        let Some(splitter) = self.splitter.as_mut() else {
+            return self.encode_and_write(&block.samples);
+        };
+        let mut spans = std::mem::take(&mut self.spans);
+        splitter.split(block, &mut spans);
+        let mut result = Ok(());
+        for span in spans.iter() {
+            if span.track != self.track
+                && let Err(error) = self.open_track(span.track)
+            {
+                result = Err(error);
+                break;
+            }
+            let samples = &block.samples
+                [span.frames.start * block.channels..span.frames.end * block.channels];
+            if let Err(error) = self.encode_and_write(samples) {
+                result = Err(error);
+                break;
+            }
+        }
+        self.spans = spans;
+        result
+    }
+
+    fn encode_and_write(&mut self, samples: &[f32]) -> Result<()> {
+        if self.audio_ext_file.is_none() {
+            return Ok(());
+        }
+        let channels = self.file_format.mChannelsPerFrame as usize;
+        let samples = match self.trimmer.as_mut() {
+            Some(trimmer) => trimmer.process(samples, channels),
+            None => samples,
+        };
+        if samples.is_empty() {
+            return Ok(());
+        }
+        if let Some(segments) = self.segments.as_ref() {
+            let elapsed = Duration::from_secs_f64(segments.frames as f64 / self.sample_rate);
+            if segments
+                .spec
+                .rotation
+                .should_rotate(elapsed, segments.bytes)
+            {
+                self.rotate()?;
+            }
+        }
+        self.data.clear();
+        self.sample_format.encode(samples, &mut self.data);
+        if let Some(audio_ext_file) = self.audio_ext_file.as_mut() {
+            audio_ext_file.write_interleaved(&self.data)?;
+            self.file_frames += (samples.len() / channels) as u64;
+        }
+        if let Some(segments) = self.segments.as_mut() {
+            segments.frames += (samples.len() / channels) as u64;
+            segments.bytes += self.data.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// 在当前文件中下一次写入的第 offset 帧加入标记，返回文件和帧位置
+    pub(super) fn mark(&mut self, label: &str, offset: u64) -> Option<(PathBuf, u64)> {
+        self.audio_ext_file.as_ref()?;
+        let path = self.paths.last()?.clone();
+        let frame = self.file_frames + offset;
+        self.markers.push(caf::Marker {
+            frame,
+            label: label.to_string(),
+        });
+        Some((path, frame))
+    }
+
+    /// 把当前文件缓存的数据写入磁盘
+    pub(super) fn flush(&mut self) -> Result<()> {
+        match self.audio_ext_file.as_mut() {
+            Some(audio_ext_file) => Ok(audio_ext_file.flush()?),
+            None => Ok(()),
+        }
+    }
+
+    // 之前的录音留下的分段也按保留策略删除，结束时间是文件的修改时间
+    fn seed_segments(&mut self) -> Result<()> {
+        let Some(segments) = self.segments.as_mut() else {
+            return Ok(());
+        };
+        for path in self.output_path.existing_segments(SystemTime::now())? {
+            let metadata = fs::metadata(&path)?;
+            segments.closed.push(Segment {
+                path,
+                end: metadata.modified()?,
+                size: metadata.len(),
+            });
+        }
+        segments.closed.sort_by_key(|segment| segment.end);
+        Ok(())
+    }
+
+    // 开始新的分段，删除超过保留策略的分段
+    fn rotate(&mut self) -> Result<()> {
+        self.open_track(self.track)?;
+        self.remove_expired();
+        Ok(())
+    }
+
+    fn remove_expired(&mut self) {
+        let Some(segments) = self.segments.as_mut() else {
+            return;
+        };
+        let expired = segments
+            .spec
+            .retention
+            .expired(&segments.closed, 0, SystemTime::now());
+        for segment in segments.closed.drain(..expired) {
+            if let Err(error) = fs::remove_file(&segment.path) {
+                eprintln!("remove {} fail: {}", segment.path.display(), error);
+            }
+            self.paths.retain(|path| *path != segment.path);
+        }
+    }
+
+    // 关闭当前文件，需要时去掉结尾的静音，然后写入元数据
+    // caf 的 info chunk 和 wav 的 LIST、bext 在 data chunk 之后，所以要先截断
+    // flac 不能截断，创建时已经拒绝 --trim-silence
+    fn close_file(&mut self) -> Result<()> {
+        let Some(audio_ext_file) = self.audio_ext_file.take() else {
+            return Ok(());
+        };
+        drop(audio_ext_file);
+        // 文件最终的帧数
+        let mut frames = self.file_frames;
+        if let Some(trimmer) = self.trimmer.as_mut() {
+            let (written, sound_end) = (trimmer.frames(), trimmer.sound_end());
+            trimmer.next_file();
+            if sound_end < written
+                && let Some(path) = self.paths.last()
+            {
+                let bytes_per_frame = self.file_format.mBytesPerFrame;
+                match self.file_type {
+                    FileType::Caf => caf::truncate_frames(path, sound_end, bytes_per_frame)?,
+                    FileType::Wav => wav::truncate_frames(path, sound_end, bytes_per_frame)?,
+                    FileType::Flac => {}
+                }
+                frames = frames.min(sound_end);
+            }
+        }
+        if let Some(path) = self.paths.last() {
+            // 截断后超出文件的标记放在文件末尾
+            let markers = std::mem::take(&mut self.markers)
+                .into_iter()
+                .map(|marker| caf::Marker {
+                    frame: marker.frame.min(frames),
+                    ..marker
+                })
+                .collect::<Vec<_>>();
+            match self.file_type {
+                _ if markers.is_empty() => {}
+                FileType::Caf => caf::write_markers(path, &markers)?,
+                FileType::Wav => wav::write_markers(path, &markers)?,
+                // flac 的 CUESHEET 只能用于 CD，标记只写入 manifest
+                FileType::Flac => {}
+            }
+            let mut tags = self.tags.clone();
+            tags.push((
+                "start time".to_string(),
+                segment::utc_iso8601(self.file_start),
+            ));
+            tags.push((
+                "end time".to_string(),
+                segment::utc_iso8601(SystemTime::now()),
+            ));
+            match self.file_type {
+                FileType::Caf => caf::write_info(path, &tags)?,
+                FileType::Wav => self.write_wav_tags(path, &tags)?,
+                FileType::Flac => flac::write_vorbis_comment(path, VENDOR, &vorbis_comment(&tags))?,
+            }
+        }
+        if let Some(segments) = self.segments.as_mut()
+            && let Some(path) = self.paths.last()
+        {
+            segments.closed.push(Segment {
+                path: path.clone(),
+                end: SystemTime::now(),
+                size: fs::metadata(path).map(|metadata| metadata.len())?,
+            });
+            segments.frames = 0;
+            segments.bytes = 0;
+        }
+        Ok(())
+    }
+
+    // LIST/INFO 中软件名称和日期单独保存，其余写入注释，bext 的描述也使用注释
+    fn write_wav_tags(&self, path: &Path, tags: &[(String, String)]) -> Result<()> {
+        let comment = tags
+            .iter()
+            .filter(|(key, _)| key != TAG_ENCODER)
+            .map(|(key, value)| format!("{}: {}", key, value))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let encoder = tags
+            .iter()
+            .find(|(key, _)| key == TAG_ENCODER)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        let date = segment::utc_date(self.file_start);
+        let mut info = vec![(*b"ICRD", date.clone()), (*b"ICMT", comment.clone())];
+        if !encoder.is_empty() {
+            info.insert(0, (*b"ISFT", encoder.clone()));
+        }
+        wav::write_info(path, &info)?;
+        // 从 UTC 0 点开始的帧数
+        let secs_of_day = self
+            .file_start
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64() % 86400.0)
+            .unwrap_or_default();
+        // EBU R98 只定义了单声道和立体声，多声道不写 M
+        let mode = match self.file_format.mChannelsPerFrame {
+            1 => "M=mono,",
+            2 => "M=stereo,",
+            _ => "",
+        };
+        let bext = wav::Bext {
+            description: comment,
+            originator: VENDOR.to_string(),
+            originator_reference: String::new(),
+            origination_date: date,
+            origination_time: segment::utc_time(self.file_start).replace('-', ":"),
+            time_reference: (secs_of_day * self.sample_rate) as u64,
+            coding_history: format!(
+                "A=PCM,F={},W={},{}T={}\r\n",
+                self.sample_rate, self.file_format.mBitsPerChannel, mode, encoder
+            ),
+        };
+        wav::write_bext(path, &bext)?;
+        Ok(())
+    }
+
+    /// 关闭文件，拆分时写入 cue
+    pub(super) fn finish(&mut self) -> Result<()> {
+        self.close_file()?;
+        let Some(splitter) = self.splitter.as_mut() else {
+            return Ok(());
+        };
+        splitter.finish();
+        let tracks = splitter.tracks().to_vec();
+        let cue_path = self
+            .output_path
+            .resolve(self.output_path.cue(), &self.paths)?;
+        let mut sheet = CueSheet::new();
+        sheet.title = cue_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned());
+        // 拆分时不会删除文件，第 i 个文件是第 i 个 track
+        for (range, file) in tracks.iter().zip(self.paths.iter()) {
+            let to_duration =
+                |frames: u64| Duration::from_secs_f64(frames as f64 / self.sample_rate);
+            sheet.push(CueTrack {
+                file: file
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                title: None,
+                start: to_duration(range.start),
+                duration: range.end.map(|end| to_duration(end - range.start)),
+            });
+        }
+        fs::write(cue_path, sheet.render())?;
+        Ok(())
+    }
+
+    /// 删除已经创建的文件
+    pub(super) fn remove_files(&mut self) {
+        self.audio_ext_file = None;
+        self.paths.iter().for_each(|path| {
+            let _ = fs::remove_file(path);
+        });
+    }
+}
+
+// Vorbis comment 的 key 习惯使用大写，软件名称和日期使用通用的 ENCODER 和 DATE
+fn vorbis_comment(tags: &[(String, String)]) -> Vec<(String, String)> {
+    tags.iter()
+        .map(|(key, value)| {
+            let key = match key.as_str() {
+                TAG_ENCODER => "ENCODER".to_string(),
+                "start time" => "DATE".to_string(),
+                key => key.to_uppercase().replace(' ', "_"),
+            };
+            (key, value.clone())
+        })
+        .collect()
+}
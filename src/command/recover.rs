@@ -0,0 +1,61 @@
+//! recover audio file
+//! 修复录音时异常退出、没有正常关闭的文件，按扩展名支持 caf、wav、flac
+
+use std::borrow::Cow;
+
+use audio::{caf, ext_audio_file::FileType, flac, wav};
+
+use crate::interactive::{PROMPT_DEFAULT_COW, print_list};
+use crate::rserror::{Result, RsError};
+
+use super::registry::{Arg, Args, Command, Complete, Kind};
+
+pub(super) const COMMAND: Command = Command::new(
+    "recover",
+    "repair a caf, wav or flac file left unfinished by a crash, drop incomplete data",
+)
+.args(&[Arg::positional("file", Kind::Text).complete(Complete::File)])
+.run(run);
+
+fn run(args: &Args) -> Result<Cow<'static, str>> {
+    let Some(path) = args.text("file") else {
+        return Err(RsError::with_msg("缺少参数: file").into());
+    };
+    let recovery = match FileType::from_path(path) {
+        Some(FileType::Caf) => caf::recover(path)?,
+        Some(FileType::Wav) => wav::recover(path)?,
+        Some(FileType::Flac) => flac::recover(path)?,
+        None => {
+            return Err(RsError::with_msg(format!(
+                "不支持的文件格式: {}，支持 caf、wav、flac",
+                path
+            ))
+            .into());
+        }
+    };
+    print_recovery(path, &recovery);
+    Ok(PROMPT_DEFAULT_COW)
+}
+
+fn print_recovery(path: &str, recovery: &caf::Recovery) {
+    let list = [
+        [(Cow::Borrowed("file"), Cow::Borrowed(path))],
+        [(
+            Cow::Borrowed("status"),
+            Cow::Borrowed(if recovery.repaired {
+                "repaired"
+            } else {
+                "ok, nothing to repair"
+            }),
+        )],
+        [(
+            Cow::Borrowed("frames"),
+            Cow::from(recovery.frames.to_string()),
+        )],
+        [(
+            Cow::Borrowed("removed"),
+            Cow::from(format!("{} bytes", recovery.removed)),
+        )],
+    ];
+    print_list(&list);
+}
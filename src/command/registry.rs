@@ -0,0 +1,773 @@
+//! command registry
+//! 每个命令声明名称、参数、类型、默认值和帮助，解析、错误提示、help 和补全都由同一份定义生成
+//! 参数分为位置参数和以 -- 开始的选项，选项可以出现在任意位置
+
+use std::borrow::Cow;
+use std::fmt;
+use std::time::Duration;
+
+use crate::interactive::print_list;
+use crate::rserror::{Result, RsError};
+
+/// 命令的处理函数，返回提示信息，执行失败时返回错误
+pub(super) type Handler = fn(&Args) -> Result<Cow<'static, str>>;
+
+/// 参数的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Kind {
+    Text,
+    Integer,
+    Number,
+    /// 30s、10m、1h，没有单位时是秒
+    Duration,
+    /// 512KB、100MB、2GB，没有单位时是字节
+    Size,
+    /// 没有值的选项
+    Flag,
+    /// 剩余的所有参数，以空格连接
+    Rest,
+}
+
+/// 参数值的补全方式，choices 不为空时使用 choices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Complete {
+    None,
+    /// 进程 id
+    Process,
+    /// 进程的 bundle id
+    Bundle,
+    /// 文件或目录
+    File,
+}
+
+/// 一个参数
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Arg {
+    /// 选项以 -- 开始
+    pub(super) name: &'static str,
+    pub(super) kind: Kind,
+    pub(super) required: bool,
+    /// 没有指定时使用的值，按 kind 解析
+    pub(super) default: Option<&'static str>,
+    /// 可以使用的值，为空时不限制
+    pub(super) choices: &'static [&'static str],
+    /// usage 中显示的值
+    pub(super) value: &'static str,
+    pub(super) complete: Complete,
+}
+
+impl Arg {
+    /// 必须的位置参数
+    pub(super) const fn positional(name: &'static str, kind: Kind) -> Arg {
+        Arg {
+            name,
+            kind,
+            required: true,
+            default: None,
+            choices: &[],
+            value: name,
+            complete: Complete::None,
+        }
+    }
+
+    /// 带值的选项，value 是 usage 中的示例
+    pub(super) const fn option(name: &'static str, kind: Kind, value: &'static str) -> Arg {
+        Arg {
+            name,
+            kind,
+            required: false,
+            default: None,
+            choices: &[],
+            value,
+            complete: Complete::None,
+        }
+    }
+
+    /// 没有值的选项
+    pub(super) const fn flag(name: &'static str) -> Arg {
+        Arg::option(name, Kind::Flag, "")
+    }
+
+    pub(super) const fn optional(self) -> Arg {
+        Arg {
+            required: false,
+            ..self
+        }
+    }
+
+    pub(super) const fn default(self, default: &'static str) -> Arg {
+        Arg {
+            default: Some(default),
+            required: false,
+            ..self
+        }
+    }
+
+    pub(super) const fn choices(self, choices: &'static [&'static str]) -> Arg {
+        Arg { choices, ..self }
+    }
+
+    pub(super) const fn complete(self, complete: Complete) -> Arg {
+        Arg { complete, ..self }
+    }
+
+    fn is_option(&self) -> bool {
+        self.name.starts_with("--")
+    }
+
+    // usage 中的写法
+    fn usage(&self) -> String {
+        let value = match (self.choices.is_empty(), self.kind) {
+            (false, _) => self.choices.join("|"),
+            (true, Kind::Rest) => format!("{}...", self.value),
+            _ => self.value.to_string(),
+        };
+        let text = match (self.is_option(), self.kind) {
+            (true, Kind::Flag) => self.name.to_string(),
+            (true, _) => format!("{} {}", self.name, value),
+            (false, _) => value,
+        };
+        if self.required {
+            text
+        } else {
+            format!("[{}]", text)
+        }
+    }
+
+    fn parse(&self, value: &str) -> Result<Value> {
+        if !self.choices.is_empty() && !self.choices.contains(&value) {
+            return Err(RsError::with_msg(format!(
+                "{} 只能是 {}: {}",
+                self.name,
+                self.choices.join(", "),
+                value
+            ))
+            .into());
+        }
+        let value = match self.kind {
+            Kind::Text | Kind::Rest => Value::Text(value.to_string()),
+            Kind::Integer => value
+                .parse::<i64>()
+                .map(Value::Integer)
+                .map_err(|_| RsError::with_msg(format!("{} 需要整数: {}", self.name, value)))?,
+            Kind::Number => match value.parse::<f64>() {
+                Ok(number) if number.is_finite() => Value::Number(number),
+                _ => {
+                    return Err(
+                        RsError::with_msg(format!("{} 需要数字: {}", self.name, value)).into(),
+                    );
+                }
+            },
+            Kind::Duration => Value::Duration(parse_duration(value)?),
+            Kind::Size => Value::Size(parse_size(value)?),
+            Kind::Flag => Value::Flag,
+        };
+        Ok(value)
+    }
+}
+
+/// 一个命令，有子命令时自己不能执行
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Command {
+    pub(super) name: &'static str,
+    pub(super) help: &'static str,
+    pub(super) args: &'static [Arg],
+    /// 多个命令共用的选项
+    pub(super) common: &'static [Arg],
+    pub(super) subcommands: &'static [Command],
+    pub(super) run: Option<Handler>,
+}
+
+impl Command {
+    pub(super) const fn new(name: &'static str, help: &'static str) -> Command {
+        Command {
+            name,
+            help,
+            args: &[],
+            common: &[],
+            subcommands: &[],
+            run: None,
+        }
+    }
+
+    pub(super) const fn args(self, args: &'static [Arg]) -> Command {
+        Command { args, ..self }
+    }
+
+    pub(super) const fn common(self, common: &'static [Arg]) -> Command {
+        Command { common, ..self }
+    }
+
+    pub(super) const fn subcommands(self, subcommands: &'static [Command]) -> Command {
+        Command {
+            subcommands,
+            ..self
+        }
+    }
+
+    pub(super) const fn run(self, run: Handler) -> Command {
+        Command {
+            run: Some(run),
+            ..self
+        }
+    }
+
+    fn all_args(&self) -> impl Iterator<Item = &'static Arg> + use<> {
+        self.args.iter().chain(self.common.iter())
+    }
+
+    fn find_option(&self, name: &str) -> Option<&'static Arg> {
+        self.all_args()
+            .find(|arg| arg.is_option() && arg.name == name)
+    }
+
+    /// 完整的用法，prefix 是上级命令
+    pub(super) fn usage(&self, prefix: &[&str]) -> String {
+        let mut parts = prefix
+            .iter()
+            .map(|part| part.to_string())
+            .collect::<Vec<_>>();
+        parts.push(self.name.to_string());
+        if !self.subcommands.is_empty() {
+            parts.push(
+                self.subcommands
+                    .iter()
+                    .map(|command| command.name)
+                    .collect::<Vec<_>>()
+                    .join("|"),
+            );
+        }
+        // 位置参数在前
+        parts.extend(
+            self.all_args()
+                .filter(|arg| !arg.is_option())
+                .map(Arg::usage),
+        );
+        parts.extend(
+            self.all_args()
+                .filter(|arg| arg.is_option())
+                .map(Arg::usage),
+        );
+        parts.join(" ")
+    }
+}
+
+/// 解析后的参数值
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Value {
+    Text(String),
+    Integer(i64),
+    Number(f64),
+    Duration(Duration),
+    Size(u64),
+    Flag,
+}
+
+/// 一条命令的参数，按参数名读取
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(super) struct Args {
+    values: Vec<(&'static str, Value)>,
+}
+
+impl Args {
+    // 同一个选项出现多次时使用最后一个
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(arg, _)| *arg == name)
+            .map(|(_, value)| value)
+    }
+
+    pub(super) fn text(&self, name: &str) -> Option<&str> {
+        match self.get(name)? {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// 整数，超出 T 的范围时返回错误
+    pub(super) fn integer<T: TryFrom<i64>>(&self, name: &str) -> Result<Option<T>> {
+        match self.get(name) {
+            Some(Value::Integer(integer)) => T::try_from(*integer)
+                .map(Some)
+                .map_err(|_| RsError::with_msg(format!("{} 超出范围: {}", name, integer)).into()),
+            _ => Ok(None),
+        }
+    }
+
+    pub(super) fn number(&self, name: &str) -> Option<f64> {
+        match self.get(name)? {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub(super) fn duration(&self, name: &str) -> Option<Duration> {
+        match self.get(name)? {
+            Value::Duration(duration) => Some(*duration),
+            _ => None,
+        }
+    }
+
+    pub(super) fn size(&self, name: &str) -> Option<u64> {
+        match self.get(name)? {
+            Value::Size(size) => Some(*size),
+            _ => None,
+        }
+    }
+
+    pub(super) fn flag(&self, name: &str) -> bool {
+        self.get(name) == Some(&Value::Flag)
+    }
+}
+
+/// 解析的结果
+#[derive(Debug)]
+pub(super) enum Parsed {
+    /// 执行命令
+    Run(&'static Command, Args),
+    /// 显示 commands 的帮助，prefix 是上级命令
+    Help(&'static [Command], Vec<&'static str>),
+}
+
+/// 解析错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum ParseError {
+    /// 没有这个命令
+    Unknown(String),
+    /// 参数错误，带上命令的用法
+    Invalid { message: String, usage: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Unknown(command) => write!(f, "unsupported command: {}", command),
+            ParseError::Invalid { message, usage } => write!(f, "{}. usage: {}", message, usage),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// 在 commands 中查找 tokens 对应的命令并解析参数
+/// 命令位置上的 help 显示这一级的帮助
+pub(super) fn parse(
+    commands: &'static [Command],
+    tokens: &[&str],
+) -> std::result::Result<Parsed, ParseError> {
+    let mut commands = commands;
+    let mut prefix = Vec::new();
+    let mut tokens = tokens.iter();
+    loop {
+        let token = tokens.next().copied();
+        if token == Some("help") {
+            return Ok(Parsed::Help(commands, prefix));
+        }
+        let Some(command) =
+            token.and_then(|token| commands.iter().find(|command| command.name == token))
+        else {
+            let mut command = prefix.clone();
+            command.extend(token);
+            return Err(ParseError::Unknown(command.join(" ")));
+        };
+        if command.subcommands.is_empty() {
+            let rest = tokens.copied().collect::<Vec<_>>();
+            if rest.first() == Some(&"help") {
+                return Ok(Parsed::Help(std::slice::from_ref(command), prefix));
+            }
+            return parse_args(command, &rest)
+                .map(|args| Parsed::Run(command, args))
+                .map_err(|error| ParseError::Invalid {
+                    message: error.to_string(),
+                    usage: command.usage(&prefix),
+                });
+        }
+        prefix.push(command.name);
+        commands = command.subcommands;
+    }
+}
+
+// 解析一个命令的参数
+fn parse_args(command: &Command, tokens: &[&str]) -> Result<Args> {
+    let mut args = Args::default();
+    let mut positionals = command.all_args().filter(|arg| !arg.is_option());
+    let mut tokens = tokens.iter();
+    while let Some(token) = tokens.next() {
+        if token.starts_with("--") {
+            let Some(option) = command.find_option(token) else {
+                return Err(RsError::with_msg(format!("未知参数: {}", token)).into());
+            };
+            let value = match option.kind {
+                Kind::Flag => Value::Flag,
+                _ => match tokens.next() {
+                    Some(value) => option.parse(value)?,
+                    None => return Err(RsError::with_msg(format!("{} 缺少参数", token)).into()),
+                },
+            };
+            args.values.push((option.name, value));
+            continue;
+        }
+        let Some(positional) = positionals.next() else {
+            return Err(RsError::with_msg(format!("多余的参数: {}", token)).into());
+        };
+        let value = match positional.kind {
+            Kind::Rest => std::iter::once(token)
+                .chain(tokens.by_ref())
+                .copied()
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => token.to_string(),
+        };
+        args.values
+            .push((positional.name, positional.parse(&value)?));
+    }
+    for arg in command.all_args() {
+        if args.get(arg.name).is_some() {
+            continue;
+        }
+        match arg.default {
+            Some(default) => args.values.push((arg.name, arg.parse(default)?)),
+            None if arg.required => {
+                return Err(RsError::with_msg(format!("缺少参数: {}", arg.name)).into());
+            }
+            None => {}
+        }
+    }
+    Ok(args)
+}
+
+/// 显示 commands 的帮助，prefix 不为空时显示完整的用法
+pub(super) fn print_help(commands: &[Command], prefix: &[&str]) {
+    let mut list = vec![[(Cow::Borrowed("help"), Cow::Borrowed("show this"))]];
+    for command in commands {
+        let help = if prefix.is_empty() {
+            Cow::Borrowed(command.help)
+        } else {
+            Cow::from(format!(
+                "{}. usage: {}",
+                command.help,
+                command.usage(prefix)
+            ))
+        };
+        list.push([(Cow::Borrowed(command.name), help)]);
+    }
+    print_list(&list);
+}
+
+/// 补全 line 的最后一个词，返回可以使用的词
+/// line 以空格结束时补全新的词；values 返回 Complete 对应的、以第二个参数开始的值
+pub(super) fn complete<F>(commands: &'static [Command], line: &str, values: F) -> Vec<String>
+where
+    F: Fn(Complete, &str) -> Vec<String>,
+{
+    let mut tokens = line.split_whitespace().collect::<Vec<_>>();
+    let current = match line.ends_with(char::is_whitespace) || line.is_empty() {
+        true => "",
+        false => tokens.pop().unwrap_or_default(),
+    };
+    let mut commands = commands;
+    let mut tokens = tokens.into_iter();
+    let candidates = loop {
+        let Some(token) = tokens.next() else {
+            // 命令的位置
+            break std::iter::once("help")
+                .chain(commands.iter().map(|command| command.name))
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+        };
+        let Some(command) = commands.iter().find(|command| command.name == token) else {
+            return Vec::new();
+        };
+        if command.subcommands.is_empty() {
+            break complete_args(command, tokens.as_slice(), current, values);
+        }
+        commands = command.subcommands;
+    };
+    candidates
+        .into_iter()
+        .filter(|candidate| candidate.starts_with(current))
+        .collect()
+}
+
+// 补全参数：前一个词是带值的选项时补全选项的值，
+// 还有位置参数并且当前的词不是选项时补全位置参数的值，否则补全还没有使用的选项
+fn complete_args<F>(command: &Command, tokens: &[&str], current: &str, values: F) -> Vec<String>
+where
+    F: Fn(Complete, &str) -> Vec<String>,
+{
+    let arg_values = |arg: &Arg| match arg.choices.is_empty() {
+        true => values(arg.complete, current),
+        false => arg
+            .choices
+            .iter()
+            .map(|choice| choice.to_string())
+            .collect(),
+    };
+    // 已经输入的位置参数数量，跳过选项的值
+    let mut positionals = 0;
+    let mut tokens_iter = tokens.iter();
+    while let Some(token) = tokens_iter.next() {
+        match command.find_option(token) {
+            Some(option) if option.kind == Kind::Flag => {}
+            Some(option) => {
+                if tokens_iter.next().is_none() {
+                    // 正在输入选项的值
+                    return arg_values(option);
+                }
+            }
+            None => positionals += 1,
+        }
+    }
+    let positional = command
+        .all_args()
+        .filter(|arg| !arg.is_option())
+        .nth(positionals);
+    if let Some(positional) = positional
+        && positional.kind != Kind::Rest
+        && !current.starts_with('-')
+    {
+        return arg_values(positional);
+    }
+    command
+        .all_args()
+        .filter(|arg| arg.is_option() && !tokens.contains(&arg.name))
+        .map(|arg| arg.name.to_string())
+        .collect()
+}
+
+/// 解析时间：30s、10m、1h，没有单位时是秒
+pub(super) fn parse_duration(value: &str) -> Result<Duration> {
+    let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let scale = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(RsError::with_msg(format!("时间错误: {}", value)).into()),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => {
+            Ok(Duration::from_secs_f64(number * scale))
+        }
+        _ => Err(RsError::with_msg(format!("时间错误: {}", value)).into()),
+    }
+}
+
+/// 解析大小：512KB、100MB、2GB，没有单位时是字节
+pub(super) fn parse_size(value: &str) -> Result<u64> {
+    let (number, unit) = match value.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => value.split_at(index),
+        None => (value, "B"),
+    };
+    let scale = match unit.to_ascii_uppercase().as_str() {
+        "B" => 1.0,
+        "KB" | "K" => 1024.0,
+        "MB" | "M" => 1024.0 * 1024.0,
+        "GB" | "G" => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(RsError::with_msg(format!("大小错误: {}", value)).into()),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok((number * scale) as u64),
+        _ => Err(RsError::with_msg(format!("大小错误: {}", value)).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(_: &Args) -> Result<Cow<'static, str>> {
+        Ok(Cow::Borrowed("ok"))
+    }
+
+    const COMMON: &[Arg] = &[
+        Arg::option("--format", Kind::Text, "s16").choices(&["s16", "f32"]),
+        Arg::flag("--overwrite"),
+    ];
+
+    const COMMANDS: &[Command] = &[
+        Command::new("quit", "quit"),
+        Command::new("re", "record").subcommands(&[
+            Command::new("start", "start record")
+                .args(&[
+                    Arg::positional("process_id", Kind::Integer).complete(Complete::Process),
+                    Arg::option("--window", Kind::Duration, "60s").default("60s"),
+                    Arg::option("--max-total", Kind::Size, "2GB"),
+                ])
+                .common(COMMON)
+                .run(ok),
+            Command::new("mark", "add marker")
+                .args(&[Arg::positional("label", Kind::Rest).optional()])
+                .run(ok),
+            Command::new("meter", "show meter")
+                .args(&[Arg::positional("seconds", Kind::Number).optional()])
+                .run(ok),
+        ]),
+    ];
+
+    fn run(tokens: &[&str]) -> Args {
+        match parse(COMMANDS, tokens) {
+            Ok(Parsed::Run(_, args)) => args,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn invalid(tokens: &[&str]) -> String {
+        match parse(COMMANDS, tokens) {
+            Err(ParseError::Invalid { message, .. }) => message,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let args = run(&["re", "start", "--format", "f32", "42", "--overwrite"]);
+        assert_eq!(args.integer::<u32>("process_id").unwrap(), Some(42));
+        assert_eq!(args.text("--format"), Some("f32"));
+        assert!(args.flag("--overwrite"));
+        // 默认值
+        assert_eq!(args.duration("--window"), Some(Duration::from_secs(60)));
+        assert_eq!(args.size("--max-total"), None);
+        let args = run(&["re", "start", "1", "--window", "2m", "--max-total", "1KB"]);
+        assert_eq!(args.duration("--window"), Some(Duration::from_secs(120)));
+        assert_eq!(args.size("--max-total"), Some(1024));
+        assert!(!args.flag("--overwrite"));
+        // 剩余的参数
+        let args = run(&["re", "mark", "chorus", "--starts", "here"]);
+        assert_eq!(args.text("label"), Some("chorus --starts here"));
+        assert_eq!(run(&["re", "mark"]).text("label"), None);
+        assert_eq!(run(&["re", "meter", "-1.5"]).number("seconds"), Some(-1.5));
+        // 同一个选项使用最后一个
+        let args = run(&["re", "start", "1", "--format", "s16", "--format", "f32"]);
+        assert_eq!(args.text("--format"), Some("f32"));
+        assert!(args.integer::<u8>("process_id").unwrap().is_some());
+        let args = run(&["re", "start", "300"]);
+        assert!(args.integer::<u8>("process_id").is_err());
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert_eq!(
+            parse(COMMANDS, &["record"]).unwrap_err(),
+            ParseError::Unknown("record".to_string())
+        );
+        assert_eq!(
+            parse(COMMANDS, &["re"]).unwrap_err(),
+            ParseError::Unknown("re".to_string())
+        );
+        assert_eq!(
+            parse(COMMANDS, &["re", "pause"]).unwrap_err(),
+            ParseError::Unknown("re pause".to_string())
+        );
+        assert_eq!(invalid(&["re", "start"]), "缺少参数: process_id");
+        assert_eq!(invalid(&["re", "start", "x"]), "process_id 需要整数: x");
+        assert_eq!(invalid(&["re", "start", "1", "2"]), "多余的参数: 2");
+        assert_eq!(
+            invalid(&["re", "start", "1", "--rate", "8"]),
+            "未知参数: --rate"
+        );
+        assert_eq!(
+            invalid(&["re", "start", "1", "--window"]),
+            "--window 缺少参数"
+        );
+        assert_eq!(
+            invalid(&["re", "start", "1", "--window", "1d"]),
+            "时间错误: 1d"
+        );
+        assert_eq!(
+            invalid(&["re", "start", "1", "--format", "s8"]),
+            "--format 只能是 s16, f32: s8"
+        );
+        let error = parse(COMMANDS, &["re", "meter", "x"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "seconds 需要数字: x. usage: re meter [seconds]"
+        );
+    }
+
+    #[test]
+    fn test_help() {
+        match parse(COMMANDS, &["help"]) {
+            Ok(Parsed::Help(commands, prefix)) => {
+                assert_eq!(commands.len(), 2);
+                assert!(prefix.is_empty());
+            }
+            other => panic!("{:?}", other),
+        }
+        match parse(COMMANDS, &["re", "help"]) {
+            Ok(Parsed::Help(commands, prefix)) => {
+                assert_eq!(commands.len(), 3);
+                assert_eq!(prefix, vec!["re"]);
+            }
+            other => panic!("{:?}", other),
+        }
+        match parse(COMMANDS, &["re", "start", "help"]) {
+            Ok(Parsed::Help(commands, _)) => assert_eq!(commands[0].name, "start"),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            COMMANDS[1].subcommands[0].usage(&["re"]),
+            "re start process_id [--window 60s] [--max-total 2GB] [--format s16|f32] [--overwrite]"
+        );
+        assert_eq!(COMMANDS[1].usage(&[]), "re start|mark|meter");
+        assert_eq!(
+            COMMANDS[1].subcommands[1].usage(&["re"]),
+            "re mark [label...]"
+        );
+    }
+
+    // 进程 id 补全为 1、12，其它没有补全
+    fn values(complete: Complete, prefix: &str) -> Vec<String> {
+        match complete {
+            Complete::Process => vec!["1".to_string(), "12".to_string()],
+            _ => vec![format!("{}?", prefix)],
+        }
+    }
+
+    fn complete_line(line: &str) -> Vec<String> {
+        complete(COMMANDS, line, values)
+    }
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(complete_line(""), vec!["help", "quit", "re"]);
+        assert_eq!(complete_line("q"), vec!["quit"]);
+        assert_eq!(complete_line("re m"), vec!["mark", "meter"]);
+        // 位置参数的值
+        assert_eq!(complete_line("re start "), vec!["1", "12"]);
+        assert_eq!(complete_line("re start 1"), vec!["1", "12"]);
+        assert_eq!(
+            complete_line("re start --overwrite --format s16 1"),
+            vec!["1", "12"]
+        );
+        assert_eq!(complete_line("re start -"), complete_line("re start 1 -"));
+        assert_eq!(
+            complete_line("re start 1 --"),
+            vec!["--window", "--max-total", "--format", "--overwrite"]
+        );
+        assert_eq!(
+            complete_line("re start 1 --overwrite "),
+            vec!["--window", "--max-total", "--format"]
+        );
+        // 选项的值
+        assert_eq!(complete_line("re start 1 --format "), vec!["s16", "f32"]);
+        assert_eq!(complete_line("re start 1 --format f"), vec!["f32"]);
+        assert_eq!(complete_line("re start 1 --window 3"), vec!["3?"]);
+        assert!(complete_line("re mark ").is_empty());
+        assert!(complete_line("record ").is_empty());
+    }
+
+    #[test]
+    fn test_parse_value() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1.5m").unwrap(), Duration::from_secs(90));
+        assert!(parse_duration("-1s").is_err());
+        assert_eq!(parse_size("100MB").unwrap(), 100 * 1024 * 1024);
+        assert_eq!(parse_size("2g").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert!(parse_size("0").is_err());
+    }
+}
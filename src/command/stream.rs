@@ -0,0 +1,54 @@
+//! stream command operation
+
+use std::borrow::Cow;
+
+use audio::{AudioObjectId, stream};
+
+use crate::interactive::{OutputFormat, PROMPT_DEFAULT_COW, print_records};
+use crate::rserror::{Result, RsError};
+
+use super::registry::{Arg, Args, Command, Kind};
+
+pub(super) const COMMAND: Command =
+    Command::new("stream", "stream of device").subcommands(&[Command::new(
+        "list",
+        "show streams and formats of device",
+    )
+    .args(&[Arg::positional("device_id", Kind::Integer)])
+    .common(super::LIST_OPTIONS)
+    .run(list)]);
+
+// show all stream of device
+fn list(args: &Args) -> Result<Cow<'static, str>> {
+    let Some(device_id) = args.integer::<AudioObjectId>("device_id")? else {
+        return Err(RsError::with_msg("usage: stream list device_id").into());
+    };
+    let stream_vec = stream::list_by_id(&device_id)?;
+    let options = super::list_options(args);
+    if stream_vec.is_empty() && options.format == OutputFormat::Text {
+        return Ok(Cow::Borrowed("device has no stream"));
+    }
+    let content_vec = stream_vec
+        .iter()
+        .map(|stream| {
+            let (format, check) = match stream.get_format() {
+                Ok(format) => {
+                    let check = match format.validate() {
+                        Ok(_) => Cow::from("ok"),
+                        Err(error) => Cow::from(error.to_string()),
+                    };
+                    (Cow::from(format.to_string()), check)
+                }
+                Err(error) => (Cow::from("query err"), Cow::from(error.to_string())),
+            };
+            vec![
+                (Cow::from("id"), Cow::from(stream.get_id().to_string())),
+                (Cow::from("format"), format),
+                (Cow::from("check"), check),
+            ]
+        })
+        .collect::<Vec<Vec<(Cow<'_, str>, Cow<'_, str>)>>>();
+
+    print_records(content_vec, &options).map_err(RsError::with_msg)?;
+    Ok(PROMPT_DEFAULT_COW)
+}
@@ -1,17 +1,83 @@
 //! user interactive
 
 use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use rustyline::{
+    CompletionType, Config, Context, Editor, Helper, completion::Completer, error::ReadlineError,
+    highlight::Highlighter, hint::Hinter, history::FileHistory, validate::Validator,
+};
 
 // promtp start
 // default
 const PROMPT_DEFAULT: &str = "please input command(help can show all command): ";
 pub(crate) const PROMPT_DEFAULT_COW: Cow<'_, str> = Cow::Borrowed(PROMPT_DEFAULT);
-
-// no command
-const PROMPT_ERR_COMMAND: &str = "unsupported command";
-pub(crate) const PROMPT_ERR_COMMAND_COW: Cow<'_, str> = Cow::Borrowed(PROMPT_ERR_COMMAND);
 // promtp end
 
+// 最多保存的历史命令数量
+const HISTORY_SIZE: usize = 1000;
+// 表格中一列最多显示的字符数
+const MAX_COLUMN_WIDTH: usize = 48;
+// 表格中列之间的空格数
+const COLUMN_GAP: usize = 2;
+
+// 没有指定 --json、--csv 时列表的输出格式，由 output 命令修改
+static OUTPUT_FORMAT: Mutex<OutputFormat> = Mutex::new(OutputFormat::Text);
+
+/// 列表的输出格式，json 和 csv 供其它程序读取
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    /// 对齐的表格
+    #[default]
+    Text,
+    /// 每一行是一个对象，所有的值都是字符串
+    Json,
+    /// 第一行是所有的 key
+    Csv,
+}
+
+impl OutputFormat {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!(
+                "unsupported output format: {s}, supported: text, json, csv"
+            )),
+        }
+    }
+}
+
+/// 默认的输出格式
+pub(crate) fn output_format() -> OutputFormat {
+    *OUTPUT_FORMAT
+        .lock()
+        .unwrap_or_else(|error| error.into_inner())
+}
+
+pub(crate) fn set_output_format(format: OutputFormat) {
+    *OUTPUT_FORMAT
+        .lock()
+        .unwrap_or_else(|error| error.into_inner()) = format;
+}
+
 // show one line
 pub(super) fn print_line(data: &str) {
     println!("\n{}", data);
@@ -37,3 +103,418 @@ where
         println!();
     });
 }
+
+/// 列表中的一行，多个 (key, value)
+pub(crate) type Record<'a> = Vec<(Cow<'a, str>, Cow<'a, str>)>;
+
+/// 列表的输出格式、排序和过滤
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ListOptions<'a> {
+    pub(crate) format: OutputFormat,
+    /// 按这一列排序，可以是列名的前缀，空格可以写成 _
+    pub(crate) sort: Option<&'a str>,
+    pub(crate) reverse: bool,
+    /// 只保留有值包含这个字符串的行，不区分大小写
+    pub(crate) filter: Option<&'a str>,
+}
+
+/// 过滤、排序后按 options.format 输出列表
+/// json 和 csv 中 key 的空格替换为 _
+pub(crate) fn print_records(
+    mut data: Vec<Record<'_>>,
+    options: &ListOptions,
+) -> Result<(), String> {
+    select_records(&mut data, options)?;
+    match options.format {
+        OutputFormat::Text => print!("\n{}", render_table(&data)),
+        OutputFormat::Json => println!("{}", records_json(&data)),
+        OutputFormat::Csv => print!("{}", records_csv(&data)),
+    }
+    Ok(())
+}
+
+fn record_key(key: &str) -> String {
+    key.replace(' ', "_")
+}
+
+// 所有行中出现过的 key，按第一次出现的顺序
+fn record_keys<'a>(data: &'a [Record<'_>]) -> Vec<&'a str> {
+    let mut keys = Vec::<&str>::new();
+    for (key, _) in data.iter().flatten() {
+        if !keys.contains(&key.as_ref()) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+// 行中 key 的值，没有时为空
+fn record_value<'a>(line_data: &'a Record<'_>, key: &str) -> &'a str {
+    line_data
+        .iter()
+        .find(|(line_key, _)| line_key == key)
+        .map(|(_, value)| value.as_ref())
+        .unwrap_or_default()
+}
+
+// 按 options 过滤、排序，排序是稳定的
+pub(crate) fn select_records(
+    data: &mut Vec<Record<'_>>,
+    options: &ListOptions,
+) -> Result<(), String> {
+    let sort_key = match options.sort {
+        Some(name) => Some(find_column(&record_keys(data), name)?),
+        None => None,
+    };
+    if let Some(filter) = options.filter {
+        let filter = filter.to_lowercase();
+        data.retain(|line_data| {
+            line_data
+                .iter()
+                .any(|(_, value)| value.to_lowercase().contains(&filter))
+        });
+    }
+    if let Some(key) = sort_key {
+        data.sort_by(|a, b| compare_values(record_value(a, &key), record_value(b, &key)));
+    }
+    if options.reverse {
+        data.reverse();
+    }
+    Ok(())
+}
+
+// 列名完全相同或者只有一个列名以 name 开始，没有数据时不检查
+fn find_column(keys: &[&str], name: &str) -> Result<String, String> {
+    if keys.is_empty() {
+        return Ok(name.to_string());
+    }
+    let name = record_key(name);
+    if let Some(key) = keys.iter().find(|key| record_key(key) == name) {
+        return Ok(key.to_string());
+    }
+    let matched = keys
+        .iter()
+        .filter(|key| record_key(key).starts_with(&name))
+        .collect::<Vec<_>>();
+    match matched.as_slice() {
+        [key] => Ok(key.to_string()),
+        _ => Err(format!(
+            "unknown column: {}, columns: {}",
+            name,
+            keys.iter()
+                .map(|key| record_key(key))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+// 都是数字时按数值比较，否则按不区分大小写的字符串比较
+fn compare_values(a: &str, b: &str) -> Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        _ => a.to_lowercase().cmp(&b.to_lowercase()),
+    }
+}
+
+// 左对齐的表格，第一行是大写的列名，过长的值截断后以 … 结束
+fn render_table(data: &[Record<'_>]) -> String {
+    let keys = record_keys(data);
+    let rows = std::iter::once(
+        keys.iter()
+            .map(|key| key.to_uppercase())
+            .collect::<Vec<_>>(),
+    )
+    .chain(data.iter().map(|line_data| {
+        keys.iter()
+            .map(|key| truncate(record_value(line_data, key)))
+            .collect()
+    }))
+    .collect::<Vec<_>>();
+    let widths = (0..keys.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+    let mut text = String::new();
+    for row in rows.iter().filter(|row| !row.is_empty()) {
+        for (column, cell) in row.iter().enumerate() {
+            if column + 1 == row.len() {
+                // 最后一列不补空格
+                text.push_str(cell);
+            } else {
+                text.push_str(&format!(
+                    "{:width$}",
+                    cell,
+                    width = widths[column] + COLUMN_GAP
+                ));
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn truncate(value: &str) -> String {
+    if value.chars().count() <= MAX_COLUMN_WIDTH {
+        return value.to_string();
+    }
+    let mut value = value.chars().take(MAX_COLUMN_WIDTH - 1).collect::<String>();
+    value.push('…');
+    value
+}
+
+pub(crate) fn records_json(data: &[Record<'_>]) -> serde_json::Value {
+    data.iter()
+        .map(|line_data| {
+            line_data
+                .iter()
+                .map(|(key, value)| (record_key(key), serde_json::Value::from(value.as_ref())))
+                .collect::<serde_json::Map<_, _>>()
+        })
+        .collect()
+}
+
+// 列是所有行中出现过的 key，行中没有的 key 为空
+fn records_csv(data: &[Record<'_>]) -> String {
+    let keys = record_keys(data);
+    let mut text = String::new();
+    let mut push_line = |fields: Vec<String>| {
+        text.push_str(&fields.join(","));
+        text.push('\n');
+    };
+    push_line(keys.iter().map(|key| csv_field(&record_key(key))).collect());
+    for line_data in data {
+        push_line(
+            keys.iter()
+                .map(|key| csv_field(record_value(line_data, key)))
+                .collect(),
+        );
+    }
+    text
+}
+
+// 包含逗号、引号、换行的值用引号括起来，引号写两次
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// 读取用户输入的一行命令，支持编辑、历史记录和 tab 补全
+/// 历史记录保存在 ~/Library/Application Support/resound/history
+pub(super) struct LineReader {
+    editor: Editor<LineHelper, FileHistory>,
+    history: Option<PathBuf>,
+}
+
+impl LineReader {
+    /// complete 返回 line 的最后一个词可以补全的词
+    pub(super) fn new(complete: fn(&str) -> Vec<String>) -> rustyline::Result<LineReader> {
+        let config = Config::builder()
+            .max_history_size(HISTORY_SIZE)?
+            .completion_type(CompletionType::List)
+            .build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(LineHelper { complete }));
+        let history = data_dir().map(|dir| dir.join("history"));
+        if let Some(history) = &history
+            && history.exists()
+            && let Err(error) = editor.load_history(history)
+        {
+            eprintln!("load history fail: {}", error);
+        }
+        Ok(LineReader { editor, history })
+    }
+
+    /// 读取一行，ctrl + c、ctrl + d 或读取失败时返回 None
+    pub(super) fn read_line(&mut self) -> Option<String> {
+        let line = match self.editor.readline("") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => return None,
+            Err(error) => {
+                eprintln!("read command fail: {}", error);
+                return None;
+            }
+        };
+        if !line.trim().is_empty() {
+            self.add_history(line.trim());
+        }
+        Some(line)
+    }
+
+    // 每条命令都追加到文件，异常退出时不会丢失
+    fn add_history(&mut self, line: &str) {
+        if !self.editor.add_history_entry(line).unwrap_or(false) {
+            return;
+        }
+        if let Some(history) = &self.history
+            && let Err(error) = self.editor.append_history(history)
+        {
+            eprintln!("save history fail: {}", error);
+        }
+    }
+}
+
+/// 保存历史记录、control socket 等文件的目录，不存在时创建
+pub(crate) fn data_dir() -> Option<PathBuf> {
+    let dir = PathBuf::from(std::env::var_os("HOME")?)
+        .join("Library")
+        .join("Application Support")
+        .join("resound");
+    // 目录中有 control socket 和日志，只允许当前用户访问
+    if let Err(error) = std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+    {
+        eprintln!("create {} fail: {}", dir.display(), error);
+        return None;
+    }
+    Some(dir)
+}
+
+// 补全 line 中光标前的最后一个词
+struct LineHelper {
+    complete: fn(&str) -> Vec<String>,
+}
+
+impl Completer for LineHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.len() - line.rsplit(char::is_whitespace).next().map_or(0, str::len);
+        Ok((start, (self.complete)(line)))
+    }
+}
+
+impl Hinter for LineHelper {
+    type Hint = String;
+}
+
+impl Highlighter for LineHelper {}
+
+impl Validator for LineHelper {}
+
+impl Helper for LineHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<Record<'static>> {
+        vec![
+            vec![
+                (Cow::from("id"), Cow::from("1")),
+                (Cow::from("bundle id"), Cow::from("com.apple.Music")),
+            ],
+            vec![
+                (Cow::from("id"), Cow::from("2")),
+                (Cow::from("check"), Cow::from("bad \"rate\", 0")),
+            ],
+        ]
+    }
+
+    fn process<'a>(id: &'a str, bundle_id: &'a str) -> Record<'a> {
+        vec![
+            (Cow::from("id"), Cow::from(id)),
+            (Cow::from("bundle id"), Cow::from(bundle_id)),
+        ]
+    }
+
+    #[test]
+    fn test_render_table() {
+        let long = "x".repeat(60);
+        let data = vec![process("9", "com.apple.Music"), process("120", &long)];
+        assert_eq!(
+            render_table(&data),
+            format!(
+                "ID   BUNDLE ID\n9    com.apple.Music\n120  {}…\n",
+                "x".repeat(MAX_COLUMN_WIDTH - 1)
+            )
+        );
+        // 行中没有的列为空
+        assert_eq!(
+            render_table(&records()),
+            "ID  BUNDLE ID        CHECK\n1   com.apple.Music  \n2                    bad \"rate\", 0\n"
+        );
+        assert_eq!(render_table(&[]), "");
+    }
+
+    #[test]
+    fn test_select_records() {
+        let data = vec![
+            process("120", "us.zoom.xos"),
+            process("9", "com.apple.Music"),
+            process("35", "us.zoom.ZoomPhone"),
+        ];
+        let select = |options: ListOptions| {
+            let mut data = data.clone();
+            select_records(&mut data, &options).map(|_| {
+                data.iter()
+                    .map(|line_data| record_value(line_data, "id").to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+        // 数字按数值排序，字符串不区分大小写
+        let options = ListOptions {
+            sort: Some("id"),
+            ..Default::default()
+        };
+        assert_eq!(
+            select(options),
+            Ok(vec!["9".into(), "35".into(), "120".into()])
+        );
+        let options = ListOptions {
+            sort: Some("bundle"),
+            reverse: true,
+            filter: Some("ZOOM"),
+            ..Default::default()
+        };
+        assert_eq!(select(options), Ok(vec!["35".into(), "120".into()]));
+        let options = ListOptions {
+            sort: Some("bundle_id"),
+            filter: Some("nothing"),
+            ..Default::default()
+        };
+        assert_eq!(select(options), Ok(vec![]));
+        let options = ListOptions {
+            sort: Some("name"),
+            ..Default::default()
+        };
+        assert_eq!(
+            select(options),
+            Err("unknown column: name, columns: id, bundle_id".to_string())
+        );
+    }
+
+    #[test]
+    fn test_records_json() {
+        assert_eq!(
+            records_json(&records()).to_string(),
+            r#"[{"bundle_id":"com.apple.Music","id":"1"},{"check":"bad \"rate\", 0","id":"2"}]"#
+        );
+        assert_eq!(records_json(&[]).to_string(), "[]");
+    }
+
+    #[test]
+    fn test_records_csv() {
+        assert_eq!(
+            records_csv(&records()),
+            "id,bundle_id,check\n1,com.apple.Music,\n2,,\"bad \"\"rate\"\", 0\"\n"
+        );
+        assert_eq!("json".parse::<OutputFormat>(), Ok(OutputFormat::Json));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
@@ -1,6 +1,8 @@
 //! 录系统播放的声音
 //!
 //! 交互式命令，通常情况下，格式为： 对象 + 动作 + 参数
+//! 有命令行参数时执行一条命令后退出，例如 resound record --bundle com.apple.Music --duration 10m
+//! 交互式运行或者 resound daemon 在后台运行时，可以用 resound ctl 控制，例如 resound ctl mark intro
 
 use std::thread;
 
@@ -15,6 +17,11 @@ mod rserror;
 
 #[tokio::main]
 async fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    if !args.is_empty() {
+        std::process::exit(command::run_args(&args).await);
+    }
+
     // 监听默认 kill pid
     let kill_stream = signal(SignalKind::terminate());
     if let Err(_) = kill_stream {
@@ -40,6 +47,9 @@ async fn main() {
         command::wait_command(tx);
     });
 
+    // 其它程序通过 control socket 控制录音，退出时删除 socket 文件
+    tokio::spawn(command::control::serve());
+
     let (callback_tx, callback_rx) = oneshot::channel::<()>();
     tokio::select! {
         // start main task